@@ -6,7 +6,7 @@ extern crate snow;
 use snow::*;
 use snow::params::*;
 use snow::types::*;
-use snow::wrappers::crypto_wrapper::Dh25519;
+use snow::wrappers::crypto_wrapper::{Dh25519, CipherChaChaPoly, HashBLAKE2b, HashSHA256};
 use snow::wrappers::rand_wrapper::RandomOs;
 use test::Bencher;
 
@@ -51,6 +51,56 @@ fn bench_handshake_xx(b: &mut Bencher) {
     });
 }
 
+/// A full `Noise_XX_25519_AESGCM_SHA256` handshake using primitives from `resolver()`.
+fn handshake_xx_with(b: &mut Bencher, resolver: fn() -> Box<CryptoResolver>) {
+    let pattern: NoiseParams = "Noise_XX_25519_AESGCM_SHA256".parse().unwrap();
+    let static_i = NoiseBuilder::new(pattern.clone()).generate_private_key().unwrap();
+    let static_r = NoiseBuilder::new(pattern.clone()).generate_private_key().unwrap();
+
+    b.bytes = MSG_SIZE as u64;
+    b.iter(move || {
+        let mut h_i = NoiseBuilder::with_resolver(pattern.clone(), resolver())
+            .local_private_key(&static_i)
+            .build_initiator().unwrap();
+        let mut h_r = NoiseBuilder::with_resolver(pattern.clone(), resolver())
+            .local_private_key(&static_r)
+            .build_responder().unwrap();
+
+        let mut buffer_msg = [0u8; MSG_SIZE * 2];
+        let mut buffer_out = [0u8; MSG_SIZE * 2];
+
+        let len = h_i.write_message(&[0u8; 0], &mut buffer_msg).unwrap();
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        let len = h_r.write_message(&[0u8; 0], &mut buffer_msg).unwrap();
+        h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        let len = h_i.write_message(&[0u8;0], &mut buffer_msg).unwrap();
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    });
+}
+
+#[bench]
+fn bench_handshake_xx_aesgcm_sha256(b: &mut Bencher) {
+    handshake_xx_with(b, || Box::new(DefaultResolver));
+}
+
+/// ring's ciphers and hashes with the default DH and RNG, which is all `RingAcceleratedResolver`
+/// used to provide.
+#[cfg(feature = "ring-resolver")]
+#[bench]
+fn bench_handshake_xx_aesgcm_sha256_ring_mixed(b: &mut Bencher) {
+    handshake_xx_with(b, || Box::new(ResolverBuilder::new()
+        .rng(DefaultResolver)
+        .dh(DefaultResolver)
+        .cipher(RingAcceleratedResolver::new())
+        .hash(RingAcceleratedResolver::new())));
+}
+
+#[cfg(feature = "ring-resolver")]
+#[bench]
+fn bench_handshake_xx_aesgcm_sha256_ring(b: &mut Bencher) {
+    handshake_xx_with(b, || Box::new(RingAcceleratedResolver::new()));
+}
+
 #[bench]
 fn bench_handshake_nn(b: &mut Bencher) {
     b.bytes = MSG_SIZE as u64;
@@ -72,6 +122,26 @@ fn bench_handshake_nn(b: &mut Bencher) {
     });
 }
 
+#[bench]
+fn bench_handshake_nn_static(b: &mut Bencher) {
+    b.bytes = MSG_SIZE as u64;
+    b.iter(move || {
+        let pattern = "Noise_NN_25519_ChaChaPoly_BLAKE2b";
+        let mut h_i = NoiseBuilder::new(pattern.parse().unwrap())
+            .build_static_initiator::<Dh25519, CipherChaChaPoly, HashBLAKE2b, _>(RandomOs::default()).unwrap();
+        let mut h_r = NoiseBuilder::new(pattern.parse().unwrap())
+            .build_static_responder::<Dh25519, CipherChaChaPoly, HashBLAKE2b, _>(RandomOs::default()).unwrap();
+
+        let mut buffer_msg = [0u8; MSG_SIZE * 2];
+        let mut buffer_out = [0u8; MSG_SIZE * 2];
+
+        let len = h_i.write_handshake_message(&[0u8; 0], &mut buffer_msg).unwrap();
+        h_r.read_handshake_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        let len = h_r.write_handshake_message(&[0u8; 0], &mut buffer_msg).unwrap();
+        h_i.read_handshake_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    });
+}
+
 #[bench]
 fn bench_write_throughput_aesgcm_sha256(b: &mut Bencher) {
     b.bytes = MSG_SIZE as u64;
@@ -310,3 +380,11 @@ fn bench_builder_skeleton(b: &mut Bencher) {
             .build_initiator().unwrap();
     });
 }
+
+#[bench]
+fn bench_builder_skeleton_static(b: &mut Bencher) {
+    b.iter(move || {
+        NoiseBuilder::new("Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap())
+            .build_static_initiator::<Dh25519, CipherChaChaPoly, HashSHA256, _>(RandomOs::default()).unwrap();
+    });
+}
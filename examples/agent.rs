@@ -0,0 +1,138 @@
+//! A `Noise_XX` handshake where the initiator's static private key never leaves a key agent.
+//!
+//! The "agent" here is a thread holding the key behind a Unix socket, standing in for an HSM or
+//! a real agent process. It answers two requests: `P` returns the public key, and `D` followed
+//! by a peer public key returns the DH result (prefixed by a status byte, nonzero if the agent
+//! couldn't compute it). The initiator only sees the agent through `AgentKey`, a
+//! `StaticKeyProvider`.
+//!
+//! # Usage
+//! `cargo run --example agent`
+
+extern crate snow;
+
+#[cfg(unix)]
+mod agent {
+    use snow::NoiseBuilder;
+    use snow::params::NoiseParams;
+    use snow::types::{Dh, StaticKeyProvider};
+    use snow::wrappers::crypto_wrapper::Dh25519;
+    use std::env;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::thread;
+
+    const KEY_LEN: usize = 32;
+
+    /// Serve DH requests with `private_key` on `path` until the process exits.
+    fn spawn_agent(path: &Path, private_key: Vec<u8>) {
+        let listener = UnixListener::bind(path).unwrap();
+        thread::spawn(move || {
+            let mut dh = Dh25519::default();
+            dh.set(&private_key);
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0u8; 1];
+                stream.read_exact(&mut request).unwrap();
+                match request[0] {
+                    b'P' => stream.write_all(dh.pubkey()).unwrap(),
+                    b'D' => {
+                        let mut peer = [0u8; KEY_LEN];
+                        stream.read_exact(&mut peer).unwrap();
+                        let mut out = [0u8; KEY_LEN];
+                        // Low-order points are left to the handshake, which rejects all-zero results.
+                        let _ = dh.dh(&peer, &mut out);
+                        stream.write_all(&[0]).unwrap();
+                        stream.write_all(&out).unwrap();
+                    },
+                    _ => {},
+                }
+            }
+        });
+    }
+
+    /// A static key held by the agent listening on `path`.
+    struct AgentKey {
+        path: PathBuf,
+        pubkey: [u8; KEY_LEN],
+    }
+
+    impl AgentKey {
+        fn connect(path: &Path) -> AgentKey {
+            let mut stream = UnixStream::connect(path).unwrap();
+            stream.write_all(b"P").unwrap();
+            let mut pubkey = [0u8; KEY_LEN];
+            stream.read_exact(&mut pubkey).unwrap();
+            AgentKey { path: path.to_owned(), pubkey: pubkey }
+        }
+    }
+
+    impl StaticKeyProvider for AgentKey {
+        fn name(&self) -> &'static str {
+            "25519"
+        }
+
+        fn pub_len(&self) -> usize {
+            KEY_LEN
+        }
+
+        fn pubkey(&self) -> &[u8] {
+            &self.pubkey
+        }
+
+        fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+            let mut stream = UnixStream::connect(&self.path).map_err(|_| ())?;
+            stream.write_all(b"D").map_err(|_| ())?;
+            stream.write_all(&pubkey[..KEY_LEN]).map_err(|_| ())?;
+            let mut status = [0u8; 1];
+            stream.read_exact(&mut status).map_err(|_| ())?;
+            stream.read_exact(&mut out[..KEY_LEN]).map_err(|_| ())?;
+            if status[0] == 0 { Ok(()) } else { Err(()) }
+        }
+    }
+
+    pub fn run() {
+        let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let path = env::temp_dir().join(format!("snow-agent-{}.sock", process::id()));
+
+        // The agent gets the initiator's key; from here on only the agent touches it.
+        let initiator_key = NoiseBuilder::new(params.clone()).generate_private_key().unwrap();
+        spawn_agent(&path, initiator_key);
+        let agent_key = AgentKey::connect(&path);
+        let agent_pubkey = agent_key.pubkey;
+
+        let responder_key = NoiseBuilder::new(params.clone()).generate_private_key().unwrap();
+        let mut initiator = NoiseBuilder::new(params.clone())
+            .static_key_provider(Box::new(agent_key))
+            .build_initiator().unwrap();
+        let mut responder = NoiseBuilder::new(params)
+            .local_private_key(&responder_key)
+            .build_responder().unwrap();
+
+        let (mut msg, mut payload) = (vec![0u8; 65535], vec![0u8; 65535]);
+        let len = initiator.write_message(&[], &mut msg).unwrap();
+        responder.read_message(&msg[..len], &mut payload).unwrap();
+        let len = responder.write_message(&[], &mut msg).unwrap();
+        initiator.read_message(&msg[..len], &mut payload).unwrap();
+        let len = initiator.write_message(b"hello from behind the agent", &mut msg).unwrap();
+        let len = responder.read_message(&msg[..len], &mut payload).unwrap();
+        assert_eq!(responder.get_remote_static(), Some(&agent_pubkey[..]));
+
+        println!("responder got {:?} from a peer whose key is in the agent",
+                 String::from_utf8_lossy(&payload[..len]));
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+#[cfg(unix)]
+fn main() {
+    agent::run();
+}
+
+#[cfg(not(unix))]
+fn main() {
+    println!("this example needs Unix sockets.");
+}
@@ -0,0 +1,319 @@
+//! The `snow` command-line tool, for generating keys, sealing and opening messages, running
+//! handshakes against other implementations, and working with test vectors.
+//!
+//! Build it with `cargo build --features cli`. Private keys are always read from files (as
+//! written by `snow keygen`), never from the command line. Run `snow help` for usage.
+
+extern crate base64;
+extern crate clap;
+extern crate hex;
+extern crate snow;
+
+use clap::{App, AppSettings, ArgMatches, SubCommand};
+use hex::{FromHex, ToHex};
+use snow::{CryptoResolver, DefaultResolver, NoiseBuilder, sealed};
+#[cfg(feature = "rustcrypto-resolver")]
+use snow::{FallbackResolver, RustCryptoResolver};
+use snow::params::*;
+use snow::trace::TraceEvent;
+use snow::types::Dh;
+use snow::vectors::{TestVector, TestVectors};
+use snow::wrappers::rand_wrapper::RandomOs;
+use std::fs::File;
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::process;
+
+type CliResult<T> = Result<T, String>;
+
+fn main() {
+    let matches = App::new("snow")
+        .about("Noise Protocol keys, handshakes and test vectors")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .args_from_usage("-f --format=[FORMAT] 'Key encoding: hex (default) or base64'")
+        .subcommand(SubCommand::with_name("keygen")
+            .about("Generate a private key")
+            .args_from_usage("-d --dh=[DH] 'DH function, e.g. 25519 (default)'"))
+        .subcommand(SubCommand::with_name("pubkey")
+            .about("Print the public key for a private key")
+            .args_from_usage("-d --dh=[DH] 'DH function, e.g. 25519 (default)'
+                              <KEYFILE> 'File containing the private key'"))
+        .subcommand(SubCommand::with_name("seal")
+            .about("Encrypt stdin to a recipient with a one-way pattern")
+            .args_from_usage("-p --protocol=<NAME> 'Protocol name, e.g. Noise_N_25519_ChaChaPoly_BLAKE2s'
+                              -t --to=<PUBKEY> 'The recipient's public key'
+                              -k --key=[KEYFILE] 'File containing your private key, for X and K'"))
+        .subcommand(SubCommand::with_name("open")
+            .about("Decrypt stdin with your private key")
+            .args_from_usage("-p --protocol=<NAME> 'Protocol name, e.g. Noise_N_25519_ChaChaPoly_BLAKE2s'
+                              -k --key=<KEYFILE> 'File containing your private key'
+                              --from=[PUBKEY] 'The sender's public key, required for K'"))
+        .subcommand(SubCommand::with_name("handshake")
+            .about("Run one side of a handshake over stdin/stdout or a Unix socket")
+            .args_from_usage("-p --protocol=<NAME> 'Protocol name, e.g. Noise_XX_25519_ChaChaPoly_BLAKE2s'
+                              -r --responder 'Act as the responder (default is initiator)'
+                              -k --key=[KEYFILE] 'File containing your static private key'
+                              --remote=[PUBKEY] 'The remote party's static public key'
+                              --prologue=[PROLOGUE] 'Prologue, as a UTF-8 string'
+                              --psk=[PSK]... 'PSKs, in the order of the pskN modifiers'
+                              -s --socket=[PATH] 'Unix socket: the responder listens, the initiator connects'
+                              --trace 'Print every handshake step to stderr, for diffing against other implementations'"))
+        .subcommand(SubCommand::with_name("vectors")
+            .about("Generate or verify test vectors")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("generate")
+                .about("Generate deterministic vectors for the given protocols, as JSON on stdout")
+                .args_from_usage("--seed=[SEED] 'Seed for the first vector, incremented for each one after (default 0)'
+                                  <PROTOCOL>... 'Protocol names'"))
+            .subcommand(SubCommand::with_name("verify")
+                .about("Verify a JSON file of vectors")
+                .args_from_usage("<FILE> 'The vectors file'")))
+        .get_matches();
+
+    let format = matches.value_of("format").unwrap_or("hex");
+    let result = match matches.subcommand() {
+        ("keygen", Some(m))    => keygen(m, format),
+        ("pubkey", Some(m))    => pubkey(m, format),
+        ("seal", Some(m))      => seal(m, format),
+        ("open", Some(m))      => open(m, format),
+        ("handshake", Some(m)) => handshake(m, format),
+        ("vectors", Some(m))   => match m.subcommand() {
+            ("generate", Some(m)) => generate_vectors(m),
+            ("verify", Some(m))   => verify_vectors(m),
+            _                     => unreachable!(),
+        },
+        _ => unreachable!(),
+    };
+
+    if let Err(e) = result {
+        let _ = writeln!(io::stderr(), "snow: {}", e);
+        process::exit(1);
+    }
+}
+
+fn encode(bytes: &[u8], format: &str) -> CliResult<String> {
+    match format {
+        "hex"    => Ok(bytes.to_hex()),
+        "base64" => Ok(base64::encode(bytes)),
+        _        => Err(format!("unknown key format: {}", format)),
+    }
+}
+
+fn decode(s: &str, format: &str) -> CliResult<Vec<u8>> {
+    match format {
+        "hex"    => Vec::<u8>::from_hex(s.trim()).map_err(|_| "invalid hex key".to_owned()),
+        "base64" => base64::decode(s.trim()).map_err(|_| "invalid base64 key".to_owned()),
+        _        => Err(format!("unknown key format: {}", format)),
+    }
+}
+
+fn read_key_file(path: &str, format: &str) -> CliResult<Vec<u8>> {
+    let mut contents = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut contents))
+        .map_err(|e| format!("couldn't read {}: {}", path, e))?;
+    decode(&contents, format)
+}
+
+fn parse_params(name: &str) -> CliResult<NoiseParams> {
+    name.parse().map_err(|e: snow::Error| format!("{}: {}", name, e))
+}
+
+/// The resolver for keys and handshakes, which picks up the RustCrypto curves when they're built in.
+#[cfg(not(feature = "rustcrypto-resolver"))]
+fn resolver() -> Box<CryptoResolver> {
+    Box::new(DefaultResolver)
+}
+
+#[cfg(feature = "rustcrypto-resolver")]
+fn resolver() -> Box<CryptoResolver> {
+    Box::new(FallbackResolver::new(RustCryptoResolver::new(), DefaultResolver))
+}
+
+fn resolve_dh(m: &ArgMatches) -> CliResult<Box<Dh>> {
+    let choice: DHChoice = m.value_of("dh").unwrap_or("25519").parse().map_err(|e: snow::Error| e.to_string())?;
+    resolver().resolve_dh(&choice).ok_or_else(|| "DH function not supported by this build".to_owned())
+}
+
+fn read_stdin() -> CliResult<Vec<u8>> {
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input).map_err(|e| e.to_string())?;
+    Ok(input)
+}
+
+fn keygen(m: &ArgMatches, format: &str) -> CliResult<()> {
+    let mut dh = resolve_dh(m)?;
+    dh.generate(&mut RandomOs::default());
+    println!("{}", encode(dh.privkey(), format)?);
+    Ok(())
+}
+
+fn pubkey(m: &ArgMatches, format: &str) -> CliResult<()> {
+    let mut dh = resolve_dh(m)?;
+    let private = read_key_file(m.value_of("KEYFILE").unwrap(), format)?;
+    if private.len() != dh.priv_len() {
+        return Err("private key has the wrong length".to_owned());
+    }
+    dh.set(&private);
+    println!("{}", encode(dh.pubkey(), format)?);
+    Ok(())
+}
+
+fn seal(m: &ArgMatches, format: &str) -> CliResult<()> {
+    let params = parse_params(m.value_of("protocol").unwrap())?;
+    let recipient = decode(m.value_of("to").unwrap(), format)?;
+    let sender = match m.value_of("key") {
+        Some(path) => Some(read_key_file(path, format)?),
+        None => None,
+    };
+    let sealed = sealed::seal(&params, &recipient, sender.as_ref().map(|k| &k[..]), &read_stdin()?)
+        .map_err(|e| e.to_string())?;
+    io::stdout().write_all(&sealed).map_err(|e| e.to_string())
+}
+
+fn open(m: &ArgMatches, format: &str) -> CliResult<()> {
+    let params = parse_params(m.value_of("protocol").unwrap())?;
+    let private = read_key_file(m.value_of("key").unwrap(), format)?;
+    let input = read_stdin()?;
+    let (payload, sender) = match m.value_of("from") {
+        Some(from) => sealed::open_from(&params, &private, &decode(from, format)?, &input),
+        None       => sealed::open(&params, &private, &input),
+    }.map_err(|e| e.to_string())?;
+    if let Some(sender) = sender {
+        let _ = writeln!(io::stderr(), "sender: {}", encode(&sender, format)?);
+    }
+    io::stdout().write_all(&payload).map_err(|e| e.to_string())
+}
+
+/// A bidirectional channel carrying handshake messages, each prefixed by its 16-bit BE length.
+struct Channel {
+    reader: Box<Read>,
+    writer: Box<Write>,
+}
+
+impl Channel {
+    fn recv(&mut self) -> io::Result<Vec<u8>> {
+        let mut len = [0u8; 2];
+        self.reader.read_exact(&mut len)?;
+        let mut msg = vec![0u8; ((len[0] as usize) << 8) + (len[1] as usize)];
+        self.reader.read_exact(&mut msg)?;
+        Ok(msg)
+    }
+
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.writer.write_all(&[(msg.len() >> 8) as u8, (msg.len() & 0xff) as u8])?;
+        self.writer.write_all(msg)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(unix)]
+fn open_channel(socket: Option<&str>, responder: bool) -> io::Result<Channel> {
+    let stream = match socket {
+        None => return Ok(Channel { reader: Box::new(io::stdin()), writer: Box::new(io::stdout()) }),
+        Some(path) if responder => UnixListener::bind(path)?.accept()?.0,
+        Some(path) => UnixStream::connect(path)?,
+    };
+    Ok(Channel { reader: Box::new(stream.try_clone()?), writer: Box::new(stream) })
+}
+
+#[cfg(not(unix))]
+fn open_channel(socket: Option<&str>, _responder: bool) -> io::Result<Channel> {
+    match socket {
+        None => Ok(Channel { reader: Box::new(io::stdin()), writer: Box::new(io::stdout()) }),
+        Some(_) => Err(io::Error::new(io::ErrorKind::Other, "Unix sockets aren't supported on this platform")),
+    }
+}
+
+fn handshake(m: &ArgMatches, format: &str) -> CliResult<()> {
+    let params = parse_params(m.value_of("protocol").unwrap())?;
+    let responder = m.is_present("responder");
+    let static_key = match m.value_of("key") {
+        Some(path) => Some(read_key_file(path, format)?),
+        None => None,
+    };
+    let remote = match m.value_of("remote") {
+        Some(key) => Some(decode(key, format)?),
+        None => None,
+    };
+    let psks = match m.values_of("psk") {
+        Some(values) => values.map(|psk| decode(psk, format)).collect::<CliResult<Vec<_>>>()?,
+        None => vec![],
+    };
+
+    let mut builder = NoiseBuilder::with_resolver(params.clone(), resolver());
+    let mut psk_iter = psks.iter();
+    for modifier in &params.handshake.modifiers.list {
+        if let HandshakeModifier::Psk(n) = *modifier {
+            let psk = psk_iter.next().ok_or_else(|| "missing a PSK for a pskN modifier".to_owned())?;
+            builder = builder.psk(n, psk);
+        }
+    }
+    if let Some(ref key) = static_key {
+        builder = builder.local_private_key(key);
+    }
+    if let Some(ref key) = remote {
+        builder = builder.remote_public_key(key);
+    }
+    if let Some(prologue) = m.value_of("prologue") {
+        builder = builder.prologue(prologue.as_bytes());
+    }
+    if m.is_present("trace") {
+        builder = builder.tracer(Box::new(|event: &TraceEvent| { let _ = writeln!(io::stderr(), "{}", event); }));
+    }
+    let mut session = if responder { builder.build_responder() } else { builder.build_initiator() }
+        .map_err(|e| e.to_string())?;
+
+    let mut channel = open_channel(m.value_of("socket"), responder).map_err(|e| e.to_string())?;
+    let mut buf = vec![0u8; 65535];
+    let mut payload = vec![0u8; 65535];
+    let mut writing = !responder;
+    while !session.is_handshake_finished() {
+        if writing {
+            let len = session.write_message(&[], &mut buf).map_err(|e| format!("write_message failed: {}", e))?;
+            channel.send(&buf[..len]).map_err(|e| e.to_string())?;
+        } else {
+            let msg = channel.recv().map_err(|e| e.to_string())?;
+            session.read_message(&msg, &mut payload).map_err(|e| format!("read_message failed: {}", e))?;
+        }
+        writing = !writing;
+    }
+
+    let _ = writeln!(io::stderr(), "handshake complete: {}", params.name);
+    if let Some(remote) = session.get_remote_static() {
+        let _ = writeln!(io::stderr(), "remote static key: {}", encode(remote, format)?);
+    }
+    Ok(())
+}
+
+fn generate_vectors(m: &ArgMatches) -> CliResult<()> {
+    let seed = match m.value_of("seed") {
+        Some(seed) => seed.parse().map_err(|_| format!("invalid seed: {}", seed))?,
+        None => 0,
+    };
+    let mut vectors = vec![];
+    for (i, name) in m.values_of("PROTOCOL").unwrap().enumerate() {
+        let vector = TestVector::generate(&parse_params(name)?, seed + i as u64)
+            .map_err(|e| format!("{}: {}", name, e))?;
+        vectors.push(vector);
+    }
+    println!("{}", TestVectors { vectors: vectors }.to_json());
+    Ok(())
+}
+
+fn verify_vectors(m: &ArgMatches) -> CliResult<()> {
+    let path = m.value_of("FILE").unwrap();
+    let mut json = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut json))
+        .map_err(|e| format!("couldn't read {}: {}", path, e))?;
+    let report = TestVectors::from_json(&json).map_err(|e| e.to_string())?.verify();
+
+    for &(ref name, ref reason) in &report.failures {
+        println!("FAIL {}: {}", name, reason);
+    }
+    println!("{}/{} passed, {} ignored", report.passed, report.passed + report.failures.len(), report.ignored);
+    if !report.is_success() {
+        return Err(format!("{} vectors failed", report.failures.len()));
+    }
+    Ok(())
+}
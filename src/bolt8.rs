@@ -0,0 +1,292 @@
+//! Lightning's message framing, from [BOLT 8](https://github.com/lightning/bolts/blob/master/08-transport.md).
+//!
+//! With [`NoiseBuilder::bolt8`](../struct.NoiseBuilder.html#method.bolt8), every handshake message
+//! is prefixed with a version byte, and every transport message is sent as its encrypted 2-byte
+//! big-endian length followed by the encrypted payload, each with its own nonce. Each direction's
+//! key is rotated once it has been used 1000 times, with `ck, k = HKDF(ck, k)`, starting from the
+//! chaining key the handshake ended with.
+//!
+//! Lightning itself uses `Noise_XK_secp256k1_ChaChaPoly_SHA256` with the prologue `lightning`;
+//! the secp256k1 DH comes from `RustCryptoResolver`.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "rustcrypto-resolver")] {
+//! # use snow::{NoiseBuilder, RustCryptoResolver, bolt8};
+//! # let node_key = [0x21u8; 32];
+//! let noise = NoiseBuilder::with_resolver(bolt8::PROTOCOL_NAME.parse().unwrap(),
+//!                                         Box::new(RustCryptoResolver::new()))
+//!     .prologue(bolt8::PROLOGUE)
+//!     .local_private_key(&node_key)
+//!     .bolt8()
+//!     .build_responder()
+//!     .unwrap();
+//! # }
+//! ```
+
+use byteorder::{ByteOrder, BigEndian};
+use cipherstate::CipherState;
+use constants::{CIPHERKEYLEN, MAXHASHLEN, MAXMSGLEN, TAGLEN};
+use error::{ErrorKind, Result, InputProblem, MessagePart};
+use types::{Cipher, Hash};
+use utils::{copy_memory, Zeroize};
+use std::prelude::v1::*;
+
+/// The protocol Lightning nodes speak.
+pub const PROTOCOL_NAME: &'static str = "Noise_XK_secp256k1_ChaChaPoly_SHA256";
+
+/// The prologue Lightning nodes use.
+pub const PROLOGUE: &'static [u8] = b"lightning";
+
+/// The handshake version byte that prefixes every handshake message.
+pub const VERSION: u8 = 0;
+
+/// The length of a transport message's encrypted length prefix.
+pub const LENGTH_HEADER_LEN: usize = 2 + TAGLEN;
+
+/// How many times a key is used before it's rotated.
+const ROTATION_INTERVAL: u64 = 1000;
+
+/// One direction's current key, and the chaining key it's rotated with.
+struct Direction {
+    ck: [u8; MAXHASHLEN],
+    k: [u8; CIPHERKEYLEN],
+}
+
+impl Direction {
+    /// Rotate the key once `cipher` has used it `ROTATION_INTERVAL` times.
+    fn rotate<C: Cipher>(&mut self, hasher: &mut Hash, cipher: &mut CipherState<C>) {
+        if cipher.nonce() < ROTATION_INTERVAL {
+            return;
+        }
+        let hash_len = hasher.hash_len();
+        let mut hkdf_output = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+        hasher.hkdf(&self.ck[..hash_len], &self.k, 2, &mut hkdf_output.0, &mut hkdf_output.1, &mut []);
+        copy_memory(&hkdf_output.0, &mut self.ck);
+        copy_memory(&hkdf_output.1[..CIPHERKEYLEN], &mut self.k);
+        cipher.set(&self.k, 0);
+        hkdf_output.0.zeroize();
+        hkdf_output.1.zeroize();
+    }
+}
+
+impl Drop for Direction {
+    fn drop(&mut self) {
+        self.ck.zeroize();
+        self.k.zeroize();
+    }
+}
+
+/// The transport framing and key rotation, taken over from the handshake by `TransportState`.
+pub(crate) struct Framing {
+    hasher: Box<Hash>,
+    send: Direction,
+    recv: Direction,
+    pending_len: Option<usize>,
+}
+
+impl Framing {
+    /// Start from the handshake's final chaining key `ck`, rederiving the keys `Split()` gave the
+    /// transport ciphers.
+    pub fn new(mut hasher: Box<Hash>, ck: &[u8], initiator: bool) -> Self {
+        let mut keys = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+        hasher.hkdf(ck, &[0u8; 0], 2, &mut keys.0, &mut keys.1, &mut []);
+        let mut initiator_dir = Direction { ck: [0u8; MAXHASHLEN], k: [0u8; CIPHERKEYLEN] };
+        let mut responder_dir = Direction { ck: [0u8; MAXHASHLEN], k: [0u8; CIPHERKEYLEN] };
+        copy_memory(ck, &mut initiator_dir.ck);
+        copy_memory(ck, &mut responder_dir.ck);
+        copy_memory(&keys.0[..CIPHERKEYLEN], &mut initiator_dir.k);
+        copy_memory(&keys.1[..CIPHERKEYLEN], &mut responder_dir.k);
+        keys.0.zeroize();
+        keys.1.zeroize();
+        let (send, recv) = if initiator {
+            (initiator_dir, responder_dir)
+        } else {
+            (responder_dir, initiator_dir)
+        };
+        Framing {
+            hasher: hasher,
+            send: send,
+            recv: recv,
+            pending_len: None,
+        }
+    }
+
+    /// Encrypt the length of `payload`, then `payload`, into `message`.
+    pub fn write<C: Cipher>(&mut self, cipher: &mut CipherState<C>, payload: &[u8], message: &mut [u8]) -> Result<usize> {
+        if payload.len() > MAXMSGLEN {
+            bail!(ErrorKind::Input(InputProblem::MessageTooLong));
+        }
+        let len = LENGTH_HEADER_LEN + payload.len() + TAGLEN;
+        if len > message.len() {
+            bail!(ErrorKind::Input(InputProblem::BufferTooSmall));
+        }
+        let mut header = [0u8; 2];
+        BigEndian::write_u16(&mut header, payload.len() as u16);
+        cipher.encrypt(&header, &mut message[..LENGTH_HEADER_LEN]);
+        self.send.rotate(&mut *self.hasher, cipher);
+        cipher.encrypt(payload, &mut message[LENGTH_HEADER_LEN..len]);
+        self.send.rotate(&mut *self.hasher, cipher);
+        Ok(len)
+    }
+
+    /// Decrypt a length prefix, returning how many bytes the rest of its message has.
+    pub fn read_length<C: Cipher>(&mut self, cipher: &mut CipherState<C>, header: &[u8]) -> Result<usize> {
+        let part = MessagePart::Transport { nonce: cipher.nonce() };
+        if header.len() < LENGTH_HEADER_LEN {
+            bail!(ErrorKind::Input(InputProblem::Truncated(part)));
+        } else if header.len() > LENGTH_HEADER_LEN {
+            bail!(ErrorKind::Input(InputProblem::Malformed("BOLT 8 length prefix")));
+        }
+        let mut plain = [0u8; 2];
+        cipher.decrypt(header, &mut plain).map_err(|_| ErrorKind::Decrypt(part))?;
+        self.recv.rotate(&mut *self.hasher, cipher);
+        let len = BigEndian::read_u16(&plain) as usize;
+        self.pending_len = Some(len);
+        Ok(len + TAGLEN)
+    }
+
+    /// Decrypt a whole message, or the rest of one whose prefix went to `read_length`.
+    pub fn read<C: Cipher>(&mut self, cipher: &mut CipherState<C>, message: &[u8], payload: &mut [u8]) -> Result<usize> {
+        let (body, expected) = match self.pending_len.take() {
+            Some(len) => (message, len + TAGLEN),
+            None => {
+                let rest = self.read_length(cipher, &message[..LENGTH_HEADER_LEN.min(message.len())])?;
+                self.pending_len = None;
+                (&message[LENGTH_HEADER_LEN..], rest)
+            },
+        };
+        let part = MessagePart::Transport { nonce: cipher.nonce() };
+        if body.len() != expected {
+            bail!(ErrorKind::Input(InputProblem::Malformed("BOLT 8 message length")));
+        } else if body.len() - TAGLEN > payload.len() {
+            bail!(ErrorKind::Input(InputProblem::BufferTooSmall));
+        }
+        let len = cipher.decrypt(body, payload).map_err(|_| ErrorKind::Decrypt(part))?;
+        self.recv.rotate(&mut *self.hasher, cipher);
+        Ok(len)
+    }
+}
+
+#[cfg(all(test, feature = "rustcrypto-resolver"))]
+mod tests {
+    extern crate hex;
+
+    use self::hex::{FromHex, ToHex};
+    use error::{Error, ErrorKind, InputProblem};
+    use noise::NoiseBuilder;
+    use session::Session;
+    use wrappers::rustcrypto_wrapper::RustCryptoResolver;
+    use super::*;
+
+    const RESPONDER_PUBKEY: &'static str = "028d7500dd4c12685d1f568b4c2b5048e8534b873319f3a8daa612b469132ec7f7";
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        Vec::<u8>::from_hex(hex).unwrap()
+    }
+
+    /// The initiator and responder of the BOLT 8 test vectors, with their fixed keys.
+    fn vector_sessions() -> (Session, Session) {
+        let rs = from_hex(RESPONDER_PUBKEY);
+        let initiator = NoiseBuilder::with_resolver(PROTOCOL_NAME.parse().unwrap(), Box::new(RustCryptoResolver::new()))
+            .prologue(PROLOGUE)
+            .local_private_key(&[0x11u8; 32])
+            .fixed_ephemeral_key_for_testing_only(&[0x12u8; 32])
+            .remote_public_key(&rs)
+            .bolt8()
+            .build_initiator()
+            .unwrap();
+        let responder = NoiseBuilder::with_resolver(PROTOCOL_NAME.parse().unwrap(), Box::new(RustCryptoResolver::new()))
+            .prologue(PROLOGUE)
+            .local_private_key(&[0x21u8; 32])
+            .fixed_ephemeral_key_for_testing_only(&[0x22u8; 32])
+            .bolt8()
+            .build_responder()
+            .unwrap();
+        (initiator, responder)
+    }
+
+    fn handshake(initiator: &mut Session, responder: &mut Session) -> [Vec<u8>; 3] {
+        let (mut buf, mut payload) = ([0u8; 100], [0u8; 100]);
+        let len = initiator.write_message(&[], &mut buf).unwrap();
+        let act1 = buf[..len].to_vec();
+        responder.read_message(&act1, &mut payload).unwrap();
+        let len = responder.write_message(&[], &mut buf).unwrap();
+        let act2 = buf[..len].to_vec();
+        initiator.read_message(&act2, &mut payload).unwrap();
+        let len = initiator.write_message(&[], &mut buf).unwrap();
+        let act3 = buf[..len].to_vec();
+        responder.read_message(&act3, &mut payload).unwrap();
+        [act1, act2, act3]
+    }
+
+    #[test]
+    fn test_handshake_vectors() {
+        let (mut initiator, mut responder) = vector_sessions();
+        let [act1, act2, act3] = handshake(&mut initiator, &mut responder);
+        assert_eq!(act1.to_hex(), "00036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f70df6086551151f58b8afe6c195782c6a");
+        assert_eq!(act2.to_hex(), "0002466d7fcae563e5cb09a0d1870bb580344804617879a14949cf22285f1bae3f276e2470b93aac583c9ef6eafca3f730ae");
+        assert_eq!(act3.to_hex(), "00b9e3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc9d5b57357049aa22355361aa02e55a8fc28fef5bd6d71ad0c38228dc68b1c466263b47fdf31e560e139ba");
+        assert_eq!(responder.get_remote_static().unwrap().to_vec().to_hex(),
+                   "034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa");
+    }
+
+    #[test]
+    fn test_message_encryption_vectors() {
+        let (mut initiator, mut responder) = vector_sessions();
+        handshake(&mut initiator, &mut responder);
+        let mut initiator = initiator.into_transport_mode().unwrap();
+        let mut responder = responder.into_transport_mode().unwrap();
+
+        let expected = [
+            (0,    "cf2b30ddf0cf3f80e7c35a6e6730b59fe802473180f396d88a8fb0db8cbcf25d2f214cf9ea1d95"),
+            (1,    "72887022101f0b6753e0c7de21657d35a4cb2a1f5cde2650528bbc8f837d0f0d7ad833b1a256a1"),
+            (500,  "178cb9d7387190fa34db9c2d50027d21793c9bc2d40b1e14dcf30ebeeeb220f48364f7a4c68bf8"),
+            (501,  "1b186c57d44eb6de4c057c49940d79bb838a145cb528d6e8fd26dbe50a60ca2c104b56b60e45bd"),
+            (1000, "4a2f3cc3b5e78ddb83dcb426d9863d9d9a723b0337c89dd0b005d89f8d3c05c52b76b29b740f09"),
+            (1001, "2ecd8c8a5629d0d02ab457a0fdd0f7b90a192cd46be5ecb6ca570bfc5e268338b1a16cf4ef2d36"),
+        ];
+        let (mut buf, mut payload) = ([0u8; 100], [0u8; 100]);
+        let mut checked = 0;
+        for i in 0..1002 {
+            let len = initiator.write_message(b"hello", &mut buf).unwrap();
+            if let Some(&(_, hex)) = expected.iter().find(|&&(n, _)| n == i) {
+                assert_eq!(buf[..len].to_vec().to_hex(), hex, "message {}", i);
+                checked += 1;
+            }
+            // Alternate between reading whole messages and reading the length prefix first.
+            let read = if i % 2 == 0 {
+                responder.read_message(&buf[..len], &mut payload).unwrap()
+            } else {
+                let rest = responder.read_bolt8_length(&buf[..LENGTH_HEADER_LEN]).unwrap();
+                assert_eq!(rest, len - LENGTH_HEADER_LEN);
+                responder.read_message(&buf[LENGTH_HEADER_LEN..len], &mut payload).unwrap()
+            };
+            assert_eq!(&payload[..read], b"hello");
+        }
+        assert_eq!(checked, expected.len());
+    }
+
+    #[test]
+    fn test_bad_version_rejected() {
+        let (mut initiator, mut responder) = vector_sessions();
+        let (mut buf, mut payload) = ([0u8; 100], [0u8; 100]);
+        let len = initiator.write_message(&[], &mut buf).unwrap();
+        buf[0] = 1;
+        match responder.read_message(&buf[..len], &mut payload) {
+            Err(Error(ErrorKind::Input(InputProblem::Malformed(_)), _)) => {},
+            other => panic!("expected a malformed version, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_bolt8_without_padding() {
+        let result = NoiseBuilder::with_resolver(PROTOCOL_NAME.parse().unwrap(), Box::new(RustCryptoResolver::new()))
+            .local_private_key(&[0x21u8; 32])
+            .padding(::padding::PaddingPolicy::Multiple(16))
+            .bolt8()
+            .build_responder();
+        assert!(result.is_err());
+    }
+}
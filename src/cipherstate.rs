@@ -1,14 +1,15 @@
 use error::{self, ErrorKind, InitStage};
 use types::Cipher;
+use std::prelude::v1::*;
 
-pub struct CipherState {
-    cipher : Box<Cipher>,
+pub struct CipherState<C: Cipher = Box<Cipher>> {
+    cipher : C,
     n : u64,
     has_key : bool,
 }
 
-impl CipherState {
-    pub fn new(cipher: Box<Cipher>) -> Self {
+impl<C: Cipher> CipherState<C> {
+    pub fn new(cipher: C) -> Self {
         Self {
             cipher: cipher,
             n: 0,
@@ -63,10 +64,10 @@ impl CipherState {
     }
 }
 
-pub struct CipherStates(pub CipherState, pub CipherState);
+pub struct CipherStates<C: Cipher = Box<Cipher>>(pub CipherState<C>, pub CipherState<C>);
 
-impl CipherStates {
-    pub fn new(initiator: CipherState, responder: CipherState) -> error::Result<Self> {
+impl<C: Cipher> CipherStates<C> {
+    pub fn new(initiator: CipherState<C>, responder: CipherState<C>) -> error::Result<Self> {
         if initiator.name() != responder.name() {
             bail!(ErrorKind::Init(InitStage::ValidateCipherTypes));
         }
@@ -0,0 +1,347 @@
+//! Multi-recipient encryption built on the one-way `N` and `X` handshake patterns.
+//!
+//! The payload is encrypted once under a random content key. The content key is then sent to each
+//! recipient in its own "stanza": a one-way handshake message whose payload is the key followed by
+//! a hash of the header and the encrypted content. The hash commits every stanza to the same
+//! content, so with `X` one recipient can't reuse the content key to forge a different payload
+//! that appears to come from the sender for the others. Stanzas are tagged with a short hash of
+//! the recipient's public key so recipients can skip stanzas that aren't theirs, at the cost of
+//! revealing which (known) keys an envelope is addressed to.
+//!
+//! `seal` and `open` use the same resolver as `NoiseBuilder::new`; `seal_with_resolver` and
+//! `open_with_resolver` take every primitive from the resolver you give them instead.
+//!
+//! An envelope is laid out as:
+//!
+//! ```text
+//! magic || version || stanza count (u16)
+//!       || stanzas: recipient tag (8 bytes) || message length (u16)
+//!                   || handshake message carrying content key || H(header || content)
+//!       || content: chunks of CHUNK_LEN plaintext, each encrypted with the content key
+//! ```
+//!
+//! Every content chunk is authenticated with a flag marking whether it is the final chunk, so
+//! truncation at a chunk boundary is detected.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::NoiseBuilder;
+//! # use snow::envelope;
+//! # use snow::types::Dh;
+//! # use snow::wrappers::crypto_wrapper::Dh25519;
+//! let params = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+//! # let builder = NoiseBuilder::new("Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap());
+//! # let (alice_private, bob_private) = (builder.generate_private_key().unwrap(),
+//! #                                     builder.generate_private_key().unwrap());
+//! # let (mut alice, mut bob) = (Dh25519::default(), Dh25519::default());
+//! # alice.set(&alice_private);
+//! # bob.set(&bob_private);
+//! let sealed = envelope::seal(&params, &[alice.pubkey(), bob.pubkey()], None, b"release v1.0").unwrap();
+//!
+//! let (payload, _) = envelope::open(&params, &bob_private, &sealed).unwrap();
+//! assert_eq!(payload, b"release v1.0");
+//! ```
+
+use byteorder::{ByteOrder, BigEndian};
+use cipherstate::CipherState;
+use constants::{CIPHERKEYLEN, MAXHASHLEN, MAXMSGLEN, TAGLEN};
+use error::{ErrorKind, Result, InitStage, InputProblem, MessagePart};
+use noise::{CryptoResolver, NoiseBuilder, default_resolver};
+use params::{HandshakePattern, NoiseParams};
+use types::{Cipher, Hash};
+use utils::{Zeroize, Zeroizing};
+
+/// The magic bytes at the start of every envelope.
+pub const MAGIC: &'static [u8] = b"snowenv";
+
+/// The version of the envelope format written by `seal`.
+pub const VERSION: u8 = 2;
+
+/// The amount of plaintext carried by every content chunk but the last.
+pub const CHUNK_LEN: usize = MAXMSGLEN - TAGLEN;
+
+/// The length of the recipient tag in front of each stanza.
+pub const TAG_LEN: usize = 8;
+
+const FLAG_MORE: u8 = 0;
+const FLAG_FINAL: u8 = 1;
+
+fn prologue() -> Vec<u8> {
+    let mut prologue = MAGIC.to_vec();
+    prologue.push(VERSION);
+    prologue
+}
+
+fn validate_pattern(params: &NoiseParams) -> Result<()> {
+    match params.handshake.pattern {
+        HandshakePattern::N | HandshakePattern::X => Ok(()),
+        _ => bail!(ErrorKind::Init(InitStage::ValidateHandshakePattern)),
+    }
+}
+
+fn resolve_hash(resolver: &CryptoResolver, params: &NoiseParams) -> Result<Box<Hash>> {
+    resolver.resolve_hash(&params.hash).ok_or_else(|| ErrorKind::Init(InitStage::GetHashImpl).into())
+}
+
+fn resolve_cipher(resolver: &CryptoResolver, params: &NoiseParams) -> Result<Box<Cipher>> {
+    resolver.resolve_cipher(&params.cipher).ok_or_else(|| ErrorKind::Init(InitStage::GetCipherImpl).into())
+}
+
+/// The tag identifying stanzas addressed to `public_key`.
+pub fn recipient_tag(params: &NoiseParams, public_key: &[u8]) -> Result<[u8; TAG_LEN]> {
+    tag_with(&*default_resolver(), params, public_key)
+}
+
+fn tag_with(resolver: &CryptoResolver, params: &NoiseParams, public_key: &[u8]) -> Result<[u8; TAG_LEN]> {
+    let mut hasher = resolve_hash(resolver, params)?;
+    let mut digest = [0u8; MAXHASHLEN];
+    hasher.reset();
+    hasher.input(&prologue());
+    hasher.input(public_key);
+    hasher.result(&mut digest);
+    let mut tag = [0u8; TAG_LEN];
+    tag.copy_from_slice(&digest[..TAG_LEN]);
+    Ok(tag)
+}
+
+/// The hash of the envelope header and encrypted content carried in every stanza.
+fn commitment(resolver: &CryptoResolver, params: &NoiseParams, header: &[u8], content: &[u8])
+    -> Result<Vec<u8>>
+{
+    let mut hasher = resolve_hash(resolver, params)?;
+    let mut digest = [0u8; MAXHASHLEN];
+    hasher.reset();
+    hasher.input(header);
+    hasher.input(content);
+    hasher.result(&mut digest);
+    Ok(digest[..hasher.hash_len()].to_vec())
+}
+
+/// Encrypt `payload` to every key in `recipients`.
+///
+/// `sender_private` is your static private key, which is required for the `X` pattern and
+/// ignored for `N`.
+///
+/// # Errors
+///
+/// Will result in `ErrorKind::Init(InitStage::ValidateHandshakePattern)` if `params` isn't `N` or
+/// `X`, and `ErrorKind::Input` if there are no recipients or more than 65535.
+pub fn seal(params: &NoiseParams,
+            recipients: &[&[u8]],
+            sender_private: Option<&[u8]>,
+            payload: &[u8]) -> Result<Vec<u8>> {
+    seal_with_resolver(&*default_resolver(), params, recipients, sender_private, payload)
+}
+
+/// Like `seal`, but using primitives from `resolver`.
+pub fn seal_with_resolver(resolver: &CryptoResolver,
+                          params: &NoiseParams,
+                          recipients: &[&[u8]],
+                          sender_private: Option<&[u8]>,
+                          payload: &[u8]) -> Result<Vec<u8>> {
+    validate_pattern(params)?;
+    if recipients.is_empty() || recipients.len() > u16::max_value() as usize {
+        bail!(ErrorKind::Input(InputProblem::Argument("recipients")));
+    }
+
+    let mut rng = resolver.resolve_rng().ok_or(ErrorKind::Init(InitStage::GetRngImpl))?;
+    let mut content_key = Zeroizing::new([0u8; CIPHERKEYLEN]);
+    rng.fill_bytes(&mut content_key[..]);
+
+    let prologue = prologue();
+    let mut sealed = prologue.clone();
+    let mut count = [0u8; 2];
+    BigEndian::write_u16(&mut count, recipients.len() as u16);
+    sealed.extend_from_slice(&count);
+
+    let mut message = vec![0u8; MAXMSGLEN];
+    let mut content = CipherState::new(resolve_cipher(resolver, params)?);
+    content.set(&content_key[..], 0);
+    let mut ciphertext = Vec::with_capacity(payload.len() + TAGLEN);
+    let mut chunks = payload.chunks(CHUNK_LEN).peekable();
+    if chunks.peek().is_none() {
+        let len = content.encrypt_ad(&[FLAG_FINAL], &[], &mut message);
+        ciphertext.extend_from_slice(&message[..len]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let flag = if chunks.peek().is_some() { FLAG_MORE } else { FLAG_FINAL };
+        let len = content.encrypt_ad(&[flag], chunk, &mut message);
+        ciphertext.extend_from_slice(&message[..len]);
+    }
+
+    let digest = commitment(resolver, params, &sealed, &ciphertext)?;
+    let mut stanza_payload = Zeroizing::new([0u8; CIPHERKEYLEN + MAXHASHLEN]);
+    stanza_payload[..CIPHERKEYLEN].copy_from_slice(&content_key[..]);
+    stanza_payload[CIPHERKEYLEN..CIPHERKEYLEN + digest.len()].copy_from_slice(&digest);
+    let stanza_len = CIPHERKEYLEN + digest.len();
+    for recipient in recipients {
+        let mut builder = NoiseBuilder::with_resolver(params.clone(), Box::new(resolver))
+            .remote_public_key(recipient)
+            .prologue(&prologue);
+        if let Some(key) = sender_private {
+            if params.handshake.pattern.needs_local_static_key(true) {
+                builder = builder.local_private_key(key);
+            }
+        }
+        let mut session = builder.build_initiator()?;
+        let len = session.write_message(&stanza_payload[..stanza_len], &mut message)?;
+
+        sealed.extend_from_slice(&tag_with(resolver, params, recipient)?);
+        let mut len_bytes = [0u8; 2];
+        BigEndian::write_u16(&mut len_bytes, len as u16);
+        sealed.extend_from_slice(&len_bytes);
+        sealed.extend_from_slice(&message[..len]);
+    }
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypt an envelope with your static private key, returning the payload and, for the `X`
+/// pattern, the sender's static public key.
+///
+/// Stanzas tagged for your key are tried first, then every other stanza, until one yields the
+/// content key along with a commitment matching the envelope's content.
+///
+/// # Errors
+///
+/// Will result in `ErrorKind::Input` if the envelope is malformed, and `ErrorKind::Decrypt` if no
+/// stanza can be opened with your key or the content doesn't decrypt.
+pub fn open(params: &NoiseParams,
+            recipient_private: &[u8],
+            sealed: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+    open_with_resolver(&*default_resolver(), params, recipient_private, sealed)
+}
+
+/// Like `open`, but using primitives from `resolver`.
+pub fn open_with_resolver(resolver: &CryptoResolver,
+                          params: &NoiseParams,
+                          recipient_private: &[u8],
+                          sealed: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+    validate_pattern(params)?;
+
+    let prologue = prologue();
+    if sealed.len() < prologue.len() + 2 || sealed[..prologue.len()] != prologue[..] {
+        bail!(ErrorKind::Input(InputProblem::Malformed("envelope")));
+    }
+    let header = &sealed[..prologue.len() + 2];
+    let count = BigEndian::read_u16(&sealed[prologue.len()..]) as usize;
+    let mut remaining = &sealed[prologue.len() + 2..];
+
+    let my_tag = {
+        let mut dh = resolver.resolve_dh(&params.dh).ok_or(ErrorKind::Init(InitStage::GetDhImpl))?;
+        dh.set(recipient_private);
+        tag_with(resolver, params, dh.pubkey())?
+    };
+
+    let mut stanzas = Vec::with_capacity(count);
+    for _ in 0..count {
+        if remaining.len() < TAG_LEN + 2 {
+            bail!(ErrorKind::Input(InputProblem::Malformed("envelope")));
+        }
+        let len = BigEndian::read_u16(&remaining[TAG_LEN..]) as usize;
+        if remaining.len() < TAG_LEN + 2 + len {
+            bail!(ErrorKind::Input(InputProblem::Malformed("envelope")));
+        }
+        stanzas.push((&remaining[..TAG_LEN], &remaining[TAG_LEN + 2..TAG_LEN + 2 + len]));
+        remaining = &remaining[TAG_LEN + 2 + len..];
+    }
+    // Stanzas tagged for this key are tried first, but the rest are still tried in case the sender
+    // used a different tag for the same key.
+    stanzas.sort_by_key(|&(tag, _)| tag != my_tag);
+    let expected = commitment(resolver, params, header, remaining)?;
+
+    let mut opened = None;
+    let mut buf = vec![0u8; MAXMSGLEN];
+    for &(_, message) in &stanzas {
+        let mut session = NoiseBuilder::with_resolver(params.clone(), Box::new(resolver))
+            .local_private_key(recipient_private)
+            .prologue(&prologue)
+            .build_responder()?;
+        if let Ok(len) = session.read_message(message, &mut buf) {
+            if len == CIPHERKEYLEN + expected.len() && buf[CIPHERKEYLEN..len] == expected[..] {
+                let mut content_key = Zeroizing::new([0u8; CIPHERKEYLEN]);
+                content_key.copy_from_slice(&buf[..CIPHERKEYLEN]);
+                buf[..CIPHERKEYLEN].zeroize();
+                let sender = if params.handshake.pattern.needs_local_static_key(true) {
+                    session.get_remote_static().map(|k| k.to_vec())
+                } else {
+                    None
+                };
+                opened = Some((content_key, sender));
+                break;
+            }
+        }
+    }
+
+    let (content_key, sender) = match opened {
+        Some(opened) => opened,
+        None => bail!(ErrorKind::Decrypt(MessagePart::Other("recipient stanzas"))),
+    };
+
+    let mut content = CipherState::new(resolve_cipher(resolver, params)?);
+    content.set(&content_key[..], 0);
+    let mut payload = Vec::with_capacity(remaining.len());
+    loop {
+        if remaining.len() < TAGLEN {
+            bail!(ErrorKind::Input(InputProblem::Malformed("envelope")));
+        }
+        let (chunk, flag) = if remaining.len() > CHUNK_LEN + TAGLEN {
+            (&remaining[..CHUNK_LEN + TAGLEN], FLAG_MORE)
+        } else {
+            (remaining, FLAG_FINAL)
+        };
+        let len = content.decrypt_ad(&[flag], chunk, &mut buf).map_err(|_| ErrorKind::Decrypt(MessagePart::Other("envelope content")))?;
+        payload.extend_from_slice(&buf[..len]);
+        remaining = &remaining[chunk.len()..];
+        if flag == FLAG_FINAL {
+            break;
+        }
+    }
+    Ok((payload, sender))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::Dh;
+    use wrappers::crypto_wrapper::Dh25519;
+
+    #[test]
+    fn test_recipient_cannot_swap_content() {
+        let params: NoiseParams = "Noise_X_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let (mut sender, mut alice, mut bob) = (Dh25519::default(), Dh25519::default(), Dh25519::default());
+        sender.set(&[1u8; 32]);
+        alice.set(&[2u8; 32]);
+        bob.set(&[3u8; 32]);
+        let sealed = seal(&params, &[alice.pubkey(), bob.pubkey()], Some(sender.privkey()), b"pay bob 1").unwrap();
+
+        let header_len = MAGIC.len() + 1 + 2;
+        let mut content_start = header_len;
+        for _ in 0..2 {
+            content_start += TAG_LEN + 2 + BigEndian::read_u16(&sealed[content_start + TAG_LEN..]) as usize;
+        }
+
+        // Alice recovers the content key from her own stanza...
+        let alice_len = BigEndian::read_u16(&sealed[header_len + TAG_LEN..]) as usize;
+        let alice_stanza = &sealed[header_len + TAG_LEN + 2..header_len + TAG_LEN + 2 + alice_len];
+        let mut session = NoiseBuilder::new(params.clone())
+            .local_private_key(alice.privkey())
+            .prologue(&prologue())
+            .build_responder().unwrap();
+        let mut buf = vec![0u8; MAXMSGLEN];
+        session.read_message(alice_stanza, &mut buf).unwrap();
+
+        // ...and re-encrypts different content under it, keeping Bob's stanza.
+        let mut content = CipherState::new(resolve_cipher(&*default_resolver(), &params).unwrap());
+        content.set(&buf[..CIPHERKEYLEN], 0);
+        let mut forged = sealed[..content_start].to_vec();
+        let len = content.encrypt_ad(&[FLAG_FINAL], b"pay eve 1000", &mut buf);
+        forged.extend_from_slice(&buf[..len]);
+
+        assert!(open(&params, bob.privkey(), &forged).is_err());
+        let (payload, from) = open(&params, bob.privkey(), &sealed).unwrap();
+        assert_eq!(payload, b"pay bob 1");
+        assert_eq!(from.unwrap(), sender.pubkey());
+    }
+}
@@ -1,6 +1,15 @@
 //! All error types used by Snow operations.
 
+use params::Token;
+use std::fmt;
+use std::prelude::v1::*;
+
+#[cfg(feature = "std")]
 error_chain!{
+    foreign_links {
+        Io(::std::io::Error) #[doc = "An I/O error from a stream being read or written."];
+    }
+
     errors {
         Init(stage: InitStage) {
             description("an error occurred during initialization")
@@ -14,17 +23,130 @@ error_chain!{
             description("invalid state error")
             display("state error of type {:?}", state_problem)
         }
-        Input
-        Decrypt
+        Input(problem: InputProblem) {
+            description("invalid input")
+            display("invalid input: {}", problem)
+        }
+        Decrypt(part: MessagePart) {
+            description("decryption failed")
+            display("decryption or authentication failed in {}", part)
+        }
+        Dh(part: MessagePart) {
+            description("a Diffie-Hellman result was degenerate")
+            display("invalid or low-order public key, or all-zero shared secret in {}", part)
+        }
+        KeyProvider(part: MessagePart) {
+            description("a static key provider couldn't compute a Diffie-Hellman result")
+            display("the static key provider failed in {}", part)
+        }
+        Parse(problem: ParseProblem) {
+            description("a protocol name couldn't be parsed")
+            display("invalid protocol name: {}", problem)
+        }
+        SelfTest(report: ::selftest::SelfTestReport) {
+            description("a self-test failed")
+            display("self-test failed: {}", self_test_failures(report))
+        }
     }
 }
 
+#[cfg(not(feature = "std"))]
+pub use self::bare::*;
+
+/// The same shape as the `error_chain` types, for builds without `std`.
+#[cfg(not(feature = "std"))]
+mod bare {
+    use super::*;
+
+    /// The error type for Snow operations. The second field takes the place of `error_chain`'s
+    /// backtrace and cause, so `Error(kind, _)` patterns work with and without `std`.
+    #[derive(Debug)]
+    pub struct Error(pub ErrorKind, pub ());
+
+    /// A `Result` with `Error` as its error type.
+    pub type Result<T> = ::core::result::Result<T, Error>;
+
+    /// The kinds of errors Snow operations can fail with.
+    #[derive(Debug)]
+    pub enum ErrorKind {
+        Init(InitStage),
+        Prereq(Prerequisite),
+        State(StateProblem),
+        Input(InputProblem),
+        Decrypt(MessagePart),
+        Dh(MessagePart),
+        KeyProvider(MessagePart),
+        Parse(ParseProblem),
+        SelfTest(::selftest::SelfTestReport),
+    }
+
+    impl Error {
+        /// The kind of error.
+        pub fn kind(&self) -> &ErrorKind {
+            &self.0
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Error(kind, ())
+        }
+    }
+
+    impl fmt::Display for ErrorKind {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                ErrorKind::Init(ref stage)      => write!(f, "initialization failed at {:?} stage", stage),
+                ErrorKind::Prereq(ref prereq)   => write!(f, "missing prerequisite: {:?}", prereq),
+                ErrorKind::State(ref problem)   => write!(f, "state error of type {:?}", problem),
+                ErrorKind::Input(ref problem)   => write!(f, "invalid input: {}", problem),
+                ErrorKind::Decrypt(ref part)    => write!(f, "decryption or authentication failed in {}", part),
+                ErrorKind::Dh(ref part)         => write!(f, "invalid or low-order public key, or all-zero shared secret in {}", part),
+                ErrorKind::KeyProvider(ref part) => write!(f, "the static key provider failed in {}", part),
+                ErrorKind::Parse(ref problem)   => write!(f, "invalid protocol name: {}", problem),
+                ErrorKind::SelfTest(ref report) => write!(f, "self-test failed: {}", self_test_failures(report)),
+            }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+}
+
+fn self_test_failures(report: &::selftest::SelfTestReport) -> String {
+    report.failures.iter()
+        .map(|(test, reason)| format!("{} ({})", test, reason))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 /// The various stages of initialization used to help identify
 /// the specific cause of an `Init` error.
 #[derive(Debug)]
 pub enum InitStage {
-    ValidateKeyLengths, ValidatePskLengths, ValidateCipherTypes,
-    GetRngImpl, GetDhImpl, GetCipherImpl, GetHashImpl
+    ValidateKeyLengths, ValidateCipherTypes, ValidateHandshakePattern, NegotiateParams,
+    GetRngImpl, GetDhImpl, GetCipherImpl, GetHashImpl,
+    /// A value given to the builder was invalid.
+    ValidateField(BuilderField),
+    /// The resolver refuses the named primitive by policy, e.g. a `FipsResolver` refusing
+    /// `ChaChaPoly`.
+    Disallowed(String),
+    /// A static private key or PSK couldn't be moved into locked memory, e.g. because
+    /// `RLIMIT_MEMLOCK` was reached (only with the `secure-memory` feature).
+    LockMemory,
+}
+
+/// A value given to `NoiseBuilder` (or `NegotiatingBuilder`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuilderField {
+    LocalPrivateKey, RemotePublicKey, FixedEphemeralKey, Psk(u8), PaddingPolicy, ProtocolHint,
+    /// The provider's DH function or key length doesn't match, or a local private key was given too.
+    StaticKeyProvider,
+    /// BOLT 8 framing can't be combined with a padding policy.
+    Bolt8,
 }
 
 /// A prerequisite that may be missing.
@@ -37,6 +159,88 @@ pub enum Prerequisite {
 #[derive(Debug)]
 pub enum StateProblem {
     MissingKeyMaterial, MissingPsk, NotTurnToWrite, NotTurnToRead,
-    HandshakeNotFinished, HandshakeAlreadyFinished, OneWay
+    HandshakeNotFinished, HandshakeAlreadyFinished, OneWay,
+    /// A BOLT 8 length prefix was given to a session built without `NoiseBuilder::bolt8`.
+    NotBolt8,
+}
+
+/// What was wrong with an input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputProblem {
+    /// The output buffer is too small.
+    BufferTooSmall,
+    /// A message or payload is longer than a Noise message can be.
+    MessageTooLong,
+    /// The message ended before the given part of it.
+    Truncated(MessagePart),
+    /// Part of a padded payload, sealed box, envelope, stream, protocol hint or BOLT 8 message is
+    /// malformed.
+    Malformed(&'static str),
+    /// An argument was invalid.
+    Argument(&'static str),
+}
+
+impl fmt::Display for InputProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InputProblem::BufferTooSmall       => write!(f, "buffer too small"),
+            InputProblem::MessageTooLong       => write!(f, "message too long"),
+            InputProblem::Truncated(ref part)  => write!(f, "message truncated before {}", part),
+            InputProblem::Malformed(what)      => write!(f, "malformed {}", what),
+            InputProblem::Argument(what)       => write!(f, "invalid {}", what),
+        }
+    }
+}
+
+/// A part of a message. Handshake messages are counted from 0.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessagePart {
+    /// A token of a handshake message.
+    Token { message: usize, token: Token },
+    /// The payload of a handshake message.
+    Payload { message: usize },
+    /// A transport message, and the nonce it was read with.
+    Transport { nonce: u64 },
+    /// Part of a sealed box, envelope or stream.
+    Other(&'static str),
+}
+
+impl fmt::Display for MessagePart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MessagePart::Token { message, token } => write!(f, "token {} of handshake message {}", token, message),
+            MessagePart::Payload { message }      => write!(f, "the payload of handshake message {}", message),
+            MessagePart::Transport { nonce }      => write!(f, "transport message {}", nonce),
+            MessagePart::Other(what)              => write!(f, "the {}", what),
+        }
+    }
+}
+
+/// The part of a protocol name that couldn't be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseProblem {
+    TooFewParameters,
+    UnsupportedBaseType(String),
+    UnsupportedHandshakePattern(String),
+    UnsupportedModifier(String),
+    UnsupportedDhType(String),
+    UnsupportedCipherType(String),
+    UnsupportedHashType(String),
+    /// A `pskN` modifier refers to a message the pattern doesn't have.
+    IncompatiblePsk(u8),
 }
 
+impl fmt::Display for ParseProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseProblem::TooFewParameters                    => write!(f, "too few parameters"),
+            ParseProblem::UnsupportedBaseType(ref s)          => write!(f, "unsupported base type \"{}\"", s),
+            ParseProblem::UnsupportedHandshakePattern(ref s)  => write!(f, "unsupported handshake pattern \"{}\"", s),
+            ParseProblem::UnsupportedModifier(ref s)          => write!(f, "unsupported modifier \"{}\"", s),
+            ParseProblem::UnsupportedDhType(ref s)            => write!(f, "unsupported DH type \"{}\"", s),
+            ParseProblem::UnsupportedCipherType(ref s)        => write!(f, "unsupported cipher type \"{}\"", s),
+            ParseProblem::UnsupportedHashType(ref s)          => write!(f, "unsupported hash type \"{}\"", s),
+            ParseProblem::IncompatiblePsk(n)                  => write!(f, "psk{} doesn't fit the handshake pattern", n),
+        }
+    }
+}
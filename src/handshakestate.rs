@@ -6,7 +6,90 @@ use cipherstate::*;
 #[cfg(not(feature = "nightly"))] use utils::TryFrom;
 use symmetricstate::*;
 use params::*;
-use error::{ErrorKind, Result, InitStage, StateProblem};
+use padding::PaddingPolicy;
+use bolt8::{self, Framing};
+use secmem::SecretBuf;
+use transportstate::TransportState;
+use error::{ErrorKind, Result, InitStage, StateProblem, InputProblem, MessagePart};
+use trace::{TraceEvent, Tracer};
+use std::prelude::v1::*;
+
+/// The local static key: a private key held by a `Dh`, or a key behind a `StaticKeyProvider`.
+pub(crate) enum StaticKey<D: Dh> {
+    Local(D),
+    Provided(Box<StaticKeyProvider>),
+}
+
+/// The local ephemeral key, which the handshake only uses through `EphemeralDh` once the builder
+/// has generated or fixed it.
+pub(crate) struct EphemeralKey<D: Dh>(pub(crate) D);
+
+impl<D: Dh> EphemeralDh for EphemeralKey<D> {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn pub_len(&self) -> usize {
+        self.0.pub_len()
+    }
+
+    fn dh_len(&self) -> usize {
+        self.0.dh_len()
+    }
+
+    fn generate(&mut self, rng: &mut Random) {
+        self.0.generate(rng)
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        self.0.pubkey()
+    }
+
+    fn pub_valid(&self, pubkey: &[u8]) -> bool {
+        self.0.pub_valid(pubkey)
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> ::std::result::Result<(), ()> {
+        self.0.dh(pubkey, out)
+    }
+}
+
+impl<D: Dh> StaticKey<D> {
+    fn pub_len(&self) -> usize {
+        match *self {
+            StaticKey::Local(ref dh) => dh.pub_len(),
+            StaticKey::Provided(ref provider) => provider.pub_len(),
+        }
+    }
+
+    fn dh_len(&self) -> usize {
+        match *self {
+            StaticKey::Local(ref dh) => dh.dh_len(),
+            StaticKey::Provided(ref provider) => provider.dh_len(),
+        }
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        match *self {
+            StaticKey::Local(ref dh) => dh.pubkey(),
+            StaticKey::Provided(ref provider) => provider.pubkey(),
+        }
+    }
+
+    fn is_provided(&self) -> bool {
+        match *self {
+            StaticKey::Local(_) => false,
+            StaticKey::Provided(_) => true,
+        }
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> ::std::result::Result<(), ()> {
+        match *self {
+            StaticKey::Local(ref dh) => dh.dh(pubkey, out),
+            StaticKey::Provided(ref provider) => provider.dh(pubkey, out),
+        }
+    }
+}
 
 
 /// A state machine encompassing the handshake phase of a Noise session.
@@ -14,39 +97,52 @@ use error::{ErrorKind, Result, InitStage, StateProblem};
 /// **Note:** you are probably looking for [`NoiseBuilder`](struct.NoiseBuilder.html) to
 /// get started.
 ///
+/// The primitives are type parameters, so a state built with
+/// [`NoiseBuilder::build_static_initiator`](struct.NoiseBuilder.html#method.build_static_initiator)
+/// calls them directly and holds them inline. The defaults are the boxed primitives from a
+/// `CryptoResolver`, which is what a `Session` uses.
+///
 /// See: http://noiseprotocol.org/noise.html#the-handshakestate-object
-pub struct HandshakeState {
-    rng : Box<Random>,
-    symmetricstate : SymmetricState,
-    cipherstates: CipherStates,
-    s: Toggle<Box<Dh>>,
-    e: Toggle<Box<Dh>>,
+pub struct HandshakeState<D: Dh = Box<Dh>, C: Cipher = Box<Cipher>, H: Hash = Box<Hash>, R: Random = Box<Random>> {
+    rng : R,
+    symmetricstate : SymmetricState<C, H>,
+    cipherstates: CipherStates<C>,
+    s: Toggle<StaticKey<D>>,
+    e: Toggle<EphemeralKey<D>>,
     fixed_ephemeral: bool,
     rs: Toggle<[u8; MAXDHLEN]>,
     re: Toggle<[u8; MAXDHLEN]>,
     initiator: bool,
     params: NoiseParams,
-    psks: [Option<[u8; PSKLEN]>; 10],
+    psks: SecretBuf<[Option<[u8; PSKLEN]>; 10]>,
     my_turn: bool,
     message_patterns: MessagePatterns,
+    message_index: usize,
+    padding: Option<PaddingPolicy>,
+    bolt8: Option<Box<Hash>>,
+    allow_low_order: bool,
 }
 
-impl HandshakeState {
+impl<D: Dh, C: Cipher, H: Hash, R: Random> HandshakeState<D, C, H, R> {
     #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
-    pub fn new(
-        rng: Box<Random>,
-        cipherstate: CipherState,
-        hasher: Box<Hash>,
-        s : Toggle<Box<Dh>>,
-        e : Toggle<Box<Dh>>,
+    pub(crate) fn new(
+        rng: R,
+        cipherstate: CipherState<C>,
+        hasher: H,
+        s : Toggle<StaticKey<D>>,
+        e : Toggle<EphemeralKey<D>>,
         fixed_ephemeral: bool,
         rs: Toggle<[u8; MAXDHLEN]>,
         re: Toggle<[u8; MAXDHLEN]>,
         initiator: bool,
         params: NoiseParams,
-        psks: [Option<[u8; PSKLEN]>; 10],
-        prologue: &[u8],
-        cipherstates: CipherStates) -> Result<HandshakeState> {
+        psks: SecretBuf<[Option<[u8; PSKLEN]>; 10]>,
+        prologue: &[&[u8]],
+        cipherstates: CipherStates<C>,
+        padding: Option<PaddingPolicy>,
+        bolt8: Option<Box<Hash>>,
+        allow_low_order: bool,
+        tracer: Option<Box<Tracer>>) -> Result<Self> {
 
         if (s.is_on() && e.is_on()  && s.pub_len() != e.pub_len())
         || (s.is_on() && rs.is_on() && s.pub_len() >  rs.len())
@@ -55,12 +151,12 @@ impl HandshakeState {
             bail!(ErrorKind::Init(InitStage::ValidateKeyLengths));
         }
 
-        let tokens = HandshakeTokens::try_from(params.handshake.clone())?;
+        let tokens = HandshakeTokens::try_from(&params.handshake)?;
 
-        let mut symmetricstate = SymmetricState::new(cipherstate, hasher);
+        let mut symmetricstate = SymmetricState::new(cipherstate, hasher, tracer);
 
         symmetricstate.initialize(&params.name);
-        symmetricstate.mix_hash(prologue);
+        symmetricstate.mix_hash_parts(prologue);
 
         let dh_len = s.pub_len();
         if initiator {
@@ -109,6 +205,10 @@ impl HandshakeState {
             psks: psks,
             my_turn: initiator,
             message_patterns: tokens.msg_patterns.into(),
+            message_index: 0,
+            padding: padding,
+            bolt8: bolt8,
+            allow_low_order: allow_low_order,
         })
     }
 
@@ -116,7 +216,7 @@ impl HandshakeState {
         self.s.pub_len()
     }
 
-    fn dh(&mut self, local_s: bool, remote_s: bool) -> Result<()> {
+    fn dh(&mut self, token: Token, local_s: bool, remote_s: bool) -> Result<()> {
         if !((!local_s  || self.s.is_on())  &&
              ( local_s  || self.e.is_on())  &&
              (!remote_s || self.rs.is_on()) &&
@@ -124,45 +224,78 @@ impl HandshakeState {
         {
             bail!(ErrorKind::State(StateProblem::MissingKeyMaterial));
         }
-        let dh_len = self.dh_len();
+        // Keys that aren't points at all are never let through, whatever `allow_low_order` says.
+        let remote = if remote_s { &self.rs[..] } else { &self.re[..] };
+        if !self.e.pub_valid(&remote[..self.e.pub_len()]) {
+            bail!(ErrorKind::Dh(MessagePart::Token { message: self.message_index - 1, token: token }));
+        }
+        let dh_len = if local_s { self.s.dh_len() } else { self.e.dh_len() };
         let mut dh_out = [0u8; MAXDHLEN];
-        match (local_s, remote_s) {
+        let result = match (local_s, remote_s) {
             (true,  true ) => self.s.dh(&*self.rs, &mut dh_out),
             (true,  false) => self.s.dh(&*self.re, &mut dh_out),
             (false, true ) => self.e.dh(&*self.rs, &mut dh_out),
             (false, false) => self.e.dh(&*self.re, &mut dh_out),
+        };
+        // A provider's error means it couldn't answer at all, which no setting should paper over.
+        if result.is_err() && local_s && self.s.is_provided() {
+            dh_out.zeroize();
+            bail!(ErrorKind::KeyProvider(MessagePart::Token { message: self.message_index - 1, token: token }));
+        }
+        // Don't rely on the `Dh` implementation alone to catch an all-zero shared secret.
+        let all_zero = dh_out[..dh_len].iter().fold(0, |acc, b| acc | b) == 0;
+        if (result.is_err() || all_zero) && !self.allow_low_order {
+            dh_out.zeroize();
+            bail!(ErrorKind::Dh(MessagePart::Token { message: self.message_index - 1, token: token }));
         }
         self.symmetricstate.mix_key(&dh_out[..dh_len]);
+        dh_out.zeroize();
         Ok(())
     }
 
+    /// Whether the next payload written will be encrypted.
     pub fn is_write_encrypted(&self) -> bool {
         self.symmetricstate.has_key()
     }
 
+    /// Write the next handshake message, with `payload`, into `message`, returning its length.
     pub fn write_handshake_message(&mut self,
                          payload: &[u8], 
                          message: &mut [u8]) -> Result<usize> {
+        if self.bolt8.is_none() {
+            return self.write_noise_message(payload, message);
+        }
+        if message.is_empty() {
+            bail!(ErrorKind::Input(InputProblem::BufferTooSmall));
+        }
+        message[0] = bolt8::VERSION;
+        Ok(1 + self.write_noise_message(payload, &mut message[1..])?)
+    }
+
+    fn write_noise_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize> {
         if !self.my_turn {
             bail!(ErrorKind::State(StateProblem::NotTurnToWrite));
         }
 
         let next_tokens = if !self.message_patterns.is_empty() {
-            self.message_patterns.remove(0).unwrap()
+            self.message_patterns.remove(0)
         } else {
             bail!(ErrorKind::State(StateProblem::HandshakeAlreadyFinished));
         };
         let last = self.message_patterns.is_empty();
+        self.message_index += 1;
+        self.symmetricstate.trace(&TraceEvent::WriteMessage);
 
         let mut byte_index = 0;
         for token in next_tokens.iter() {
+            self.symmetricstate.trace(&TraceEvent::Token(*token));
             match *token {
                 Token::E => {
                     if byte_index + self.e.pub_len() > message.len() {
-                        bail!(ErrorKind::Input)
+                        bail!(ErrorKind::Input(InputProblem::BufferTooSmall))
                     }
                     if !self.fixed_ephemeral {
-                        self.e.generate(&mut *self.rng);
+                        self.e.generate(&mut self.rng);
                     }
                     {
                         let pubkey = self.e.pubkey();
@@ -180,7 +313,7 @@ impl HandshakeState {
                         bail!(ErrorKind::State(StateProblem::MissingKeyMaterial));
                     }
                     if byte_index + self.s.pub_len() > message.len() {
-                        bail!(ErrorKind::Input)
+                        bail!(ErrorKind::Input(InputProblem::BufferTooSmall))
                     }
                     byte_index += self.symmetricstate.encrypt_and_mix_hash(
                         self.s.pubkey(),
@@ -188,28 +321,28 @@ impl HandshakeState {
                 },
                 Token::Psk(n) => {
                     match self.psks[n as usize] {
-                        Some(psk) => {
-                            self.symmetricstate.mix_key_and_hash(&psk);
+                        Some(ref psk) => {
+                            self.symmetricstate.mix_key_and_hash(psk);
                         },
                         None => {
                             bail!(ErrorKind::State(StateProblem::MissingPsk));
                         }
                     }
                 },
-                Token::Dhee => self.dh(false, false)?,
-                Token::Dhes => self.dh(false, true )?,
-                Token::Dhse => self.dh(true,  false)?,
-                Token::Dhss => self.dh(true,  true )?,
+                Token::Dhee => self.dh(Token::Dhee, false, false)?,
+                Token::Dhes => self.dh(Token::Dhes, false, true )?,
+                Token::Dhse => self.dh(Token::Dhse, true,  false)?,
+                Token::Dhss => self.dh(Token::Dhss, true,  true )?,
             }
         }
 
         self.my_turn = false;
         if byte_index + payload.len() + TAGLEN > message.len() {
-            bail!(ErrorKind::Input);
+            bail!(ErrorKind::Input(InputProblem::BufferTooSmall));
         }
         byte_index += self.symmetricstate.encrypt_and_mix_hash(payload, &mut message[byte_index..]);
         if byte_index > MAXMSGLEN {
-            bail!(ErrorKind::Input);
+            bail!(ErrorKind::Input(InputProblem::MessageTooLong));
         }
         if last {
             self.symmetricstate.split(&mut self.cipherstates.0, &mut self.cipherstates.1);
@@ -217,26 +350,45 @@ impl HandshakeState {
         Ok(byte_index)
     }
 
+    /// Read the next handshake message, writing its payload into `payload` and returning its length.
     pub fn read_handshake_message(&mut self,
                         message: &[u8], 
                         payload: &mut [u8]) -> Result<usize> {
+        if self.bolt8.is_none() {
+            return self.read_noise_message(message, payload);
+        }
+        match message.first() {
+            Some(&bolt8::VERSION) => self.read_noise_message(&message[1..], payload),
+            _ => bail!(ErrorKind::Input(InputProblem::Malformed("BOLT 8 handshake version"))),
+        }
+    }
+
+    fn read_noise_message(&mut self, message: &[u8], payload: &mut [u8]) -> Result<usize> {
         if message.len() > MAXMSGLEN {
-            bail!(ErrorKind::Input);
+            bail!(ErrorKind::Input(InputProblem::MessageTooLong));
         }
 
         let next_tokens = if self.message_patterns.len() > 0 {
-            self.message_patterns.remove(0)
+            self.message_patterns.pop_at(0)
         } else {
             None
         };
         let last = next_tokens.is_some() && self.message_patterns.is_empty();
+        let index = self.message_index;
+        self.message_index += 1;
+        let token_part = |token| MessagePart::Token { message: index, token: token };
 
         let dh_len = self.dh_len();
         let mut ptr = message;
+        self.symmetricstate.trace(&TraceEvent::ReadMessage);
         if let Some(tokens) = next_tokens {
             for token in tokens.iter() {
+                self.symmetricstate.trace(&TraceEvent::Token(*token));
                 match *token {
                     Token::E => {
+                        if ptr.len() < dh_len {
+                            bail!(ErrorKind::Input(InputProblem::Truncated(token_part(Token::E))));
+                        }
                         self.re[..dh_len].copy_from_slice(&ptr[..dh_len]);
                         ptr = &ptr[dh_len..];
                         self.symmetricstate.mix_hash(&self.re[..dh_len]);
@@ -246,6 +398,10 @@ impl HandshakeState {
                         self.re.enable();
                     },
                     Token::S => {
+                        let s_len = if self.symmetricstate.has_key() { dh_len + TAGLEN } else { dh_len };
+                        if ptr.len() < s_len {
+                            bail!(ErrorKind::Input(InputProblem::Truncated(token_part(Token::S))));
+                        }
                         let data = if self.symmetricstate.has_key() {
                             let temp = &ptr[..dh_len + TAGLEN];
                             ptr = &ptr[dh_len + TAGLEN..];
@@ -255,27 +411,32 @@ impl HandshakeState {
                             ptr = &ptr[dh_len..];
                             temp
                         };
-                        self.symmetricstate.decrypt_and_mix_hash(data, &mut self.rs[..dh_len]).map_err(|_| ErrorKind::Decrypt)?;
+                        self.symmetricstate.decrypt_and_mix_hash(data, &mut self.rs[..dh_len])
+                            .map_err(|_| ErrorKind::Decrypt(token_part(Token::S)))?;
                         self.rs.enable();
                     },
                     Token::Psk(n) => {
                         match self.psks[n as usize] {
-                            Some(psk) => {
-                                self.symmetricstate.mix_key_and_hash(&psk);
+                            Some(ref psk) => {
+                                self.symmetricstate.mix_key_and_hash(psk);
                             },
                             None => {
                                 bail!(ErrorKind::State(StateProblem::MissingPsk));
                             }
                         }
                     },
-                    Token::Dhee => self.dh(false, false)?,
-                    Token::Dhes => self.dh(true, false)?,
-                    Token::Dhse => self.dh(false, true)?,
-                    Token::Dhss => self.dh(true, true)?,
+                    Token::Dhee => self.dh(Token::Dhee, false, false)?,
+                    Token::Dhes => self.dh(Token::Dhes, true, false)?,
+                    Token::Dhse => self.dh(Token::Dhse, false, true)?,
+                    Token::Dhss => self.dh(Token::Dhss, true, true)?,
                 }
             }
         }
-        self.symmetricstate.decrypt_and_mix_hash(ptr, payload).map_err(|_| ErrorKind::Decrypt)?;
+        if self.symmetricstate.has_key() && ptr.len() < TAGLEN {
+            bail!(ErrorKind::Input(InputProblem::Truncated(MessagePart::Payload { message: index })));
+        }
+        self.symmetricstate.decrypt_and_mix_hash(ptr, payload)
+            .map_err(|_| ErrorKind::Decrypt(MessagePart::Payload { message: index }))?;
         self.my_turn = true;
         if last {
             self.symmetricstate.split(&mut self.cipherstates.0, &mut self.cipherstates.1);
@@ -284,18 +445,42 @@ impl HandshakeState {
         Ok(payload_len)
     }
 
-    pub fn finish(self) -> Result<(CipherStates, HandshakeChoice)> {
+    /// Move into transport mode once the handshake is finished.
+    pub fn finish(self) -> Result<TransportState<C, R>> {
         if self.is_finished() {
-            Ok((self.cipherstates, self.params.handshake))
+            let dh_len = self.dh_len();
+            let framing = match self.bolt8 {
+                Some(hasher) => Some(Framing::new(hasher, self.symmetricstate.chaining_key(), self.initiator)),
+                None         => None,
+            };
+            Ok(TransportState::new(self.cipherstates,
+                                   self.params.handshake.pattern,
+                                   self.initiator,
+                                   self.rng,
+                                   self.padding,
+                                   framing,
+                                   self.rs,
+                                   dh_len))
         } else {
             bail!(ErrorKind::State(StateProblem::HandshakeNotFinished));
         }
     }
 
+    /// The remote party's static public key, if it has been received or was provided.
+    pub fn get_remote_static(&self) -> Option<&[u8]> {
+        if self.rs.is_on() {
+            Some(&self.rs[..self.dh_len()])
+        } else {
+            None
+        }
+    }
+
+    /// Whether this is the initiator's side of the handshake.
     pub fn is_initiator(&self) -> bool {
         self.initiator
     }
 
+    /// Whether every handshake message has been written or read.
     pub fn is_finished(&self) -> bool {
         self.message_patterns.is_empty()
     }
@@ -0,0 +1,72 @@
+//! Byte strings serialized as hex, shared by the test vector and replay formats.
+
+use hex::{FromHex, ToHex};
+use serde::de::{self, Deserialize, Deserializer, Visitor, Unexpected};
+use serde::ser::{Serialize, Serializer};
+use std::fmt;
+use std::ops::Deref;
+
+/// A byte string, serialized as hex. The original string is kept so it can be echoed back as-is.
+#[derive(Clone)]
+pub struct HexBytes {
+    original: String,
+    payload: Vec<u8>,
+}
+
+impl From<Vec<u8>> for HexBytes {
+    fn from(payload: Vec<u8>) -> Self {
+        Self {
+            original: payload.to_hex(),
+            payload: payload,
+        }
+    }
+}
+
+impl Deref for HexBytes {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.payload
+    }
+}
+
+impl fmt::Debug for HexBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.original)
+    }
+}
+
+struct HexBytesVisitor;
+impl<'de> Visitor<'de> for HexBytesVisitor {
+    type Value = HexBytes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a hex string")
+    }
+
+    fn visit_str<E>(self, s: &str) -> ::std::result::Result<Self::Value, E>
+        where E: de::Error
+    {
+        let bytes = Vec::<u8>::from_hex(s).map_err(|_| de::Error::invalid_value(Unexpected::Str(s), &self))?;
+        Ok(HexBytes {
+            original: s.to_owned(),
+            payload: bytes,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for HexBytes {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<HexBytes, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserializer.deserialize_str(HexBytesVisitor)
+    }
+}
+
+impl Serialize for HexBytes {
+    fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
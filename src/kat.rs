@@ -0,0 +1,255 @@
+//! Known-answer tests for the primitives behind the standard Noise choices, used to check
+//! resolvers other than the default one.
+//!
+//! The answers come from RFC 7748 (X25519), BOLT 8 (secp256k1), NIST's CAVP (P-256), RFC 4231 (HMAC-SHA2), RFC 7693 (BLAKE2), and
+//! independent implementations for the remaining HMAC/HKDF and AEAD cases.
+
+use constants::{MAXDHLEN, MAXHASHLEN, MAXMSGLEN, TAGLEN};
+use params::{CipherChoice, DHChoice, HashChoice};
+use session::Session;
+#[cfg(feature = "std")] use std::panic::{self, AssertUnwindSafe};
+use std::prelude::v1::*;
+use types::{Cipher, Dh, Hash};
+
+/// The standard DH choices, in the order they're tested.
+pub const DHS: [DHChoice; 4] = [DHChoice::Curve25519, DHChoice::Ed448, DHChoice::Secp256k1, DHChoice::P256];
+/// The standard hash choices, in the order they're tested.
+pub const HASHES: [HashChoice; 4] = [HashChoice::SHA256, HashChoice::SHA512, HashChoice::Blake2s, HashChoice::Blake2b];
+/// The standard cipher choices, in the order they're tested.
+pub const CIPHERS: [CipherChoice; 2] = [CipherChoice::ChaChaPoly, CipherChoice::AESGCM];
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len() / 2).map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap()).collect()
+}
+
+fn check(name: &str, actual: &[u8], expected: &str) -> Result<(), String> {
+    let expected = from_hex(expected);
+    if actual == &expected[..] {
+        Ok(())
+    } else {
+        Err(format!("{} doesn't match the known answer", name))
+    }
+}
+
+/// The name of `choice` in protocol names.
+pub fn dh_name(choice: &DHChoice) -> &str {
+    match *choice {
+        DHChoice::Curve25519       => "25519",
+        DHChoice::Ed448            => "448",
+        DHChoice::Secp256k1        => "secp256k1",
+        DHChoice::P256             => "P256",
+        DHChoice::Custom(ref name) => name,
+    }
+}
+
+/// The name of `choice` in protocol names.
+pub fn hash_name(choice: &HashChoice) -> &str {
+    match *choice {
+        HashChoice::SHA256           => "SHA256",
+        HashChoice::SHA512           => "SHA512",
+        HashChoice::Blake2s          => "BLAKE2s",
+        HashChoice::Blake2b          => "BLAKE2b",
+        HashChoice::Custom(ref name) => name,
+    }
+}
+
+/// The name of `choice` in protocol names.
+pub fn cipher_name(choice: &CipherChoice) -> &str {
+    match *choice {
+        CipherChoice::ChaChaPoly       => "ChaChaPoly",
+        CipherChoice::AESGCM           => "AESGCM",
+        CipherChoice::Custom(ref name) => name,
+    }
+}
+
+/// Run `f`, turning a panic into an error so a broken implementation can't take the caller down.
+#[cfg(feature = "std")]
+pub fn guard<T, F: FnOnce() -> Result<T, String>>(f: F) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| Err("panicked".to_owned()))
+}
+
+/// Without `std` panics can't be caught, so `f` is just run.
+#[cfg(not(feature = "std"))]
+pub fn guard<T, F: FnOnce() -> Result<T, String>>(f: F) -> Result<T, String> {
+    f()
+}
+
+/// Send `payload` from `send` to `recv`, checking it arrives intact, and return the message.
+pub fn exchange(send: &mut Session, recv: &mut Session, payload: &[u8]) -> Result<Vec<u8>, String> {
+    let (mut message, mut out) = (vec![0u8; MAXMSGLEN], vec![0u8; MAXMSGLEN]);
+    let len = send.write_message(payload, &mut message).map_err(|e| format!("writing failed: {}", e))?;
+    let out_len = recv.read_message(&message[..len], &mut out).map_err(|e| format!("reading failed: {}", e))?;
+    if out[..out_len] != payload[..] {
+        return Err("payload didn't survive the round trip".to_owned());
+    }
+    message.truncate(len);
+    Ok(message)
+}
+
+/// Check `dh` against the known answers for `choice`, or return `None` if there aren't any.
+pub fn check_dh(choice: &DHChoice, dh: &mut Dh) -> Option<Result<(), String>> {
+    match *choice {
+        DHChoice::Curve25519 => Some(guard(|| {
+            if dh.pub_len() != 32 || dh.priv_len() != 32 {
+                return Err("key lengths are wrong".to_owned());
+            }
+            dh.set(&from_hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a"));
+            check("public key", dh.pubkey(), "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")?;
+            let mut out = [0u8; MAXDHLEN];
+            dh.dh(&from_hex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"), &mut out)
+                .map_err(|_| "a valid public key was rejected".to_owned())?;
+            check("shared secret", &out[..32], "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742")
+        })),
+        DHChoice::Secp256k1 => Some(guard(|| {
+            if dh.pub_len() != 33 || dh.priv_len() != 32 || dh.dh_len() != 32 {
+                return Err("key lengths are wrong".to_owned());
+            }
+            dh.set(&[0x12u8; 32]);
+            check("public key", dh.pubkey(), "036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f7")?;
+            let mut out = [0u8; MAXDHLEN];
+            dh.dh(&from_hex("028d7500dd4c12685d1f568b4c2b5048e8534b873319f3a8daa612b469132ec7f7"), &mut out)
+                .map_err(|_| "a valid public key was rejected".to_owned())?;
+            check("shared secret", &out[..32], "1e2fb3c8fe8fb9f262f649f64d26ecf0f2c0a805a767cf02dc2d77a6ef1fdcc3")
+        })),
+        DHChoice::P256 => Some(guard(|| {
+            if dh.pub_len() != 33 || dh.priv_len() != 32 || dh.dh_len() != 32 {
+                return Err("key lengths are wrong".to_owned());
+            }
+            dh.set(&from_hex("7d7dc5f71eb29ddaf80d6214632eeae03d9058af1fb6d22ed80badb62bc1a534"));
+            check("public key", dh.pubkey(), "03ead218590119e8876b29146ff89ca61770c4edbbf97d38ce385ed281d8a6b230")?;
+            let mut out = [0u8; MAXDHLEN];
+            dh.dh(&from_hex("02700c48f77f56584c5cc632ca65640db91b6bacce3a4df6b42ce7cc838833d287"), &mut out)
+                .map_err(|_| "a valid public key was rejected".to_owned())?;
+            check("shared secret", &out[..32], "46fc62106420ff012e54a434fbdd2d25ccc5852060561e68040dd7778997bd7b")
+        })),
+        DHChoice::Ed448 | DHChoice::Custom(_) => None,
+    }
+}
+
+/// Check `hash`, including its `hmac` and `hkdf`, against the known answers for `choice`.
+pub fn check_hash(choice: &HashChoice, hash: &mut Hash) -> Result<(), String> {
+    let (block_len, digest, hmac, hkdf) = match *choice {
+        HashChoice::SHA256 => (64,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ["95db16190b27b09d636e5373d0912c9773cead4617a75af0593c31603548c86c",
+             "9dc53d3efb747e5ab4fc2dd2021b7d777bba8bb27b006b3e57c880ca4d7dfe06",
+             "455048e4c535cfa77b202c3e6bc902c9a0c2ec2bd8ec07db467a064ca97ad27e"]),
+        HashChoice::SHA512 => (128,
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            "fa73b0089d56a284efb0f0756c890be9b1b5dbdd8ee81a3655f83e33b2279d39\
+             bf3e848279a722c806b485a47e67c807b946a337bee8942674278859e13292fb",
+            ["fe046f0f66fc50ac6ea3a31bc0fb9d9313738ef0c693fb158555c533de95653a\
+              6d78e5255e9fdd74fe158e334b91926d498f08ebd239d5c93d2bcf66eba0cffa",
+             "13e8eacd001d298bcf2666567ee273016cddcbcc5362293f43bbdaa94a4d8cb9\
+              e5c60dcf60a68e2597386de42d9d79ddb7f8b43da2ac32064079d0d8d201a741",
+             "6024dbf7a708282cbcee12bf71e9f777d5e5dac7e24c77ac5ccccb3d376fda5c\
+              750228d6b022a8a4ea56e592cf922353a955a6901f9c476103416bef78bc3acc"]),
+        HashChoice::Blake2s => (64,
+            "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982",
+            "fcc4f59529502e34c3d8da3ffdab82966a2cb637ff5e9bd701135c2e9469e790",
+            ["82cf9f18b3be3d2ccebaf2b1d0c8ec96532a29989e4371eb2a485066e041f8f2",
+             "19e895d4a1a5a245ffd9e728b406d447c868a6da315f1f7f686b25a6f0a81c2b",
+             "74c023807eb6fe1dd3c5b486d7a64d1b74e87449f53ecb199f6662f7b5d9b633"]),
+        HashChoice::Blake2b => (128,
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923",
+            "f43bc62c7a99353c3b2c60e8ef24fbbd42e9547866dc9c5be4edc6f4a7d4bc0a\
+             c620c2c60034d040f0dbaf86f9e9cd7891a095595eed55e2a996215f0c15c018",
+            ["351669c054c4349d00abfaa0d9eeb89abb2ac562b061acd3d90a7a345341625b\
+              c8bff0e8f99d22d6feedd55a98b80d6017196e74ff4caaa551808a8e0f105c66",
+             "addd7d00fb71b715dd1bc1babae38712ede4135c8cc5f014416bb0c2660a18f7\
+              5fd4f5cd11e8b605f96b425ebea251be71872247648774e68e2781da6116c1ff",
+             "01ea01d9c02426fc842e4b9d603a726bd29dc67a006f17e8851b91590a2336c0\
+              9732899d92c18dde944b30c9f69eaf02693a94bd0591f8b80216647b35e68030"]),
+        HashChoice::Custom(ref name) => return Err(format!("no known answers for {}", name)),
+    };
+    let hash_len = digest.len() / 2;
+
+    guard(|| {
+        if hash.hash_len() != hash_len || hash.block_len() != block_len {
+            return Err("block or hash length is wrong".to_owned());
+        }
+        let mut out = [0u8; MAXHASHLEN];
+        hash.reset();
+        hash.input(b"a");
+        hash.input(b"bc");
+        hash.result(&mut out);
+        check("digest", &out[..hash_len], digest)?;
+
+        hash.hmac(&[0xaa; 20], &[0xdd; 50], &mut out);
+        check("hmac", &out[..hash_len], hmac)?;
+
+        let chaining_key: Vec<u8> = (0..hash_len as u8).collect();
+        let (mut out1, mut out2, mut out3) = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
+        hash.hkdf(&chaining_key, &[0x0b; 22], 3, &mut out1, &mut out2, &mut out3);
+        check("hkdf output 1", &out1[..hash_len], hkdf[0])?;
+        check("hkdf output 2", &out2[..hash_len], hkdf[1])?;
+        check("hkdf output 3", &out3[..hash_len], hkdf[2])
+    })
+}
+
+/// Check `cipher` against the known answers for `choice`, including that tampering is detected.
+pub fn check_cipher(choice: &CipherChoice, cipher: &mut Cipher) -> Result<(), String> {
+    let expected = match *choice {
+        CipherChoice::ChaChaPoly => "a567e168539d13ea09162c4193ff7eba54f2da619b986475d9ba5c91829bf768\
+                                     7848b675ab9d8a39dd77264e7e76f9f66c184d85fdde1ab6b925e4",
+        CipherChoice::AESGCM     => "b405763f66623799af7f8d5d5c23df441d6fb8a601104896b6caea97f32d9fe9\
+                                     a4d233d719a1a305c33cfde78c30d34ae2127d42a24bcb200ff124",
+        CipherChoice::Custom(ref name) => return Err(format!("no known answers for {}", name)),
+    };
+    let key: Vec<u8> = (0..32).collect();
+    let authtext = b"snow conformance";
+    let plaintext = b"The quick brown fox jumps over the lazy dog";
+
+    guard(|| {
+        let mut ciphertext = [0u8; 64 + TAGLEN];
+        let mut out = [0u8; 64];
+        cipher.set(&key);
+        let len = cipher.encrypt(7, authtext, plaintext, &mut ciphertext);
+        check("ciphertext", &ciphertext[..len], expected)?;
+
+        match cipher.decrypt(7, authtext, &ciphertext[..len], &mut out) {
+            Ok(n) if out[..n] == plaintext[..] => {},
+            _ => return Err("decryption of the known answer failed".to_owned()),
+        }
+        if cipher.decrypt(8, authtext, &ciphertext[..len], &mut out).is_ok() {
+            return Err("decryption succeeded with the wrong nonce".to_owned());
+        }
+        if cipher.decrypt(7, b"", &ciphertext[..len], &mut out).is_ok() {
+            return Err("decryption succeeded with the wrong authtext".to_owned());
+        }
+        ciphertext[len - 1] ^= 1;
+        if cipher.decrypt(7, authtext, &ciphertext[..len], &mut out).is_ok() {
+            return Err("decryption succeeded with a tampered tag".to_owned());
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noise::{CryptoResolver, DefaultResolver};
+
+    #[test]
+    fn test_default_resolver_passes() {
+        let dh = DHChoice::Curve25519;
+        assert_eq!(check_dh(&dh, &mut *DefaultResolver.resolve_dh(&dh).unwrap()), Some(Ok(())));
+        for hash in &[HashChoice::SHA256, HashChoice::SHA512, HashChoice::Blake2s, HashChoice::Blake2b] {
+            assert_eq!(check_hash(hash, &mut *DefaultResolver.resolve_hash(hash).unwrap()), Ok(()));
+        }
+        for cipher in &[CipherChoice::ChaChaPoly, CipherChoice::AESGCM] {
+            assert_eq!(check_cipher(cipher, &mut *DefaultResolver.resolve_cipher(cipher).unwrap()), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_mismatched_primitive_fails() {
+        let mut sha512 = DefaultResolver.resolve_hash(&HashChoice::SHA512).unwrap();
+        assert!(check_hash(&HashChoice::Blake2b, &mut *sha512).is_err());
+        let mut aes = DefaultResolver.resolve_cipher(&CipherChoice::AESGCM).unwrap();
+        assert!(check_cipher(&CipherChoice::ChaChaPoly, &mut *aes).is_err());
+    }
+}
@@ -27,15 +27,52 @@
 //! let noise = noise.into_transport_mode();
 //!
 //! ```
+//!
+//! # `no_std`
+//!
+//! With `default-features = false` the crate is `#![no_std]` and only needs `alloc`. The state
+//! machines, `NoiseBuilder::with_resolver` and `self_test` are available, but the default
+//! resolver, `NoiseBuilder::new` and the `sealed`, `envelope` and `stream` modules need the `std`
+//! feature, so you must supply a `CryptoResolver` (including its RNG).
 
 #![cfg_attr(feature = "nightly", feature(try_from))]
+#![cfg_attr(not(feature = "std"), no_std)]
 #![recursion_limit = "1024"]
 
+#[cfg(not(feature = "std"))] #[macro_use] extern crate alloc;
 extern crate arrayvec;
 extern crate byteorder;
-#[macro_use] extern crate static_slice;
-#[macro_use] extern crate error_chain;
+#[cfg(feature = "std")] #[macro_use] extern crate error_chain;
 #[cfg(feature = "ring-resolver")] extern crate ring;
+#[cfg(feature = "ring-resolver")] extern crate untrusted;
+#[cfg(all(feature = "secure-memory", unix))] extern crate libc;
+#[cfg(any(feature = "vectors", feature = "replay"))] extern crate hex;
+#[cfg(any(feature = "vectors", feature = "replay"))] extern crate serde;
+#[cfg(any(feature = "vectors", feature = "replay"))] #[macro_use] extern crate serde_derive;
+#[cfg(any(feature = "vectors", feature = "replay"))] extern crate serde_json;
+
+/// Stands in for `std` without the `std` feature, so the rest of the crate can keep using
+/// `std::` paths and `std::prelude::v1::*` for the `alloc` types.
+#[cfg(not(feature = "std"))]
+mod std {
+    pub use core::*;
+
+    pub mod prelude {
+        pub mod v1 {
+            pub use alloc::borrow::ToOwned;
+            pub use alloc::boxed::Box;
+            pub use alloc::string::{String, ToString};
+            pub use alloc::vec::Vec;
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+macro_rules! bail {
+    ($e:expr) => {
+        return Err($e.into())
+    };
+}
 
 mod error;
 mod constants;
@@ -46,14 +83,37 @@ mod handshakestate;
 mod noise;
 mod session;
 mod transportstate;
+mod padding;
+mod negotiation;
+mod resolvers;
+#[cfg(any(feature = "vectors", feature = "replay"))] mod hexbytes;
+mod kat;
+mod selftest;
+mod secmem;
 
+pub mod bolt8;
+#[cfg(feature = "std")] pub mod envelope;
 pub mod params;
+#[cfg(feature = "replay")] pub mod replay;
+#[cfg(feature = "std")] pub mod sealed;
+#[cfg(feature = "std")] pub mod stream;
+pub mod trace;
+#[cfg(feature = "testing")] pub mod testing;
 pub mod types;
-pub mod wrappers;
+#[cfg(feature = "vectors")] pub mod vectors;
+#[cfg(feature = "std")] pub mod wrappers;
 
 pub use error::*;
-pub use noise::{CryptoResolver, DefaultResolver};
+pub use noise::CryptoResolver;
+#[cfg(feature = "std")] pub use noise::DefaultResolver;
 pub use noise::NoiseBuilder;
+pub use resolvers::{FallbackResolver, FipsResolver, ResolverBuilder};
+pub use padding::PaddingPolicy;
+pub use negotiation::{NegotiatingBuilder, ProtocolHint, HINT_LEN};
 pub use session::Session;
+pub use handshakestate::HandshakeState;
+pub use transportstate::TransportState;
+pub use selftest::{self_test, SelfTestReport};
 
 #[cfg(feature = "ring-resolver")] pub use wrappers::ring_wrapper::RingAcceleratedResolver;
+#[cfg(feature = "rustcrypto-resolver")] pub use wrappers::rustcrypto_wrapper::RustCryptoResolver;
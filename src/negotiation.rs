@@ -0,0 +1,375 @@
+//! Responder-side negotiation between several acceptable protocols.
+//!
+//! The initiator picks one of the protocols it knows the responder accepts, and sends a compact
+//! `ProtocolHint` describing its choice ahead of the first handshake message. The responder uses
+//! `NegotiatingBuilder` to find the matching `NoiseParams` and build its `Session`.
+//!
+//! Both sides mix the hint into the prologue, so if it's tampered with in transit (for example to
+//! downgrade to a weaker suite) the handshake fails.
+
+use error::{ErrorKind, Result, InitStage, InputProblem, BuilderField};
+use noise::{CryptoResolver, NoiseBuilder};
+#[cfg(feature = "std")] use noise::default_resolver;
+use padding::PaddingPolicy;
+use params::*;
+use session::Session;
+use trace::Tracer;
+use types::StaticKeyProvider;
+use std::prelude::v1::*;
+
+/// The length in bytes of an encoded `ProtocolHint`.
+pub const HINT_LEN: usize = 6;
+
+/// How every `Custom` primitive choice is encoded in a hint.
+const CUSTOM: u8 = 0xff;
+
+/// A compact description of the protocol an initiator chose, to be sent to a negotiating responder.
+///
+/// The hint encodes the handshake pattern, its modifiers, and the DH, cipher and hash choices.
+/// Custom choices aren't distinguished from each other, so two protocols that differ only in
+/// custom primitives have the same hint. `NegotiatingBuilder` refuses to negotiate between such
+/// protocols rather than guess.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct ProtocolHint {
+    bytes: [u8; HINT_LEN],
+}
+
+impl ProtocolHint {
+    /// Create the hint describing `params`.
+    pub fn new(params: &NoiseParams) -> Self {
+        use params::HandshakePattern::*;
+        let pattern = match params.handshake.pattern {
+            N  => 0,  X  => 1,  K  => 2,
+            NN => 3,  NK => 4,  NX => 5,
+            XN => 6,  XK => 7,  XX => 8,
+            KN => 9,  KK => 10, KX => 11,
+            IN => 12, IK => 13, IX => 14,
+        };
+        let dh = match params.dh {
+            DHChoice::Curve25519 => 0,
+            DHChoice::Ed448      => 1,
+            DHChoice::Secp256k1  => 2,
+            DHChoice::P256       => 3,
+            DHChoice::Custom(_)  => CUSTOM,
+        };
+        let cipher = match params.cipher {
+            CipherChoice::ChaChaPoly => 0,
+            CipherChoice::AESGCM     => 1,
+            CipherChoice::Custom(_)  => CUSTOM,
+        };
+        let hash = match params.hash {
+            HashChoice::SHA256    => 0,
+            HashChoice::SHA512    => 1,
+            HashChoice::Blake2s   => 2,
+            HashChoice::Blake2b   => 3,
+            HashChoice::Custom(_) => CUSTOM,
+        };
+        let mut modifiers = 0u16;
+        for modifier in &params.handshake.modifiers.list {
+            match *modifier {
+                // PSK positions are bounded by the message count, well within the low 15 bits.
+                HandshakeModifier::Psk(n) => modifiers |= 1 << (n % 15),
+                HandshakeModifier::Fallback => modifiers |= 1 << 15,
+            }
+        }
+        ProtocolHint {
+            bytes: [pattern, dh, cipher, hash, (modifiers >> 8) as u8, modifiers as u8],
+        }
+    }
+
+    /// Read a hint received from the initiator.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `ErrorKind::Input(InputProblem::Malformed(_))` if `bytes` isn't exactly
+    /// `HINT_LEN` long.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != HINT_LEN {
+            bail!(ErrorKind::Input(InputProblem::Malformed("protocol hint")));
+        }
+        let mut hint = ProtocolHint { bytes: [0u8; HINT_LEN] };
+        hint.bytes.copy_from_slice(bytes);
+        Ok(hint)
+    }
+
+    /// The encoded hint, to be sent to the responder.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Whether this hint describes `params`.
+    pub fn matches(&self, params: &NoiseParams) -> bool {
+        *self == ProtocolHint::new(params)
+    }
+
+    /// The bytes appended to the prologue by both parties.
+    pub fn prologue_tag(&self) -> Vec<u8> {
+        let mut tag = b"snow-negotiation:".to_vec();
+        tag.extend_from_slice(&self.bytes);
+        tag
+    }
+}
+
+/// Builds a responder `Session` for whichever of several acceptable protocols the initiator chose.
+///
+/// # Examples
+///
+/// ```
+/// # use snow::{NoiseBuilder, NegotiatingBuilder, ProtocolHint};
+/// let chacha = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+/// let aes    = "Noise_NN_25519_AESGCM_SHA256".parse().unwrap();
+///
+/// // The initiator chooses, and sends `hint.as_bytes()` ahead of its first message.
+/// let hint = ProtocolHint::new(&aes);
+/// let initiator = NoiseBuilder::new(aes.clone())
+///                              .protocol_hint(hint)
+///                              .build_initiator()
+///                              .unwrap();
+///
+/// // The responder accepts either.
+/// let received = ProtocolHint::from_bytes(hint.as_bytes()).unwrap();
+/// let responder = NegotiatingBuilder::new(vec![chacha, aes])
+///                                    .build_responder(&received)
+///                                    .unwrap();
+/// ```
+pub struct NegotiatingBuilder<'builder> {
+    accepted: Vec<NoiseParams>,
+    resolver: Box<CryptoResolver + 'builder>,
+    s:        Option<&'builder [u8]>,
+    s_provider: Option<Box<StaticKeyProvider>>,
+    e_fixed:  Option<&'builder [u8]>,
+    rs:       Option<&'builder [u8]>,
+    psks:     [Option<&'builder [u8]>; 10],
+    bad_psk:  Option<u8>,
+    plog:     Option<&'builder [u8]>,
+    padding:  Option<PaddingPolicy>,
+    tracer:   Option<Box<Tracer>>,
+    allow_low_order: bool,
+}
+
+impl<'builder> NegotiatingBuilder<'builder> {
+    /// Create a NegotiatingBuilder accepting any of `accepted`, with the default crypto resolver.
+    ///
+    /// Accepted protocols that differ only in custom primitives share a hint, so
+    /// `build_responder` refuses lists containing them.
+    #[cfg(feature = "std")]
+    pub fn new(accepted: Vec<NoiseParams>) -> Self {
+        Self::with_resolver(accepted, default_resolver())
+    }
+
+    /// Create a NegotiatingBuilder accepting any of `accepted`, with a custom crypto resolver.
+    pub fn with_resolver(accepted: Vec<NoiseParams>, resolver: Box<CryptoResolver + 'builder>) -> Self {
+        NegotiatingBuilder {
+            accepted: accepted,
+            resolver: resolver,
+            s: None,
+            s_provider: None,
+            e_fixed: None,
+            rs: None,
+            psks: [None; 10],
+            bad_psk: None,
+            plog: None,
+            padding: None,
+            tracer: None,
+            allow_low_order: false,
+        }
+    }
+
+    /// Specify a PSK, used if the chosen protocol has the corresponding `pskN` modifier.
+    ///
+    /// Locations past the last supported one are reported when the session is built.
+    pub fn psk(mut self, location: u8, key: &'builder [u8]) -> Self {
+        match self.psks.get_mut(location as usize) {
+            Some(psk) => *psk = Some(key),
+            None      => self.bad_psk = Some(location),
+        }
+        self
+    }
+
+    /// Your static private key, used if the chosen protocol needs one.
+    pub fn local_private_key(mut self, key: &'builder [u8]) -> Self {
+        self.s = Some(key);
+        self
+    }
+
+    /// Use the static key behind `provider`, used if the chosen protocol needs one. See
+    /// [`NoiseBuilder::static_key_provider`](struct.NoiseBuilder.html#method.static_key_provider).
+    pub fn static_key_provider(mut self, provider: Box<StaticKeyProvider>) -> Self {
+        self.s_provider = Some(provider);
+        self
+    }
+
+    /// Use `key` as your ephemeral private key, for reproducible tests. See
+    /// [`NoiseBuilder::fixed_ephemeral_key`](struct.NoiseBuilder.html#method.fixed_ephemeral_key).
+    #[cfg(feature = "testing")]
+    pub fn fixed_ephemeral_key(mut self, key: &'builder [u8]) -> Self {
+        self.e_fixed = Some(key);
+        self
+    }
+
+    /// Arbitrary data to be hashed in to the handshake hash value, ahead of the protocol hint.
+    pub fn prologue(mut self, key: &'builder [u8]) -> Self {
+        self.plog = Some(key);
+        self
+    }
+
+    /// The initiator's static public key, used if the chosen protocol needs one.
+    pub fn remote_public_key(mut self, pub_key: &'builder [u8]) -> Self {
+        self.rs = Some(pub_key);
+        self
+    }
+
+    /// Pad transport messages according to `policy`, whichever protocol is chosen.
+    pub fn padding(mut self, policy: PaddingPolicy) -> Self {
+        self.padding = Some(policy);
+        self
+    }
+
+    /// Report every step of the handshake to `tracer`. See
+    /// [`NoiseBuilder::tracer`](struct.NoiseBuilder.html#method.tracer).
+    pub fn tracer(mut self, tracer: Box<Tracer>) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Accept low-order public keys and all-zero DH results. See
+    /// [`NoiseBuilder::allow_low_order_points`](struct.NoiseBuilder.html#method.allow_low_order_points).
+    pub fn allow_low_order_points(mut self) -> Self {
+        self.allow_low_order = true;
+        self
+    }
+
+    /// The accepted protocol that `hint` refers to, if any.
+    pub fn select(&self, hint: &ProtocolHint) -> Option<&NoiseParams> {
+        self.accepted.iter().find(|params| hint.matches(params))
+    }
+
+    /// Whether two different accepted protocols have the same hint, so a hint can't say which
+    /// one the initiator chose.
+    fn ambiguous(&self) -> bool {
+        self.accepted.iter().enumerate().any(|(i, a)| {
+            self.accepted[..i].iter().any(|b| a.name != b.name && ProtocolHint::new(a) == ProtocolHint::new(b))
+        })
+    }
+
+    /// Build a responder NoiseSession for the protocol the initiator chose.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `ErrorKind::Init(InitStage::ValidateField(BuilderField::ProtocolHint))` if
+    /// two of the accepted protocols have the same hint (which happens when they differ only in
+    /// custom primitives), `ErrorKind::Init(InitStage::ValidateField(BuilderField::Psk(n)))` if
+    /// a PSK was given for an unsupported location, and
+    /// `ErrorKind::Init(InitStage::NegotiateParams)` if `hint` doesn't match any of the accepted
+    /// protocols.
+    pub fn build_responder(self, hint: &ProtocolHint) -> Result<Session> {
+        if self.ambiguous() {
+            bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::ProtocolHint)));
+        }
+        if let Some(location) = self.bad_psk {
+            bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::Psk(location))));
+        }
+        let params = self.select(hint).cloned().ok_or(ErrorKind::Init(InitStage::NegotiateParams))?;
+        let mut builder = NoiseBuilder::with_resolver(params.clone(), self.resolver);
+
+        for modifier in &params.handshake.modifiers.list {
+            if let HandshakeModifier::Psk(n) = *modifier {
+                if let Some(&Some(key)) = self.psks.get(n as usize) {
+                    builder = builder.psk(n, key);
+                }
+            }
+        }
+        if let Some(key) = self.s {
+            if params.handshake.pattern.needs_local_static_key(false) {
+                builder = builder.local_private_key(key);
+            }
+        }
+        if let Some(provider) = self.s_provider {
+            if params.handshake.pattern.needs_local_static_key(false) {
+                builder = builder.static_key_provider(provider);
+            }
+        }
+        if let Some(key) = self.rs {
+            if params.handshake.pattern.need_known_remote_pubkey(false) {
+                builder = builder.remote_public_key(key);
+            }
+        }
+        if let Some(key) = self.e_fixed {
+            builder = builder.fixed_ephemeral_key_for_testing_only(key);
+        }
+        if let Some(plog) = self.plog {
+            builder = builder.prologue(plog);
+        }
+        if let Some(policy) = self.padding {
+            builder = builder.padding(policy);
+        }
+        if let Some(tracer) = self.tracer {
+            builder = builder.tracer(tracer);
+        }
+        if self.allow_low_order {
+            builder = builder.allow_low_order_points();
+        }
+
+        builder.protocol_hint(*hint).build_responder()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Error;
+
+    #[test]
+    fn test_hint_distinguishes_suites() {
+        let a: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let b: NoiseParams = "Noise_XX_25519_AESGCM_SHA256".parse().unwrap();
+        let c: NoiseParams = "Noise_XXpsk3_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        assert!(ProtocolHint::new(&a).matches(&a));
+        assert!(!ProtocolHint::new(&a).matches(&b));
+        assert!(!ProtocolHint::new(&a).matches(&c));
+    }
+
+    #[test]
+    fn test_hint_roundtrip() {
+        let p: NoiseParams = "Noise_IKpsk2_25519_AESGCM_BLAKE2b".parse().unwrap();
+        let hint = ProtocolHint::new(&p);
+        assert_eq!(ProtocolHint::from_bytes(hint.as_bytes()).unwrap(), hint);
+        assert!(ProtocolHint::from_bytes(&hint.as_bytes()[1..]).is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let a: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let b: NoiseParams = "Noise_NN_25519_AESGCM_SHA256".parse().unwrap();
+        let c: NoiseParams = "Noise_NN_25519_AESGCM_SHA512".parse().unwrap();
+        let builder = NegotiatingBuilder::new(vec![a, b.clone()]);
+        assert_eq!(builder.select(&ProtocolHint::new(&b)), Some(&b));
+        assert!(builder.select(&ProtocolHint::new(&c)).is_none());
+    }
+
+    #[test]
+    fn test_colliding_custom_hints_refused() {
+        let a: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA3".parse().unwrap();
+        let b: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE3".parse().unwrap();
+        assert_eq!(ProtocolHint::new(&a), ProtocolHint::new(&b));
+        let builder = NegotiatingBuilder::new(vec![a.clone(), b]);
+        match builder.build_responder(&ProtocolHint::new(&a)) {
+            Err(Error(ErrorKind::Init(InitStage::ValidateField(BuilderField::ProtocolHint)), _)) => {},
+            _ => panic!("colliding hints weren't refused"),
+        }
+
+        // Listing the same protocol twice is harmless.
+        let builder = NegotiatingBuilder::new(vec![a.clone(), a.clone()]);
+        assert!(!builder.ambiguous());
+    }
+
+    #[test]
+    fn test_psk_past_last_location() {
+        let a: NoiseParams = "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let builder = NegotiatingBuilder::new(vec![a.clone()]).psk(10, &[0u8; 32]);
+        match builder.build_responder(&ProtocolHint::new(&a)) {
+            Err(Error(ErrorKind::Init(InitStage::ValidateField(BuilderField::Psk(10))), _)) => {},
+            _ => panic!("out-of-range PSK location wasn't reported"),
+        }
+    }
+}
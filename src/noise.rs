@@ -1,13 +1,19 @@
 use constants::*;
 use types::*;
 use handshakestate::*;
-use wrappers::rand_wrapper::*;
-use wrappers::crypto_wrapper::*;
+#[cfg(feature = "std")] use wrappers::rand_wrapper::*;
+#[cfg(feature = "std")] use wrappers::crypto_wrapper::*;
 use cipherstate::*;
 use session::*;
 use utils::*;
 use params::*;
-use error::{ErrorKind, Result, InitStage, Prerequisite};
+use padding::PaddingPolicy;
+use secmem::SecretBuf;
+use negotiation::ProtocolHint;
+use error::{ErrorKind, Result, InitStage, BuilderField, Prerequisite};
+use trace::Tracer;
+use kat::{cipher_name, dh_name, hash_name};
+use std::prelude::v1::*;
 
 #[cfg(feature = "ring-resolver" )] use wrappers::ring_wrapper::RingAcceleratedResolver;
 
@@ -17,10 +23,19 @@ pub trait CryptoResolver {
     fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>>;
     fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>>;
     fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>>;
+
+    /// The name of a primitive in `params` that this resolver refuses by policy, rather than just
+    /// not supporting, so building fails with `InitStage::Disallowed`. Nothing is refused by
+    /// default.
+    fn disallowed(&self, _params: &NoiseParams) -> Option<String> {
+        None
+    }
 }
 
 /// The default pure-rust crypto implementation resolver.
+#[cfg(feature = "std")]
 pub struct DefaultResolver;
+#[cfg(feature = "std")]
 impl CryptoResolver for DefaultResolver {
     fn resolve_rng(&self) -> Option<Box<Random>> {
         Some(Box::new(RandomOs::default()))
@@ -39,6 +54,7 @@ impl CryptoResolver for DefaultResolver {
             HashChoice::SHA512  => Some(Box::new(HashSHA512::default())),
             HashChoice::Blake2s => Some(Box::new(HashBLAKE2s::default())),
             HashChoice::Blake2b => Some(Box::new(HashBLAKE2b::default())),
+            _                   => None,
         }
     }
 
@@ -46,10 +62,75 @@ impl CryptoResolver for DefaultResolver {
         match *choice {
             CipherChoice::ChaChaPoly => Some(Box::new(CipherChaChaPoly::default())),
             CipherChoice::AESGCM     => Some(Box::new(CipherAESGCM::default())),
+            _                        => None,
         }
     }
 }
 
+/// The resolver used by `NoiseBuilder::new`.
+#[cfg(all(feature = "std", not(feature = "ring-accelerated")))]
+pub fn default_resolver() -> Box<CryptoResolver> {
+    Box::new(DefaultResolver)
+}
+
+#[cfg(feature = "ring-accelerated")]
+pub fn default_resolver() -> Box<CryptoResolver> {
+    Box::new(RingAcceleratedResolver::new())
+}
+
+/// Append `tag` to the prologue tags, preceded by its 2-byte big-endian length.
+fn push_tag(tags: &mut Vec<u8>, tag: &[u8]) {
+    tags.push((tag.len() >> 8) as u8);
+    tags.push(tag.len() as u8);
+    tags.extend_from_slice(tag);
+}
+
+/// Lets a borrowed resolver be handed to a `NoiseBuilder`.
+impl<'a, R: CryptoResolver + ?Sized> CryptoResolver for &'a R {
+    fn resolve_rng(&self) -> Option<Box<Random>> {
+        (**self).resolve_rng()
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+        (**self).resolve_dh(choice)
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+        (**self).resolve_hash(choice)
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+        (**self).resolve_cipher(choice)
+    }
+
+    fn disallowed(&self, params: &NoiseParams) -> Option<String> {
+        (**self).disallowed(params)
+    }
+}
+
+/// Lets a boxed resolver be used where a resolver is expected, e.g. in a `FallbackResolver`.
+impl<R: CryptoResolver + ?Sized> CryptoResolver for Box<R> {
+    fn resolve_rng(&self) -> Option<Box<Random>> {
+        (**self).resolve_rng()
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+        (**self).resolve_dh(choice)
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+        (**self).resolve_hash(choice)
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+        (**self).resolve_cipher(choice)
+    }
+
+    fn disallowed(&self, params: &NoiseParams) -> Option<String> {
+        (**self).disallowed(params)
+    }
+}
+
 /// Generates a `NoiseSession` and also validate that all the prerequisites for
 /// the given parameters are satisfied.
 ///
@@ -68,43 +149,57 @@ impl CryptoResolver for DefaultResolver {
 /// ```
 pub struct NoiseBuilder<'builder> {
     params:   NoiseParams,
-    resolver: Box<CryptoResolver>,
+    resolver: Box<CryptoResolver + 'builder>,
     s:        Option<&'builder [u8]>,
+    s_provider: Option<Box<StaticKeyProvider>>,
     e_fixed:  Option<&'builder [u8]>,
     rs:       Option<&'builder [u8]>,
     psks:     [Option<&'builder [u8]>; 10],
+    bad_psk:  Option<u8>,
     plog:     Option<&'builder [u8]>,
+    padding:  Option<PaddingPolicy>,
+    bolt8:    bool,
+    hint:     Option<ProtocolHint>,
+    tracer:   Option<Box<Tracer>>,
+    allow_low_order: bool,
 }
 
 impl<'builder> NoiseBuilder<'builder> {
     /// Create a NoiseBuilder with the default crypto resolver.
-    #[cfg(not(feature = "ring-accelerated"))]
-    pub fn new(params: NoiseParams) -> Self {
-        Self::with_resolver(params, Box::new(DefaultResolver))
-    }
-
-    #[cfg(feature = "ring-accelerated")]
+    #[cfg(feature = "std")]
     pub fn new(params: NoiseParams) -> Self {
-        Self::with_resolver(params, Box::new(RingAcceleratedResolver::new()))
+        Self::with_resolver(params, default_resolver())
     }
 
     /// Create a NoiseBuilder with a custom crypto resolver.
-    pub fn with_resolver(params: NoiseParams, resolver: Box<CryptoResolver>) -> Self
+    pub fn with_resolver(params: NoiseParams, resolver: Box<CryptoResolver + 'builder>) -> Self
     {
         NoiseBuilder {
             params: params,
             resolver: resolver,
             s: None,
+            s_provider: None,
             e_fixed: None,
             rs: None,
             plog: None,
             psks: [None; 10],
+            bad_psk: None,
+            padding: None,
+            bolt8: false,
+            hint: None,
+            tracer: None,
+            allow_low_order: false,
         }
     }
 
     /// Specify a PSK (only used with `NoisePSK` base parameter)
+    ///
+    /// Locations past the last supported one are reported when the session is built.
     pub fn psk(mut self, location: u8, key: &'builder [u8]) -> Self {
-        self.psks[location as usize] = Some(key);
+        match self.psks.get_mut(location as usize) {
+            Some(psk) => *psk = Some(key),
+            None      => self.bad_psk = Some(location),
+        }
         self
     }
 
@@ -114,18 +209,70 @@ impl<'builder> NoiseBuilder<'builder> {
         self
     }
 
+    /// Use the static key behind `provider`, such as one held by an HSM or key agent, instead of a
+    /// private key given to [`local_private_key`](#method.local_private_key).
+    pub fn static_key_provider(mut self, provider: Box<StaticKeyProvider>) -> Self {
+        self.s_provider = Some(provider);
+        self
+    }
+
     #[doc(hidden)]
     pub fn fixed_ephemeral_key_for_testing_only(mut self, key: &'builder [u8]) -> Self {
         self.e_fixed = Some(key);
         self
     }
 
+    /// Use `key` as your ephemeral private key instead of generating one, for reproducible tests.
+    /// Reusing an ephemeral key destroys the handshake's security, so never do this outside tests.
+    #[cfg(feature = "testing")]
+    pub fn fixed_ephemeral_key(self, key: &'builder [u8]) -> Self {
+        self.fixed_ephemeral_key_for_testing_only(key)
+    }
+
     /// Arbitrary data to be hashed in to the handshake hash value.
     pub fn prologue(mut self, key: &'builder [u8]) -> Self {
         self.plog = Some(key);
         self
     }
 
+    /// Pad transport messages according to `policy` to hide payload lengths. The peer must be
+    /// built with the same policy, or the handshake will fail.
+    pub fn padding(mut self, policy: PaddingPolicy) -> Self {
+        self.padding = Some(policy);
+        self
+    }
+
+    /// Frame messages as Lightning does: a version byte before each handshake message, and an
+    /// encrypted length prefix before each transport message, with keys rotated every 1000 uses.
+    /// See the [`bolt8`](bolt8/index.html) module.
+    pub fn bolt8(mut self) -> Self {
+        self.bolt8 = true;
+        self
+    }
+
+    /// The hint sent to a responder using a [`NegotiatingBuilder`](struct.NegotiatingBuilder.html).
+    /// It's mixed into the prologue to protect against downgrades.
+    pub fn protocol_hint(mut self, hint: ProtocolHint) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    /// Report every step of the handshake to `tracer`, for debugging interop failures. See the
+    /// [`trace`](trace/index.html) module.
+    pub fn tracer(mut self, tracer: Box<Tracer>) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Accept low-order public keys and all-zero DH results, as the Noise spec permits, instead of
+    /// failing with `ErrorKind::Dh`. Only use this for protocols that deliberately rely on it.
+    ///
+    /// Public keys that aren't valid points (e.g. malformed secp256k1 keys) still fail.
+    pub fn allow_low_order_points(mut self) -> Self {
+        self.allow_low_order = true;
+        self
+    }
+
     /// The responder's static public key.
     pub fn remote_public_key(mut self, pub_key: &'builder [u8]) -> Self {
         self.rs = Some(pub_key);
@@ -136,6 +283,7 @@ impl<'builder> NoiseBuilder<'builder> {
     // TODO also inefficient because it creates a new RNG and DH instance just for this.
     /// Generate a new private key. It's up to the user of this library how to store this.
     pub fn generate_private_key(&self) -> Result<Vec<u8>> {
+        self.check_policy()?;
         let mut rng = self.resolver.resolve_rng()
             .ok_or(ErrorKind::Init(InitStage::GetRngImpl))?;
         let mut dh = self.resolver.resolve_dh(&self.params.dh)
@@ -156,43 +304,150 @@ impl<'builder> NoiseBuilder<'builder> {
         self.build(false)
     }
 
-    fn build(self, initiator: bool) -> Result<Session> {
-        if !self.s.is_some() && self.params.handshake.pattern.needs_local_static_key(initiator) {
+    /// Build the initiator's side as a `HandshakeState` over concrete primitive types, which are
+    /// called directly rather than through the resolver, with `rng` as its RNG.
+    ///
+    /// Nothing is allocated unless a tracer, padding policy, protocol hint or BOLT 8 framing was
+    /// given.
+    ///
+    /// # Errors
+    ///
+    /// As [`build_initiator`](#method.build_initiator), and `ErrorKind::Init` if the types don't
+    /// implement the primitives named in the `NoiseParams`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use snow::NoiseBuilder;
+    /// # use snow::wrappers::crypto_wrapper::{Dh25519, CipherChaChaPoly, HashBLAKE2s};
+    /// # use snow::wrappers::rand_wrapper::RandomOs;
+    /// let mut noise = NoiseBuilder::new("Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap())
+    ///     .build_static_initiator::<Dh25519, CipherChaChaPoly, HashBLAKE2s, _>(RandomOs::default())
+    ///     .unwrap();
+    ///
+    /// let mut buf = [0u8; 65535];
+    /// noise.write_handshake_message(&[], &mut buf).unwrap();
+    /// ```
+    pub fn build_static_initiator<D, C, H, R>(self, rng: R) -> Result<HandshakeState<D, C, H, R>>
+        where D: Dh + Default, C: Cipher + Default, H: Hash + Default, R: Random
+    {
+        self.build_static(true, rng)
+    }
+
+    /// Build the responder's side as a `HandshakeState` over concrete primitive types. See
+    /// [`build_static_initiator`](#method.build_static_initiator).
+    pub fn build_static_responder<D, C, H, R>(self, rng: R) -> Result<HandshakeState<D, C, H, R>>
+        where D: Dh + Default, C: Cipher + Default, H: Hash + Default, R: Random
+    {
+        self.build_static(false, rng)
+    }
+
+    fn check_policy(&self) -> Result<()> {
+        match self.resolver.disallowed(&self.params) {
+            Some(name) => bail!(ErrorKind::Init(InitStage::Disallowed(name))),
+            None       => Ok(()),
+        }
+    }
+
+    fn check_prereqs(&self, initiator: bool) -> Result<()> {
+        if self.s.is_none() && self.s_provider.is_none() && self.params.handshake.pattern.needs_local_static_key(initiator) {
             bail!(ErrorKind::Prereq(Prerequisite::LocalPrivateKey));
         }
 
         if !self.rs.is_some() && self.params.handshake.pattern.need_known_remote_pubkey(initiator) {
             bail!(ErrorKind::Prereq(Prerequisite::RemotePublicKey));
         }
+        Ok(())
+    }
+
+    fn build(self, initiator: bool) -> Result<Session> {
+        self.check_policy()?;
+        self.check_prereqs(initiator)?;
 
         let rng = self.resolver.resolve_rng().ok_or(ErrorKind::Init(InitStage::GetRngImpl))?;
         let cipher = self.resolver.resolve_cipher(&self.params.cipher).ok_or(ErrorKind::Init(InitStage::GetCipherImpl))?;
         let hash = self.resolver.resolve_hash(&self.params.hash).ok_or(ErrorKind::Init(InitStage::GetHashImpl))?;
-        let mut s_dh = self.resolver.resolve_dh(&self.params.dh).ok_or(ErrorKind::Init(InitStage::GetDhImpl))?;
-        let mut e_dh = self.resolver.resolve_dh(&self.params.dh).ok_or(ErrorKind::Init(InitStage::GetDhImpl))?;
+        let s_dh = self.resolver.resolve_dh(&self.params.dh).ok_or(ErrorKind::Init(InitStage::GetDhImpl))?;
+        let e_dh = self.resolver.resolve_dh(&self.params.dh).ok_or(ErrorKind::Init(InitStage::GetDhImpl))?;
         let cipher1 = self.resolver.resolve_cipher(&self.params.cipher).ok_or(ErrorKind::Init(InitStage::GetCipherImpl))?;
         let cipher2 = self.resolver.resolve_cipher(&self.params.cipher).ok_or(ErrorKind::Init(InitStage::GetCipherImpl))?;
+
+        let hs = self.build_state(initiator, rng, s_dh, e_dh, [cipher, cipher1, cipher2], hash)?;
+        Ok(hs.into())
+    }
+
+    fn build_static<D, C, H, R>(self, initiator: bool, rng: R) -> Result<HandshakeState<D, C, H, R>>
+        where D: Dh + Default, C: Cipher + Default, H: Hash + Default, R: Random
+    {
+        self.check_policy()?;
+        self.check_prereqs(initiator)?;
+
+        let (s_dh, e_dh, hash) = (D::default(), D::default(), H::default());
+        let ciphers = [C::default(), C::default(), C::default()];
+        if s_dh.name() != dh_name(&self.params.dh) {
+            bail!(ErrorKind::Init(InitStage::GetDhImpl));
+        }
+        if ciphers[0].name() != cipher_name(&self.params.cipher) {
+            bail!(ErrorKind::Init(InitStage::GetCipherImpl));
+        }
+        if hash.name() != hash_name(&self.params.hash) {
+            bail!(ErrorKind::Init(InitStage::GetHashImpl));
+        }
+
+        self.build_state(initiator, rng, s_dh, e_dh, ciphers, hash)
+    }
+
+    fn build_state<D, C, H, R>(self, initiator: bool, rng: R, mut s_dh: D, mut e_dh: D, ciphers: [C; 3], hash: H)
+        -> Result<HandshakeState<D, C, H, R>>
+        where D: Dh, C: Cipher, H: Hash, R: Random
+    {
+        let [cipher, cipher1, cipher2] = ciphers;
         let handshake_cipherstate = CipherState::new(cipher);
         let cipherstates = CipherStates::new(CipherState::new(cipher1), CipherState::new(cipher2))?;
 
-        let s = match self.s {
-            Some(k) => {
-                (&mut *s_dh).set(k);
-                Toggle::on(s_dh)
+        let s = match (self.s, self.s_provider) {
+            (Some(_), Some(_)) => {
+                bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::StaticKeyProvider)));
+            },
+            (Some(k), None) => {
+                if k.len() != s_dh.priv_len() {
+                    bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::LocalPrivateKey)));
+                }
+                s_dh.lock().map_err(|_| ErrorKind::Init(InitStage::LockMemory))?;
+                s_dh.set(k);
+                if !s_dh.priv_valid() {
+                    bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::LocalPrivateKey)));
+                }
+                Toggle::on(StaticKey::Local(s_dh))
+            },
+            (None, Some(provider)) => {
+                if provider.name() != s_dh.name() || provider.pub_len() != s_dh.pub_len() {
+                    bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::StaticKeyProvider)));
+                }
+                Toggle::on(StaticKey::Provided(provider))
             },
-            None => {
-                Toggle::off(s_dh)
+            (None, None) => {
+                Toggle::off(StaticKey::Local(s_dh))
             }
         };
 
         if let Some(fixed_k) = self.e_fixed {
-            (&mut *e_dh).set(fixed_k);
+            if fixed_k.len() != e_dh.priv_len() {
+                bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::FixedEphemeralKey)));
+            }
+            e_dh.set(fixed_k);
+            if !e_dh.priv_valid() {
+                bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::FixedEphemeralKey)));
+            }
         }
-        let e = Toggle::off(e_dh);
+        let e = Toggle::off(EphemeralKey(e_dh));
 
         let mut rs_buf = [0u8; MAXDHLEN];
         let rs = match self.rs {
             Some(v) => {
+                if v.len() != e.pub_len() {
+                    bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::RemotePublicKey)));
+                }
                 rs_buf[..v.len()].copy_from_slice(&v[..]);
                 Toggle::on(rs_buf)
             },
@@ -201,32 +456,70 @@ impl<'builder> NoiseBuilder<'builder> {
 
         let re = Toggle::off([0u8; MAXDHLEN]);
 
-        let mut psks = [None::<[u8; PSKLEN]>; 10];
+        if let Some(location) = self.bad_psk {
+            bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::Psk(location))));
+        }
+        let mut psks = SecretBuf::new([None::<[u8; PSKLEN]>; 10]);
+        if self.psks.iter().any(|psk| psk.is_some()) {
+            psks.lock().map_err(|_| ErrorKind::Init(InitStage::LockMemory))?;
+        }
         for (i, psk) in self.psks.iter().enumerate() {
             if let Some(key) = *psk {
                 if key.len() != PSKLEN {
-                    bail!(ErrorKind::Init(InitStage::ValidatePskLengths));
+                    bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::Psk(i as u8))));
                 }
                 let mut k = [0u8; PSKLEN];
                 k.copy_from_slice(key);
                 psks[i] = Some(k);
+                k.zeroize();
             }
         }
 
-        let hs = HandshakeState::new(rng, handshake_cipherstate, hash,
-                                     s, e, self.e_fixed.is_some(), rs, re,
-                                     initiator,
-                                     self.params,
-                                     psks,
-                                     self.plog.unwrap_or_else(|| &[0u8; 0]),
-                                     cipherstates)?;
-        Ok(hs.into())
+        // The hint and padding tags follow the caller's prologue, hashed as one. Each is length
+        // prefixed so that one tag can't be read as the tail of another or of the prologue.
+        let mut tags = Vec::new();
+        if let Some(hint) = self.hint {
+            if !hint.matches(&self.params) {
+                bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::ProtocolHint)));
+            }
+            push_tag(&mut tags, &hint.prologue_tag());
+        }
+        if let Some(ref policy) = self.padding {
+            if !policy.validate() {
+                bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::PaddingPolicy)));
+            }
+            push_tag(&mut tags, &policy.prologue_tag());
+        }
+        let prologue = self.plog.unwrap_or(&[]);
+
+        // The key rotation needs a hasher of its own once the handshake is over.
+        let bolt8 = if self.bolt8 {
+            if self.padding.is_some() {
+                bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::Bolt8)));
+            }
+            Some(self.resolver.resolve_hash(&self.params.hash).ok_or(ErrorKind::Init(InitStage::GetHashImpl))?)
+        } else {
+            None
+        };
+
+        HandshakeState::new(rng, handshake_cipherstate, hash,
+                            s, e, self.e_fixed.is_some(), rs, re,
+                            initiator,
+                            self.params,
+                            psks,
+                            &[prologue, &tags],
+                            cipherstates,
+                            self.padding,
+                            bolt8,
+                            self.allow_low_order,
+                            self.tracer)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use error::Error;
 
     #[test]
     fn test_builder() {
@@ -246,10 +539,73 @@ mod tests {
 
     #[test]
     fn test_builder_bad_spec() {
-        let params: ::std::result::Result<NoiseParams, _> = "Noise_NK_25519_ChaChaPoly_BLAH256".parse();
+        let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAH256".parse().unwrap();
+
+        match NoiseBuilder::new(params).build_initiator() {
+            Err(Error(ErrorKind::Init(InitStage::GetHashImpl), _)) => {},
+            _ => panic!("an unknown hash shouldn't resolve"),
+        }
+    }
+
+    #[test]
+    fn test_builder_invalid_field_named() {
+        let params: NoiseParams = "Noise_NNpsk0+psk2_25519_ChaChaPoly_SHA256".parse().unwrap();
+        let noise = NoiseBuilder::new(params.clone())
+            .psk(0, &[0u8; 32])
+            .psk(2, &[0u8; 16])
+            .build_initiator();
+        match noise {
+            Err(Error(ErrorKind::Init(InitStage::ValidateField(BuilderField::Psk(2))), _)) => {},
+            _ => panic!("psk2 wasn't reported"),
+        }
+
+        let noise = NoiseBuilder::new(params.clone())
+            .psk(0, &[0u8; 32])
+            .psk(10, &[0u8; 32])
+            .build_initiator();
+        match noise {
+            Err(Error(ErrorKind::Init(InitStage::ValidateField(BuilderField::Psk(10))), _)) => {},
+            _ => panic!("psk10 wasn't reported"),
+        }
 
-        if let Ok(_) = params {
-            panic!("NoiseParams should have failed");
+        let noise = NoiseBuilder::new("Noise_NK_25519_ChaChaPoly_SHA256".parse().unwrap())
+            .remote_public_key(&[0u8; 64])
+            .build_initiator();
+        match noise {
+            Err(Error(ErrorKind::Init(InitStage::ValidateField(BuilderField::RemotePublicKey)), _)) => {},
+            _ => panic!("remote public key wasn't reported"),
+        }
+    }
+
+    #[test]
+    fn test_static_session_interop() {
+        let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let mut init = NoiseBuilder::new(params.clone())
+            .build_static_initiator::<Dh25519, CipherChaChaPoly, HashBLAKE2s, _>(RandomOs::default())
+            .unwrap();
+        let mut resp = NoiseBuilder::new(params).build_responder().unwrap();
+
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        let len = init.write_handshake_message(b"abc", &mut msg).unwrap();
+        resp.read_message(&msg[..len], &mut buf).unwrap();
+        let len = resp.write_message(b"def", &mut msg).unwrap();
+        let payload_len = init.read_handshake_message(&msg[..len], &mut buf).unwrap();
+        assert_eq!(&buf[..payload_len], b"def");
+
+        let mut init = init.finish().unwrap();
+        let mut resp = resp.into_transport_mode().unwrap();
+        let len = init.write_transport_message(b"ghi", &mut msg).unwrap();
+        let payload_len = resp.read_message(&msg[..len], &mut buf).unwrap();
+        assert_eq!(&buf[..payload_len], b"ghi");
+    }
+
+    #[test]
+    fn test_static_primitives_must_match_params() {
+        let noise = NoiseBuilder::new("Noise_NN_25519_AESGCM_BLAKE2s".parse().unwrap())
+            .build_static_initiator::<Dh25519, CipherChaChaPoly, HashBLAKE2s, _>(RandomOs::default());
+        match noise {
+            Err(Error(ErrorKind::Init(InitStage::GetCipherImpl), _)) => {},
+            _ => panic!("mismatched cipher wasn't rejected"),
         }
     }
 
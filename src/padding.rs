@@ -0,0 +1,229 @@
+//! Length-hiding padding for transport messages.
+//!
+//! When a `PaddingPolicy` is given to the builder, every transport message's plaintext is framed
+//! as a 2-byte big-endian length of the real payload, followed by the payload, followed by zero
+//! padding. The frame is encrypted as a whole, so the inner length is authenticated along with the
+//! payload and only the padded size is visible on the wire.
+//!
+//! Both parties must use the same policy: the builder appends the policy's description to the
+//! prologue, so a mismatch causes the handshake to fail rather than the transport to misparse.
+
+use byteorder::{ByteOrder, BigEndian};
+use constants::{MAXMSGLEN, TAGLEN};
+use error::{ErrorKind, Result, InputProblem, InitStage, BuilderField};
+use types::Random;
+use std::prelude::v1::*;
+
+/// The size of the authenticated inner length field prepended to every padded payload.
+pub const PADDING_HEADER_LEN: usize = 2;
+
+/// The largest framed plaintext (length field, payload and padding) that fits in a Noise message.
+const MAXFRAMELEN: usize = MAXMSGLEN - TAGLEN;
+
+/// The policy used to choose how much padding is added to each transport message.
+///
+/// # Examples
+///
+/// ```
+/// # use snow::NoiseBuilder;
+/// # use snow::PaddingPolicy;
+/// let noise = NoiseBuilder::new("Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap())
+///                          .padding(PaddingPolicy::Multiple(256))
+///                          .build_initiator()
+///                          .unwrap();
+/// ```
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "replay", derive(Serialize, Deserialize))]
+pub enum PaddingPolicy {
+    /// Pad the framed plaintext up to the smallest of the given sizes that fits it. Payloads that
+    /// don't fit in the largest bucket are rejected.
+    Buckets(Vec<usize>),
+    /// Pad the framed plaintext up to the next multiple of the given size.
+    Multiple(usize),
+    /// Add a uniformly random amount of padding between zero and the given size (inclusive).
+    Random(usize),
+}
+
+impl PaddingPolicy {
+    /// The description of this policy that is mixed into the handshake via the prologue.
+    pub fn prologue_tag(&self) -> Vec<u8> {
+        let tag = match *self {
+            PaddingPolicy::Buckets(ref sizes) => {
+                let sizes: Vec<String> = sizes.iter().map(|s| s.to_string()).collect();
+                format!("snow-padding:buckets:{}", sizes.join(","))
+            },
+            PaddingPolicy::Multiple(n) => format!("snow-padding:multiple:{}", n),
+            PaddingPolicy::Random(max) => format!("snow-padding:random:{}", max),
+        };
+        tag.into_bytes()
+    }
+
+    /// Check that the policy can be applied at all, used by the builder.
+    pub fn validate(&self) -> bool {
+        match *self {
+            PaddingPolicy::Buckets(ref sizes) => {
+                !sizes.is_empty() && sizes.iter().all(|s| *s >= PADDING_HEADER_LEN && *s <= MAXFRAMELEN)
+            },
+            PaddingPolicy::Multiple(n) => n > 0 && n <= MAXFRAMELEN,
+            PaddingPolicy::Random(max) => max <= MAXFRAMELEN,
+        }
+    }
+
+    /// The length of the framed plaintext that a payload of `payload_len` bytes will be padded to.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `ErrorKind::Init(InitStage::ValidateField(BuilderField::PaddingPolicy))` if
+    /// the policy doesn't `validate`, and `ErrorKind::Input(InputProblem::MessageTooLong)` if the
+    /// payload doesn't fit.
+    pub fn padded_len(&self, payload_len: usize, rng: &mut Random) -> Result<usize> {
+        if !self.validate() {
+            bail!(ErrorKind::Init(InitStage::ValidateField(BuilderField::PaddingPolicy)));
+        }
+        let framed = payload_len + PADDING_HEADER_LEN;
+        if framed > MAXFRAMELEN {
+            bail!(ErrorKind::Input(InputProblem::MessageTooLong));
+        }
+        let padded = match *self {
+            PaddingPolicy::Buckets(ref sizes) => {
+                match sizes.iter().filter(|s| **s >= framed).min() {
+                    Some(size) => *size,
+                    None => bail!(ErrorKind::Input(InputProblem::MessageTooLong)),
+                }
+            },
+            PaddingPolicy::Multiple(n) => {
+                let rounded = framed.div_ceil(n) * n;
+                if rounded > MAXFRAMELEN { MAXFRAMELEN } else { rounded }
+            },
+            PaddingPolicy::Random(max) => {
+                // Redraw values from the short last window so every amount is equally likely.
+                let range = max as u32 + 1;
+                let limit = u32::MAX - u32::MAX % range;
+                let mut bytes = [0u8; 4];
+                let mut value;
+                loop {
+                    rng.fill_bytes(&mut bytes);
+                    value = BigEndian::read_u32(&bytes);
+                    if value < limit {
+                        break;
+                    }
+                }
+                let extra = (value % range) as usize;
+                if framed + extra > MAXFRAMELEN { MAXFRAMELEN } else { framed + extra }
+            },
+        };
+        Ok(padded)
+    }
+}
+
+/// Write the length field, `payload` and zero padding into `out`, which must be exactly the
+/// padded length.
+pub fn frame(payload: &[u8], out: &mut [u8]) {
+    BigEndian::write_u16(&mut out[..PADDING_HEADER_LEN], payload.len() as u16);
+    out[PADDING_HEADER_LEN..PADDING_HEADER_LEN + payload.len()].copy_from_slice(payload);
+    for byte in &mut out[PADDING_HEADER_LEN + payload.len()..] {
+        *byte = 0;
+    }
+}
+
+/// Strip the framing from a decrypted plaintext in place, returning the real payload length.
+pub fn unframe(framed: &mut [u8]) -> Result<usize> {
+    if framed.len() < PADDING_HEADER_LEN {
+        bail!(ErrorKind::Input(InputProblem::Malformed("padding")));
+    }
+    let payload_len = BigEndian::read_u16(&framed[..PADDING_HEADER_LEN]) as usize;
+    if PADDING_HEADER_LEN + payload_len > framed.len() {
+        bail!(ErrorKind::Input(InputProblem::Malformed("padding")));
+    }
+    framed.copy_within(PADDING_HEADER_LEN..PADDING_HEADER_LEN + payload_len, 0);
+    Ok(payload_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CountingRandom(u8);
+
+    impl Random for CountingRandom {
+        fn fill_bytes(&mut self, out: &mut [u8]) {
+            for byte in out.iter_mut() {
+                *byte = self.0;
+                self.0 = self.0.wrapping_add(1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bucket_padding() {
+        let policy = PaddingPolicy::Buckets(vec![1024, 64, 256]);
+        let mut rng = CountingRandom(0);
+        assert_eq!(policy.padded_len(0, &mut rng).unwrap(), 64);
+        assert_eq!(policy.padded_len(62, &mut rng).unwrap(), 64);
+        assert_eq!(policy.padded_len(63, &mut rng).unwrap(), 256);
+        assert!(policy.padded_len(1023, &mut rng).is_err());
+    }
+
+    #[test]
+    fn test_multiple_padding() {
+        let policy = PaddingPolicy::Multiple(100);
+        let mut rng = CountingRandom(0);
+        assert_eq!(policy.padded_len(0, &mut rng).unwrap(), 100);
+        assert_eq!(policy.padded_len(98, &mut rng).unwrap(), 100);
+        assert_eq!(policy.padded_len(99, &mut rng).unwrap(), 200);
+        assert_eq!(policy.padded_len(MAXFRAMELEN - PADDING_HEADER_LEN, &mut rng).unwrap(), MAXFRAMELEN);
+    }
+
+    #[test]
+    fn test_random_padding_bounds() {
+        let policy = PaddingPolicy::Random(16);
+        let mut rng = CountingRandom(0);
+        for _ in 0..100 {
+            let len = policy.padded_len(10, &mut rng).unwrap();
+            assert!(len >= 12 && len <= 28);
+        }
+    }
+
+    struct ScriptedRandom(Vec<u32>);
+
+    impl Random for ScriptedRandom {
+        fn fill_bytes(&mut self, out: &mut [u8]) {
+            BigEndian::write_u32(out, self.0.remove(0));
+        }
+    }
+
+    #[test]
+    fn test_random_padding_rejects_biased_draws() {
+        // 2^32 isn't a multiple of 3, so the top value would favour an extra of zero.
+        let policy = PaddingPolicy::Random(2);
+        let mut rng = ScriptedRandom(vec![u32::MAX, 4]);
+        assert_eq!(policy.padded_len(10, &mut rng).unwrap(), 13);
+        assert!(rng.0.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_policy_padded_len() {
+        let mut rng = CountingRandom(0);
+        assert!(PaddingPolicy::Multiple(0).padded_len(10, &mut rng).is_err());
+        assert!(PaddingPolicy::Random(u32::MAX as usize).padded_len(10, &mut rng).is_err());
+        assert!(PaddingPolicy::Buckets(vec![]).padded_len(10, &mut rng).is_err());
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut buf = [0xffu8; 32];
+        frame(b"hello", &mut buf);
+        assert_eq!(&buf[..7], b"\x00\x05hello");
+        assert!(buf[7..].iter().all(|b| *b == 0));
+        let len = unframe(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"hello");
+    }
+
+    #[test]
+    fn test_unframe_bad_length() {
+        let mut buf = [0u8; 8];
+        buf[1] = 7;
+        assert!(unframe(&mut buf).is_err());
+        assert!(unframe(&mut buf[..1]).is_err());
+    }
+}
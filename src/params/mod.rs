@@ -9,7 +9,9 @@ use std::convert::TryFrom;
 #[cfg(not(feature = "nightly"))]
 use utils::TryFrom;
 
+use error::{Error, ErrorKind, ParseProblem};
 use std::str::FromStr;
+use std::prelude::v1::*;
 mod patterns;
 
 pub use self::patterns::*;
@@ -21,65 +23,81 @@ pub enum BaseChoice {
 }
 
 impl FromStr for BaseChoice {
-    type Err = &'static str;
+    type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use self::BaseChoice::*;
         match s {
             "Noise"    => Ok(Noise),
-            _          => Err("base type unsupported"),
+            _          => bail!(ErrorKind::Parse(ParseProblem::UnsupportedBaseType(s.to_owned()))),
         }
     }
 }
 
-/// One of `25519` or `448`, per the spec.
-#[derive(PartialEq, Copy, Clone, Debug)]
+/// One of `25519` or `448`, per the spec, `secp256k1` as used by Lightning, `P256` for NIST P-256,
+/// or a non-standard name for a resolver to interpret.
+#[derive(PartialEq, Clone, Debug)]
 pub enum DHChoice {
     Curve25519,
     Ed448,
+    /// ECDH over secp256k1 with compressed public keys, hashing the shared point with SHA-256,
+    /// per [BOLT 8](https://github.com/lightning/bolts/blob/master/08-transport.md).
+    Secp256k1,
+    /// ECDH over NIST P-256 with compressed public keys. The shared secret is the x-coordinate of
+    /// the shared point.
+    P256,
+    Custom(String),
 }
 
 impl FromStr for DHChoice {
-    type Err = &'static str;
+    type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use self::DHChoice::*;
         match s {
-            "25519" => Ok(Curve25519),
-            "448"   => Ok(Ed448),
-            _       => Err("DH type unsupported")
+            "25519"     => Ok(Curve25519),
+            "448"       => Ok(Ed448),
+            "secp256k1" => Ok(Secp256k1),
+            "P256"      => Ok(P256),
+            ""          => bail!(ErrorKind::Parse(ParseProblem::UnsupportedDhType(s.to_owned()))),
+            _           => Ok(Custom(s.to_owned())),
         }
     }
 }
 
-/// One of `ChaChaPoly` or `AESGCM`, per the spec.
-#[derive(PartialEq, Copy, Clone, Debug)]
+/// One of `ChaChaPoly` or `AESGCM`, per the spec, or a non-standard name for a resolver to
+/// interpret.
+#[derive(PartialEq, Clone, Debug)]
 pub enum CipherChoice {
     ChaChaPoly,
     AESGCM,
+    Custom(String),
 }
 
 impl FromStr for CipherChoice {
-    type Err = &'static str;
+    type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use self::CipherChoice::*;
         match s {
             "ChaChaPoly" => Ok(ChaChaPoly),
             "AESGCM"     => Ok(AESGCM),
-            _            => Err("cipher type unsupported")
+            ""           => bail!(ErrorKind::Parse(ParseProblem::UnsupportedCipherType(s.to_owned()))),
+            _            => Ok(Custom(s.to_owned())),
         }
     }
 }
 
-/// One of the supported SHA-family or BLAKE-family hash choices, per the spec.
-#[derive(PartialEq, Copy, Clone, Debug)]
+/// One of the supported SHA-family or BLAKE-family hash choices, per the spec, or a non-standard
+/// name for a resolver to interpret.
+#[derive(PartialEq, Clone, Debug)]
 pub enum HashChoice {
     SHA256,
     SHA512,
     Blake2s,
     Blake2b,
+    Custom(String),
 }
 
 impl FromStr for HashChoice {
-    type Err = &'static str;
+    type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use self::HashChoice::*;
         match s {
@@ -87,7 +105,8 @@ impl FromStr for HashChoice {
             "SHA512"  => Ok(SHA512),
             "BLAKE2s" => Ok(Blake2s),
             "BLAKE2b" => Ok(Blake2b),
-            _         => Err("hash type unsupported")
+            ""        => bail!(ErrorKind::Parse(ParseProblem::UnsupportedHashType(s.to_owned()))),
+            _         => Ok(Custom(s.to_owned())),
         }
     }
 }
@@ -105,6 +124,17 @@ impl FromStr for HashChoice {
 ///
 /// let params: NoiseParams = "Noise_XX_25519_AESGCM_SHA256".parse().unwrap();
 /// ```
+///
+/// Names the spec doesn't define parse as `Custom` choices, which are passed to the
+/// `CryptoResolver` like any other. Building a session fails with `ErrorKind::Init` if it doesn't
+/// resolve them:
+///
+/// ```
+/// # use snow::params::*;
+/// let params: NoiseParams = "Noise_XX_25519_XChaChaPoly_SHA3-256".parse().unwrap();
+/// assert_eq!(params.cipher, CipherChoice::Custom("XChaChaPoly".to_owned()));
+/// assert_eq!(params.hash, HashChoice::Custom("SHA3-256".to_owned()));
+/// ```
 #[derive(PartialEq, Clone, Debug)]
 pub struct NoiseParams {
     pub name: String,
@@ -130,16 +160,16 @@ impl NoiseParams {
 }
 
 impl FromStr for NoiseParams {
-    type Err = &'static str;
+    type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut split = s.split('_');
-        static TOO_FEW: &'static str = "too few parameters";
+        let mut next = || split.next().ok_or(ErrorKind::Parse(ParseProblem::TooFewParameters));
         Ok(NoiseParams::new(s.to_owned(),
-                            split.next().ok_or(TOO_FEW)?.parse()?,
-                            split.next().ok_or(TOO_FEW)?.parse()?,
-                            split.next().ok_or(TOO_FEW)?.parse()?,
-                            split.next().ok_or(TOO_FEW)?.parse()?,
-                            split.next().ok_or(TOO_FEW)?.parse()?))
+                            next()?.parse()?,
+                            next()?.parse()?,
+                            next()?.parse()?,
+                            next()?.parse()?,
+                            next()?.parse()?))
     }
 }
 
@@ -191,6 +221,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_unsupported_component_named() {
+        match "Noise_XX_25519_AESGCM_".parse::<NoiseParams>() {
+            Err(Error(ErrorKind::Parse(ParseProblem::UnsupportedHashType(ref hash)), _)) => assert_eq!(hash, ""),
+            _ => panic!("empty hash type wasn't reported"),
+        }
+        match "Noise__25519_AESGCM_SHA256".parse::<NoiseParams>() {
+            Err(Error(ErrorKind::Parse(ParseProblem::UnsupportedHandshakePattern(ref pattern)), _)) => assert_eq!(pattern, ""),
+            _ => panic!("empty pattern wasn't reported"),
+        }
+    }
+
+    #[test]
+    fn test_custom_choices() {
+        let p: NoiseParams = "Noise_XX_448_AESGCM_SHA999".parse().unwrap();
+        assert_eq!(p.dh, DHChoice::Ed448);
+        assert_eq!(p.hash, HashChoice::Custom("SHA999".to_owned()));
+        let p: NoiseParams = "Noise_NN_Curve41417_XChaChaPoly_BLAKE2s".parse().unwrap();
+        assert_eq!(p.dh, DHChoice::Custom("Curve41417".to_owned()));
+        assert_eq!(p.cipher, CipherChoice::Custom("XChaChaPoly".to_owned()));
+        let p: NoiseParams = "Noise_XK_secp256k1_ChaChaPoly_SHA256".parse().unwrap();
+        assert_eq!(p.dh, DHChoice::Secp256k1);
+    }
+
     #[test]
     fn test_modified_psk_handshake() {
         let p: NoiseParams = "Noise_XXpsk0_25519_AESGCM_SHA256".parse().unwrap();
@@ -201,6 +255,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_psk_past_last_message() {
+        for name in &["Noise_NNpsk5_25519_AESGCM_SHA256", "Noise_Npsk3_25519_AESGCM_SHA256"] {
+            let p: NoiseParams = name.parse().unwrap();
+            match HandshakeTokens::try_from(p.handshake) {
+                Err(Error(ErrorKind::Parse(ParseProblem::IncompatiblePsk(_)), _)) => {},
+                _ => panic!("{} wasn't rejected", name),
+            }
+        }
+    }
+
     #[test]
     fn test_modified_multi_psk_handshake() {
         let p: NoiseParams = "Noise_XXpsk0+psk2_25519_AESGCM_SHA256".parse().unwrap();
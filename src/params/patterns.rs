@@ -1,7 +1,10 @@
 #[cfg(feature = "nightly")] use std::convert::{TryFrom};
 #[cfg(not(feature = "nightly"))] use utils::{TryFrom};
+use error::{Error, ErrorKind, ParseProblem};
+use std::fmt;
 use std::str::FromStr;
 use arrayvec::ArrayVec;
+use std::prelude::v1::*;
 
 macro_rules! message_vec {
     ($($item:expr),*) => ({
@@ -24,6 +27,20 @@ macro_rules! message_vec {
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Token { E, S, Dhee, Dhes, Dhse, Dhss, Psk(u8) }
 
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::E      => write!(f, "e"),
+            Token::S      => write!(f, "s"),
+            Token::Dhee   => write!(f, "ee"),
+            Token::Dhes   => write!(f, "es"),
+            Token::Dhse   => write!(f, "se"),
+            Token::Dhss   => write!(f, "ss"),
+            Token::Psk(n) => write!(f, "psk{}", n),
+        }
+    }
+}
+
 /// One of the patterns as defined in the
 /// [Handshake Pattern](http://noiseprotocol.org/noise.html#handshake-patterns) section
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -76,15 +93,16 @@ impl HandshakePattern {
 pub enum HandshakeModifier { Psk(u8), Fallback }
 
 impl FromStr for HandshakeModifier {
-    type Err = &'static str;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.starts_with("psk") {
-            Ok(HandshakeModifier::Psk((&s[3..]).parse().map_err(|_| "psk must have number parameter")?))
+            let n = (&s[3..]).parse().map_err(|_| ErrorKind::Parse(ParseProblem::UnsupportedModifier(s.to_owned())))?;
+            Ok(HandshakeModifier::Psk(n))
         } else if s == "fallback" {
             Ok(HandshakeModifier::Fallback)
         } else {
-            Err("unrecognized or invalid modifier")
+            bail!(ErrorKind::Parse(ParseProblem::UnsupportedModifier(s.to_owned())))
         }
     }
 }
@@ -95,7 +113,7 @@ pub struct HandshakeModifierList {
 }
 
 impl FromStr for HandshakeModifierList {
-    type Err = &'static str;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.is_empty() {
@@ -138,21 +156,16 @@ impl HandshakeChoice {
 }
 
 impl FromStr for HandshakeChoice {
-    type Err = &'static str;
+    type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (pattern, remainder);
-        if s.len() > 1 {
-            if let Ok(p) = (&s[..2]).parse::<HandshakePattern>() {
-                pattern = p;
-                remainder = &s[2..];
-            } else {
-                pattern = (&s[..1]).parse::<HandshakePattern>()?;
-                remainder = &s[1..];
-            }
-        } else {
-            pattern = (&s[..1]).parse::<HandshakePattern>()?;
-            remainder = &s[1..];
-        }
+        let unsupported = || ErrorKind::Parse(ParseProblem::UnsupportedHandshakePattern(s.to_owned()));
+        let (pattern, remainder) = match s.get(..2).and_then(|p| p.parse::<HandshakePattern>().ok()) {
+            Some(pattern) => (pattern, &s[2..]),
+            None => {
+                let pattern = s.get(..1).ok_or_else(&unsupported)?.parse::<HandshakePattern>().map_err(|_| unsupported())?;
+                (pattern, &s[1..])
+            },
+        };
 
         Ok(HandshakeChoice {
             pattern: pattern,
@@ -162,7 +175,7 @@ impl FromStr for HandshakeChoice {
 }
 
 impl FromStr for HandshakePattern {
-    type Err = &'static str;
+    type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use self::HandshakePattern::*;
         match s {
@@ -181,7 +194,7 @@ impl FromStr for HandshakePattern {
             "IN" => Ok(IN),
             "IK" => Ok(IK),
             "IX" => Ok(IX),
-            _    => Err("handshake not recognized")
+            _    => bail!(ErrorKind::Parse(ParseProblem::UnsupportedHandshakePattern(s.to_owned())))
         }
     }
 }
@@ -227,97 +240,106 @@ use self::HandshakePattern::*;
 type Patterns = (PremessagePatterns, PremessagePatterns, MessagePatterns);
 
 impl TryFrom<HandshakeChoice> for HandshakeTokens {
-    type Error = &'static str;
+    type Error = Error;
 
     fn try_from(handshake: HandshakeChoice) -> Result<Self, Self::Error> {
+        HandshakeTokens::try_from(&handshake)
+    }
+}
+
+impl<'a> TryFrom<&'a HandshakeChoice> for HandshakeTokens {
+    type Error = Error;
+
+    fn try_from(handshake: &'a HandshakeChoice) -> Result<Self, Self::Error> {
         let mut patterns: Patterns = match handshake.pattern {
             N  => (
-                static_slice![Token: ],
-                static_slice![Token: S],
+                &[],
+                &[S],
                 message_vec![&[E, Dhes]]
             ),
             K  => (
-                static_slice![Token: S],
-                static_slice![Token: S],
+                &[S],
+                &[S],
                 message_vec![&[E, Dhes, Dhss]]
             ),
             X  => (
-                static_slice![Token: ],
-                static_slice![Token: S],
+                &[],
+                &[S],
                 message_vec![&[E, Dhes, S, Dhss]]
             ),
             NN => (
-                static_slice![Token: ],
-                static_slice![Token: ],
+                &[],
+                &[],
                 message_vec![&[E], &[E, Dhee]]
             ),
             NK => (
-                static_slice![Token: ],
-                static_slice![Token: S],
+                &[],
+                &[S],
                 message_vec![&[E, Dhes], &[E, Dhee]]
             ),
             NX => (
-                static_slice![Token: ],
-                static_slice![Token: ],
+                &[],
+                &[],
                 message_vec![&[E], &[E, Dhee, S, Dhse]]
             ),
             XN => (
-                static_slice![Token: ],
-                static_slice![Token: ],
+                &[],
+                &[],
                 message_vec![&[E], &[E, Dhee], &[S, Dhse]]
             ),
             XK => (
-                static_slice![Token: ],
-                static_slice![Token: S],
+                &[],
+                &[S],
                 message_vec![&[E, Dhes], &[E, Dhee], &[S, Dhse]]
             ),
             XX => (
-                static_slice![Token: ],
-                static_slice![Token: ],
+                &[],
+                &[],
                 message_vec![&[E], &[E, Dhee, S, Dhse], &[S, Dhse]],
             ),
             KN => (
-                static_slice![Token: S],
-                static_slice![Token: ],
+                &[S],
+                &[],
                 message_vec![&[E], &[E, Dhee, Dhes]],
             ),
             KK => (
-                static_slice![Token: S],
-                static_slice![Token: S],
+                &[S],
+                &[S],
                 message_vec![&[E, Dhes, Dhss], &[E, Dhee, Dhes]],
             ),
             KX => (
-                static_slice![Token: S],
-                static_slice![Token: ],
+                &[S],
+                &[],
                 message_vec![&[E], &[E, Dhee, Dhes, S, Dhse]],
             ),
             IN => (
-                static_slice![Token: ],
-                static_slice![Token: ],
+                &[],
+                &[],
                 message_vec![&[E, S], &[E, Dhee, Dhes]],
             ),
             IK => (
-                static_slice![Token: ],
-                static_slice![Token: S],
+                &[],
+                &[S],
                 message_vec![&[E, Dhes, S, Dhss], &[E, Dhee, Dhes]],
             ),
             IX => (
-                static_slice![Token: ],
-                static_slice![Token: ],
+                &[],
+                &[],
                 message_vec![&[E, S], &[E, Dhee, Dhes, S, Dhse]],
             ),
         };
 
-        for modifier in handshake.modifiers.list {
-            if let HandshakeModifier::Psk(n) = modifier {
-                match n {
-                    0 => { patterns.2[0].insert(0, Token::Psk(n)); },
-                    _ => {
-                        let i = (n as usize) - 1;
-                        if patterns.2[i].push(Token::Psk(n)).is_some() {
-                            return Err("token incompatible with selected handshake");
-                        }
-                    }
+        for modifier in &handshake.modifiers.list {
+            if let HandshakeModifier::Psk(n) = *modifier {
+                // psk0 goes at the start of the first message, pskN at the end of message N.
+                let i = if n == 0 { 0 } else { (n as usize) - 1 };
+                let placed = match patterns.2.get_mut(i) {
+                    Some(message) if n == 0 => message.try_insert(0, Token::Psk(n)).is_ok(),
+                    Some(message)           => message.try_push(Token::Psk(n)).is_ok(),
+                    None                    => false,
+                };
+                if !placed {
+                    bail!(ErrorKind::Parse(ParseProblem::IncompatiblePsk(n)));
                 }
             }
         }
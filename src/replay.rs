@@ -0,0 +1,551 @@
+//! Recording sessions, and replaying the recordings to reproduce failures.
+//!
+//! A [`RecordingBuilder`](struct.RecordingBuilder.html) builds a session that captures everything
+//! it consumes (the builder's settings, all of the RNG output, and the payload and message of every
+//! read and write, including failed ones) into a [`Transcript`](struct.Transcript.html). The
+//! transcript can be saved as JSON, and replayed later to rebuild an identical session and run it
+//! step by step, stopping at the first step whose outcome differs from the recording.
+//!
+//! A transcript contains the session's private keys, PSKs and randomness, so it must be handled
+//! as carefully as the keys themselves.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::NoiseBuilder;
+//! # use snow::replay::{HexBytes, RecordingBuilder, Transcript};
+//! let params: snow::params::NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+//! let mut initiator = RecordingBuilder::new(params.clone()).build_initiator().unwrap();
+//! let mut responder = NoiseBuilder::new(params).build_responder().unwrap();
+//!
+//! let (mut msg, mut buf) = ([0u8; 65535], [0u8; 65535]);
+//! let len = initiator.write_message(b"hello", &mut msg).unwrap();
+//! responder.read_message(&msg[..len], &mut buf).unwrap();
+//!
+//! let json = initiator.transcript().to_json();
+//! assert!(Transcript::from_json(&json).unwrap().replay().is_ok());
+//!
+//! // Without the recorded randomness the ephemeral key differs, so the replay diverges.
+//! let mut transcript = Transcript::from_json(&json).unwrap();
+//! transcript.random = HexBytes::from(vec![]);
+//! assert!(transcript.replay().is_err());
+//! ```
+
+use error::{ErrorKind, Result, StateProblem};
+pub use hexbytes::HexBytes;
+use noise::{self, CryptoResolver, NoiseBuilder};
+use padding::PaddingPolicy;
+use params::{CipherChoice, DHChoice, HashChoice, NoiseParams};
+use serde_json;
+use session::Session;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use types::{Cipher, Dh, Hash, Random};
+
+/// A PSK and the `pskN` location it was given for.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TranscriptPsk {
+    pub location: u8,
+    pub key: HexBytes,
+}
+
+/// One call made on a recorded session, and its outcome.
+///
+/// Failed calls are recorded with the error's description and no output.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    Write {
+        payload: HexBytes,
+        #[serde(default, skip_serializing_if="Option::is_none")] message: Option<HexBytes>,
+        #[serde(default, skip_serializing_if="Option::is_none")] error: Option<String>,
+    },
+    Read {
+        message: HexBytes,
+        #[serde(default, skip_serializing_if="Option::is_none")] payload: Option<HexBytes>,
+        #[serde(default, skip_serializing_if="Option::is_none")] error: Option<String>,
+    },
+    IntoTransportMode,
+    Rekey {
+        #[serde(default, skip_serializing_if="Option::is_none")] initiator: Option<HexBytes>,
+        #[serde(default, skip_serializing_if="Option::is_none")] responder: Option<HexBytes>,
+        #[serde(default, skip_serializing_if="Option::is_none")] error: Option<String>,
+    },
+    SetReceivingNonce {
+        nonce: u64,
+        #[serde(default, skip_serializing_if="Option::is_none")] error: Option<String>,
+    },
+}
+
+impl Step {
+    fn name(&self) -> &'static str {
+        match *self {
+            Step::Write { .. }             => "write message",
+            Step::Read { .. }              => "read message",
+            Step::IntoTransportMode        => "into transport mode",
+            Step::Rekey { .. }             => "rekey",
+            Step::SetReceivingNonce { .. } => "set receiving nonce",
+        }
+    }
+}
+
+/// Everything a recorded session consumed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Transcript {
+    pub protocol_name: String,
+    pub initiator: bool,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub local_private_key: Option<HexBytes>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub remote_public_key: Option<HexBytes>,
+    #[serde(default, skip_serializing_if="Vec::is_empty")] pub psks: Vec<TranscriptPsk>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub prologue: Option<HexBytes>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub padding: Option<PaddingPolicy>,
+    /// All of the RNG output the session used, in order.
+    pub random: HexBytes,
+    pub steps: Vec<Step>,
+}
+
+/// The first point at which a replay differed from its transcript.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The index of the divergent step, or `None` if the session couldn't be rebuilt. It's one
+    /// past the last step if the replay didn't use all of the recorded randomness.
+    pub step: Option<usize>,
+    pub reason: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.step {
+            Some(step) => write!(f, "step {}: {}", step, self.reason),
+            None       => write!(f, "building the session: {}", self.reason),
+        }
+    }
+}
+
+fn build(builder: NoiseBuilder, initiator: bool) -> Result<Session> {
+    if initiator { builder.build_initiator() } else { builder.build_responder() }
+}
+
+fn diverged(step: usize, reason: String) -> Divergence {
+    Divergence { step: Some(step), reason: reason }
+}
+
+/// Compare an outcome with the recorded one, describing any difference.
+fn compare(name: &str,
+           expected: (&Option<HexBytes>, &Option<String>),
+           actual: ::std::result::Result<&[u8], String>) -> ::std::result::Result<(), String> {
+    match (expected.0.as_ref(), actual) {
+        (Some(expected), Ok(actual)) if &expected[..] == actual => Ok(()),
+        (Some(_), Ok(_)) => Err(format!("{} differs from the recording", name)),
+        (_, actual)      => compare_error(expected.1, actual.map(|_| ())),
+    }
+}
+
+/// Compare the outcome of a call with no output with the recorded one.
+fn compare_error(expected: &Option<String>,
+                 actual: ::std::result::Result<(), String>) -> ::std::result::Result<(), String> {
+    match (expected.as_ref(), actual) {
+        (None, Ok(())) => Ok(()),
+        (Some(expected), Err(ref actual)) if expected == actual => Ok(()),
+        (Some(expected), Err(actual)) => Err(format!("failed with \"{}\" instead of \"{}\"", actual, expected)),
+        (Some(expected), Ok(()))      => Err(format!("succeeded, but the recording failed with \"{}\"", expected)),
+        (None, Err(actual))           => Err(format!("failed with \"{}\", but the recording succeeded", actual)),
+    }
+}
+
+/// Logs everything drawn from the wrapped `Random`.
+struct RecordingRandom {
+    inner: Box<Random>,
+    log: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Random for RecordingRandom {
+    fn fill_bytes(&mut self, out: &mut [u8]) {
+        self.inner.fill_bytes(out);
+        self.log.borrow_mut().extend_from_slice(out);
+    }
+}
+
+/// Hands out recorded randomness, noting if more is asked for than was recorded.
+struct ReplayRandom {
+    state: Rc<RefCell<ReplayState>>,
+}
+
+struct ReplayState {
+    random: Vec<u8>,
+    pos: usize,
+    overrun: bool,
+}
+
+impl Random for ReplayRandom {
+    fn fill_bytes(&mut self, out: &mut [u8]) {
+        let mut state = self.state.borrow_mut();
+        let len = ::std::cmp::min(out.len(), state.random.len() - state.pos);
+        out[..len].copy_from_slice(&state.random[state.pos..state.pos + len]);
+        for byte in &mut out[len..] {
+            *byte = 0;
+        }
+        state.pos += len;
+        state.overrun |= len < out.len();
+    }
+}
+
+/// Defers to another resolver, swapping its RNG for a recording or replaying one.
+struct WrappedResolver<'a> {
+    parent: Box<CryptoResolver + 'a>,
+    log: Option<Rc<RefCell<Vec<u8>>>>,
+    replay: Option<Rc<RefCell<ReplayState>>>,
+}
+
+impl<'a> CryptoResolver for WrappedResolver<'a> {
+    fn resolve_rng(&self) -> Option<Box<Random>> {
+        if let Some(ref state) = self.replay {
+            return Some(Box::new(ReplayRandom { state: state.clone() }));
+        }
+        let inner = self.parent.resolve_rng()?;
+        match self.log {
+            Some(ref log) => Some(Box::new(RecordingRandom { inner: inner, log: log.clone() })),
+            None          => Some(inner),
+        }
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+        self.parent.resolve_dh(choice)
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+        self.parent.resolve_hash(choice)
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+        self.parent.resolve_cipher(choice)
+    }
+}
+
+/// Builds a [`RecordingSession`](struct.RecordingSession.html). The settings are those of
+/// [`NoiseBuilder`](../struct.NoiseBuilder.html).
+pub struct RecordingBuilder<'builder> {
+    params:   NoiseParams,
+    resolver: Box<CryptoResolver + 'builder>,
+    s:        Option<&'builder [u8]>,
+    rs:       Option<&'builder [u8]>,
+    psks:     Vec<(u8, &'builder [u8])>,
+    plog:     Option<&'builder [u8]>,
+    padding:  Option<PaddingPolicy>,
+}
+
+impl<'builder> RecordingBuilder<'builder> {
+    /// Create a RecordingBuilder with the default crypto resolver.
+    pub fn new(params: NoiseParams) -> Self {
+        Self::with_resolver(params, noise::default_resolver())
+    }
+
+    /// Create a RecordingBuilder with a custom crypto resolver.
+    pub fn with_resolver(params: NoiseParams, resolver: Box<CryptoResolver + 'builder>) -> Self {
+        RecordingBuilder {
+            params: params,
+            resolver: resolver,
+            s: None,
+            rs: None,
+            psks: vec![],
+            plog: None,
+            padding: None,
+        }
+    }
+
+    /// Specify a PSK (only used with `NoisePSK` base parameter)
+    pub fn psk(mut self, location: u8, key: &'builder [u8]) -> Self {
+        self.psks.retain(|&(l, _)| l != location);
+        self.psks.push((location, key));
+        self
+    }
+
+    /// Your static private key.
+    pub fn local_private_key(mut self, key: &'builder [u8]) -> Self {
+        self.s = Some(key);
+        self
+    }
+
+    /// Arbitrary data to be hashed in to the handshake hash value.
+    pub fn prologue(mut self, key: &'builder [u8]) -> Self {
+        self.plog = Some(key);
+        self
+    }
+
+    /// Pad transport messages according to `policy`.
+    pub fn padding(mut self, policy: PaddingPolicy) -> Self {
+        self.padding = Some(policy);
+        self
+    }
+
+    /// The responder's static public key.
+    pub fn remote_public_key(mut self, pub_key: &'builder [u8]) -> Self {
+        self.rs = Some(pub_key);
+        self
+    }
+
+    /// Build a recording session for the initiator.
+    pub fn build_initiator(self) -> Result<RecordingSession> {
+        self.build(true)
+    }
+
+    /// Build a recording session for the responder.
+    pub fn build_responder(self) -> Result<RecordingSession> {
+        self.build(false)
+    }
+
+    fn build(self, initiator: bool) -> Result<RecordingSession> {
+        let transcript = Transcript {
+            protocol_name: self.params.name.clone(),
+            initiator: initiator,
+            local_private_key: self.s.map(|k| k.to_vec().into()),
+            remote_public_key: self.rs.map(|k| k.to_vec().into()),
+            psks: self.psks.iter()
+                .map(|&(location, key)| TranscriptPsk { location: location, key: key.to_vec().into() })
+                .collect(),
+            prologue: self.plog.map(|p| p.to_vec().into()),
+            padding: self.padding.clone(),
+            random: vec![].into(),
+            steps: vec![],
+        };
+        let log = Rc::new(RefCell::new(vec![]));
+        let resolver = WrappedResolver { parent: self.resolver, log: Some(log.clone()), replay: None };
+        let session = build(transcript.builder(Box::new(resolver))?, initiator)?;
+        Ok(RecordingSession {
+            session: session,
+            transcript: transcript,
+            log: log,
+        })
+    }
+}
+
+/// A `Session` that records every call made on it. Read-only accessors are available through
+/// [`session`](#method.session).
+pub struct RecordingSession {
+    session: Session,
+    transcript: Transcript,
+    log: Rc<RefCell<Vec<u8>>>,
+}
+
+impl RecordingSession {
+    /// The underlying session.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// The transcript so far.
+    pub fn transcript(&self) -> Transcript {
+        let mut transcript = self.transcript.clone();
+        transcript.random = self.log.borrow().clone().into();
+        transcript
+    }
+
+    /// As [`Session::write_message`](../enum.Session.html#method.write_message).
+    pub fn write_message(&mut self, payload: &[u8], output: &mut [u8]) -> Result<usize> {
+        let result = self.session.write_message(payload, output);
+        self.transcript.steps.push(Step::Write {
+            payload: payload.to_vec().into(),
+            message: result.as_ref().ok().map(|&len| output[..len].to_vec().into()),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        result
+    }
+
+    /// As [`Session::read_message`](../enum.Session.html#method.read_message).
+    pub fn read_message(&mut self, input: &[u8], payload: &mut [u8]) -> Result<usize> {
+        let result = self.session.read_message(input, payload);
+        self.transcript.steps.push(Step::Read {
+            message: input.to_vec().into(),
+            payload: result.as_ref().ok().map(|&len| payload[..len].to_vec().into()),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        result
+    }
+
+    /// As [`Session::rekey`](../enum.Session.html#method.rekey).
+    pub fn rekey(&mut self, initiator: Option<&[u8]>, responder: Option<&[u8]>) -> Result<()> {
+        let result = self.session.rekey(initiator, responder);
+        self.transcript.steps.push(Step::Rekey {
+            initiator: initiator.map(|k| k.to_vec().into()),
+            responder: responder.map(|k| k.to_vec().into()),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        result
+    }
+
+    /// As [`Session::set_receiving_nonce`](../enum.Session.html#method.set_receiving_nonce).
+    pub fn set_receiving_nonce(&mut self, nonce: u64) -> Result<()> {
+        let result = self.session.set_receiving_nonce(nonce);
+        self.transcript.steps.push(Step::SetReceivingNonce {
+            nonce: nonce,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        result
+    }
+
+    /// As [`Session::into_transport_mode`](../enum.Session.html#method.into_transport_mode).
+    /// The transcript is lost if this fails, so take it first if the handshake may not be finished.
+    pub fn into_transport_mode(mut self) -> Result<Self> {
+        if !self.session.is_handshake_finished() {
+            bail!(ErrorKind::State(StateProblem::HandshakeNotFinished));
+        }
+        self.session = self.session.into_transport_mode()?;
+        self.transcript.steps.push(Step::IntoTransportMode);
+        Ok(self)
+    }
+}
+
+impl Transcript {
+    /// Deserialize a transcript from JSON.
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Serialize the transcript as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("transcripts always serialize")
+    }
+
+    fn builder<'a>(&'a self, resolver: Box<CryptoResolver + 'a>) -> Result<NoiseBuilder<'a>> {
+        let params: NoiseParams = self.protocol_name.parse()?;
+        let mut builder = NoiseBuilder::with_resolver(params, resolver);
+        for psk in &self.psks {
+            builder = builder.psk(psk.location, &psk.key);
+        }
+        if let Some(ref key) = self.local_private_key {
+            builder = builder.local_private_key(key);
+        }
+        if let Some(ref key) = self.remote_public_key {
+            builder = builder.remote_public_key(key);
+        }
+        if let Some(ref prologue) = self.prologue {
+            builder = builder.prologue(prologue);
+        }
+        if let Some(ref policy) = self.padding {
+            builder = builder.padding(policy.clone());
+        }
+        Ok(builder)
+    }
+
+    /// Rebuild the session with the default crypto resolver and re-run every step, stopping at
+    /// the first whose outcome differs from the recording.
+    pub fn replay(&self) -> ::std::result::Result<(), Divergence> {
+        self.replay_with_resolver(noise::default_resolver())
+    }
+
+    /// As [`replay`](#method.replay), with a custom crypto resolver. Its RNG is never used.
+    pub fn replay_with_resolver(&self, resolver: Box<CryptoResolver>) -> ::std::result::Result<(), Divergence> {
+        let state = Rc::new(RefCell::new(ReplayState { random: self.random.to_vec(), pos: 0, overrun: false }));
+        let resolver = WrappedResolver { parent: resolver, log: None, replay: Some(state.clone()) };
+        let mut session = self.builder(Box::new(resolver))
+            .and_then(|builder| build(builder, self.initiator))
+            .map_err(|e| Divergence { step: None, reason: e.to_string() })?;
+
+        let mut buf = vec![0u8; 65535];
+        for (i, step) in self.steps.iter().enumerate() {
+            let result = match *step {
+                Step::Write { ref payload, ref message, ref error } => {
+                    let actual = session.write_message(payload, &mut buf).map(|len| &buf[..len]).map_err(|e| e.to_string());
+                    compare("the message", (message, error), actual)
+                },
+                Step::Read { ref message, ref payload, ref error } => {
+                    let actual = session.read_message(message, &mut buf).map(|len| &buf[..len]).map_err(|e| e.to_string());
+                    compare("the payload", (payload, error), actual)
+                },
+                Step::Rekey { ref initiator, ref responder, ref error } => {
+                    let actual = session.rekey(initiator.as_ref().map(|k| &k[..]), responder.as_ref().map(|k| &k[..]));
+                    compare_error(error, actual.map_err(|e| e.to_string()))
+                },
+                Step::SetReceivingNonce { nonce, ref error } => {
+                    compare_error(error, session.set_receiving_nonce(nonce).map_err(|e| e.to_string()))
+                },
+                Step::IntoTransportMode => match session.into_transport_mode() {
+                    Ok(transport) => {
+                        session = transport;
+                        Ok(())
+                    },
+                    Err(e) => return Err(diverged(i, format!("failed with \"{}\", but the recording succeeded", e))),
+                },
+            };
+            result.map_err(|reason| diverged(i, format!("{}: {}", step.name(), reason)))?;
+            if state.borrow().overrun {
+                return Err(diverged(i, format!("{}: used more randomness than was recorded", step.name())));
+            }
+        }
+
+        let state = state.borrow();
+        if state.pos != state.random.len() {
+            return Err(diverged(self.steps.len(), "less randomness was used than was recorded".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_xx() -> (Transcript, Transcript) {
+        let params: NoiseParams = "Noise_XXpsk3_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let init_s = NoiseBuilder::new(params.clone()).generate_private_key().unwrap();
+        let resp_s = NoiseBuilder::new(params.clone()).generate_private_key().unwrap();
+        let psk = [3u8; 32];
+        let mut init = RecordingBuilder::new(params.clone())
+            .local_private_key(&init_s)
+            .psk(3, &psk)
+            .padding(PaddingPolicy::Random(32))
+            .build_initiator()
+            .unwrap();
+        let mut resp = RecordingBuilder::new(params)
+            .local_private_key(&resp_s)
+            .psk(3, &psk)
+            .padding(PaddingPolicy::Random(32))
+            .build_responder()
+            .unwrap();
+
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        let mut initiator_sends = true;
+        while !init.session().is_handshake_finished() {
+            let (send, recv) = if initiator_sends { (&mut init, &mut resp) } else { (&mut resp, &mut init) };
+            let len = send.write_message(b"handshake", &mut msg).unwrap();
+            recv.read_message(&msg[..len], &mut buf).unwrap();
+            initiator_sends = !initiator_sends;
+        }
+        let mut init = init.into_transport_mode().unwrap();
+        let mut resp = resp.into_transport_mode().unwrap();
+        let len = init.write_message(b"transport", &mut msg).unwrap();
+        msg[0] ^= 1;
+        assert!(resp.read_message(&msg[..len], &mut buf).is_err());
+        resp.set_receiving_nonce(1).unwrap();
+        resp.rekey(Some(&[9u8; 32]), None).unwrap();
+        (init.transcript(), resp.transcript())
+    }
+
+    #[test]
+    fn test_replay_reproduces_session() {
+        let (init, resp) = record_xx();
+        assert_eq!(init.replay(), Ok(()));
+        assert_eq!(resp.replay(), Ok(()));
+        assert_eq!(Transcript::from_json(&resp.to_json()).unwrap().replay(), Ok(()));
+        assert_eq!(resp.steps.len(), 3 + 1 + 3);
+    }
+
+    #[test]
+    fn test_replay_reports_first_divergence() {
+        let (_, mut resp) = record_xx();
+        if let Step::Read { ref mut message, .. } = resp.steps[2] {
+            let mut tampered = message.to_vec();
+            tampered[40] ^= 1;
+            *message = tampered.into();
+        }
+        let divergence = resp.replay().unwrap_err();
+        assert_eq!(divergence.step, Some(2));
+        assert!(divergence.reason.starts_with("read message: failed with"), "{}", divergence);
+
+        let (mut init, _) = record_xx();
+        let truncated = init.random.len() - 1;
+        init.random = init.random[..truncated].to_vec().into();
+        assert_eq!(init.replay().unwrap_err().step, Some(4));
+    }
+}
@@ -0,0 +1,300 @@
+//! Resolvers built out of other resolvers, for mixing crypto providers.
+
+use kat::{cipher_name, dh_name, hash_name};
+use noise::CryptoResolver;
+use params::{CipherChoice, DHChoice, HashChoice, NoiseParams};
+use types::{Cipher, Dh, Hash, Random};
+use std::prelude::v1::*;
+
+/// Resolves each primitive with `preferred`, falling back to `fallback` for the ones it doesn't
+/// provide.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "ring-resolver")] {
+/// # use snow::{NoiseBuilder, FallbackResolver, DefaultResolver, RingAcceleratedResolver};
+/// let resolver = FallbackResolver::new(RingAcceleratedResolver::new(), DefaultResolver);
+/// let noise = NoiseBuilder::with_resolver("Noise_NN_25519_AESGCM_SHA256".parse().unwrap(),
+///                                         Box::new(resolver));
+/// # }
+/// ```
+pub struct FallbackResolver<P: CryptoResolver, F: CryptoResolver> {
+    preferred: P,
+    fallback: F,
+}
+
+impl<P: CryptoResolver, F: CryptoResolver> FallbackResolver<P, F> {
+    pub fn new(preferred: P, fallback: F) -> Self {
+        FallbackResolver {
+            preferred: preferred,
+            fallback: fallback,
+        }
+    }
+}
+
+impl<P: CryptoResolver, F: CryptoResolver> CryptoResolver for FallbackResolver<P, F> {
+    fn resolve_rng(&self) -> Option<Box<Random>> {
+        self.preferred.resolve_rng().or_else(|| self.fallback.resolve_rng())
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+        self.preferred.resolve_dh(choice).or_else(|| self.fallback.resolve_dh(choice))
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+        self.preferred.resolve_hash(choice).or_else(|| self.fallback.resolve_hash(choice))
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+        self.preferred.resolve_cipher(choice).or_else(|| self.fallback.resolve_cipher(choice))
+    }
+
+    fn disallowed(&self, params: &NoiseParams) -> Option<String> {
+        self.preferred.disallowed(params).or_else(|| self.fallback.disallowed(params))
+    }
+}
+
+/// A resolver that takes each kind of primitive from its own resolver. Primitives without one
+/// aren't resolved, so wrap it in a `FallbackResolver` to fill the gaps. A protocol is
+/// disallowed if any of the configured resolvers disallows it.
+///
+/// # Examples
+///
+/// ```
+/// # use snow::{NoiseBuilder, ResolverBuilder, DefaultResolver};
+/// # struct HsmResolver;
+/// # impl snow::CryptoResolver for HsmResolver {
+/// #     fn resolve_rng(&self) -> Option<Box<snow::types::Random>> { DefaultResolver.resolve_rng() }
+/// #     fn resolve_dh(&self, c: &snow::params::DHChoice) -> Option<Box<snow::types::Dh>> { DefaultResolver.resolve_dh(c) }
+/// #     fn resolve_hash(&self, _: &snow::params::HashChoice) -> Option<Box<snow::types::Hash>> { None }
+/// #     fn resolve_cipher(&self, _: &snow::params::CipherChoice) -> Option<Box<snow::types::Cipher>> { None }
+/// # }
+/// use snow::CryptoResolver;
+///
+/// let resolver = ResolverBuilder::new()
+///     .rng(HsmResolver)
+///     .dh(HsmResolver)
+///     .cipher(DefaultResolver)
+///     .hash(DefaultResolver);
+/// let noise = NoiseBuilder::with_resolver("Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap(),
+///                                         Box::new(resolver))
+///     .build_initiator()
+///     .unwrap();
+/// ```
+pub struct ResolverBuilder<'a> {
+    rng:    Option<Box<CryptoResolver + 'a>>,
+    dh:     Option<Box<CryptoResolver + 'a>>,
+    cipher: Option<Box<CryptoResolver + 'a>>,
+    hash:   Option<Box<CryptoResolver + 'a>>,
+}
+
+impl<'a> ResolverBuilder<'a> {
+    pub fn new() -> Self {
+        ResolverBuilder {
+            rng: None,
+            dh: None,
+            cipher: None,
+            hash: None,
+        }
+    }
+
+    /// Take the RNG from `resolver`.
+    pub fn rng<R: CryptoResolver + 'a>(mut self, resolver: R) -> Self {
+        self.rng = Some(Box::new(resolver));
+        self
+    }
+
+    /// Take DH functions from `resolver`.
+    pub fn dh<R: CryptoResolver + 'a>(mut self, resolver: R) -> Self {
+        self.dh = Some(Box::new(resolver));
+        self
+    }
+
+    /// Take ciphers from `resolver`.
+    pub fn cipher<R: CryptoResolver + 'a>(mut self, resolver: R) -> Self {
+        self.cipher = Some(Box::new(resolver));
+        self
+    }
+
+    /// Take hashes from `resolver`.
+    pub fn hash<R: CryptoResolver + 'a>(mut self, resolver: R) -> Self {
+        self.hash = Some(Box::new(resolver));
+        self
+    }
+}
+
+impl<'a> Default for ResolverBuilder<'a> {
+    fn default() -> Self {
+        ResolverBuilder::new()
+    }
+}
+
+impl<'a> CryptoResolver for ResolverBuilder<'a> {
+    fn resolve_rng(&self) -> Option<Box<Random>> {
+        self.rng.as_ref().and_then(|r| r.resolve_rng())
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+        self.dh.as_ref().and_then(|r| r.resolve_dh(choice))
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+        self.hash.as_ref().and_then(|r| r.resolve_hash(choice))
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+        self.cipher.as_ref().and_then(|r| r.resolve_cipher(choice))
+    }
+
+    fn disallowed(&self, params: &NoiseParams) -> Option<String> {
+        [&self.rng, &self.dh, &self.cipher, &self.hash].iter()
+            .filter_map(|r| r.as_ref())
+            .filter_map(|r| r.disallowed(params))
+            .next()
+    }
+}
+
+/// Resolves only FIPS-approved primitives from `inner`: P-256, AES-GCM, and SHA-256 or SHA-512.
+/// Building a session for any other protocol fails with `InitStage::Disallowed`.
+///
+/// This restricts the algorithms, not the implementations: whether `inner` is a validated module
+/// is up to you.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "rustcrypto-resolver")] {
+/// # use snow::{NoiseBuilder, FipsResolver, RustCryptoResolver, InitStage, ErrorKind};
+/// let resolver = FipsResolver::new(RustCryptoResolver::new());
+/// let result = NoiseBuilder::with_resolver("Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap(),
+///                                          Box::new(&resolver))
+///     .build_initiator();
+/// match result {
+///     Err(snow::Error(ErrorKind::Init(InitStage::Disallowed(name)), _)) => assert_eq!(name, "25519"),
+///     _ => panic!("a non-FIPS protocol was built"),
+/// }
+///
+/// let noise = NoiseBuilder::with_resolver("Noise_NN_P256_AESGCM_SHA256".parse().unwrap(),
+///                                         Box::new(&resolver))
+///     .build_initiator()
+///     .unwrap();
+/// # }
+/// ```
+pub struct FipsResolver<R: CryptoResolver> {
+    inner: R,
+}
+
+impl<R: CryptoResolver> FipsResolver<R> {
+    pub fn new(inner: R) -> Self {
+        FipsResolver { inner: inner }
+    }
+}
+
+impl<R: CryptoResolver> CryptoResolver for FipsResolver<R> {
+    fn resolve_rng(&self) -> Option<Box<Random>> {
+        self.inner.resolve_rng()
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+        match *choice {
+            DHChoice::P256 => self.inner.resolve_dh(choice),
+            _              => None,
+        }
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+        match *choice {
+            HashChoice::SHA256 | HashChoice::SHA512 => self.inner.resolve_hash(choice),
+            _                                       => None,
+        }
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+        match *choice {
+            CipherChoice::AESGCM => self.inner.resolve_cipher(choice),
+            _                    => None,
+        }
+    }
+
+    fn disallowed(&self, params: &NoiseParams) -> Option<String> {
+        if params.dh != DHChoice::P256 {
+            Some(dh_name(&params.dh).to_owned())
+        } else if params.cipher != CipherChoice::AESGCM {
+            Some(cipher_name(&params.cipher).to_owned())
+        } else if params.hash != HashChoice::SHA256 && params.hash != HashChoice::SHA512 {
+            Some(hash_name(&params.hash).to_owned())
+        } else {
+            self.inner.disallowed(params)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use noise::DefaultResolver;
+    use wrappers::crypto_wrapper::HashBLAKE2s;
+
+    /// Resolves every hash to BLAKE2s, so it's visible which resolver answered.
+    struct Blake2sEverywhere;
+
+    impl CryptoResolver for Blake2sEverywhere {
+        fn resolve_rng(&self) -> Option<Box<Random>> { None }
+        fn resolve_dh(&self, _: &DHChoice) -> Option<Box<Dh>> { None }
+        fn resolve_hash(&self, _: &HashChoice) -> Option<Box<Hash>> { Some(Box::new(HashBLAKE2s::default())) }
+        fn resolve_cipher(&self, _: &CipherChoice) -> Option<Box<Cipher>> { None }
+    }
+
+    #[test]
+    fn test_fallback_prefers_first() {
+        let resolver = FallbackResolver::new(Blake2sEverywhere, DefaultResolver);
+        assert_eq!(resolver.resolve_hash(&HashChoice::SHA256).unwrap().name(), "BLAKE2s");
+        assert_eq!(resolver.resolve_dh(&DHChoice::Curve25519).unwrap().name(), "25519");
+        assert_eq!(resolver.resolve_cipher(&CipherChoice::AESGCM).unwrap().name(), "AESGCM");
+        assert!(resolver.resolve_rng().is_some());
+        assert!(resolver.resolve_dh(&DHChoice::Ed448).is_none());
+    }
+
+    #[test]
+    fn test_fips_refuses_unapproved() {
+        let resolver = FipsResolver::new(DefaultResolver);
+        assert!(resolver.resolve_dh(&DHChoice::Curve25519).is_none());
+        assert!(resolver.resolve_cipher(&CipherChoice::ChaChaPoly).is_none());
+        assert!(resolver.resolve_hash(&HashChoice::Blake2s).is_none());
+        assert_eq!(resolver.resolve_cipher(&CipherChoice::AESGCM).unwrap().name(), "AESGCM");
+        assert_eq!(resolver.resolve_hash(&HashChoice::SHA512).unwrap().name(), "SHA512");
+
+        let disallowed = |name: &str| resolver.disallowed(&name.parse().unwrap());
+        assert_eq!(disallowed("Noise_XX_P256_AESGCM_SHA256"), None);
+        assert_eq!(disallowed("Noise_XX_P256_AESGCM_SHA512"), None);
+        assert_eq!(disallowed("Noise_XX_25519_AESGCM_SHA256"), Some("25519".to_owned()));
+        assert_eq!(disallowed("Noise_XX_P256_ChaChaPoly_SHA256"), Some("ChaChaPoly".to_owned()));
+        assert_eq!(disallowed("Noise_XX_P256_AESGCM_BLAKE2b"), Some("BLAKE2b".to_owned()));
+
+        // Falling back can't sneak a disallowed primitive past the policy.
+        let fallback = FallbackResolver::new(FipsResolver::new(DefaultResolver), DefaultResolver);
+        assert_eq!(fallback.disallowed(&"Noise_NN_25519_AESGCM_SHA256".parse().unwrap()), Some("25519".to_owned()));
+
+        // Nor can taking only some primitives from it.
+        let builder = ResolverBuilder::new().dh(FipsResolver::new(DefaultResolver)).hash(DefaultResolver);
+        let fallback = FallbackResolver::new(builder, DefaultResolver);
+        assert_eq!(fallback.disallowed(&"Noise_NN_25519_AESGCM_SHA256".parse().unwrap()), Some("25519".to_owned()));
+        assert_eq!(fallback.disallowed(&"Noise_NN_P256_ChaChaPoly_SHA256".parse().unwrap()), Some("ChaChaPoly".to_owned()));
+        assert_eq!(fallback.disallowed(&"Noise_NN_P256_AESGCM_SHA256".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_builder_per_primitive() {
+        let resolver = ResolverBuilder::new()
+            .hash(Blake2sEverywhere)
+            .cipher(&DefaultResolver);
+        assert_eq!(resolver.resolve_hash(&HashChoice::SHA512).unwrap().name(), "BLAKE2s");
+        assert_eq!(resolver.resolve_cipher(&CipherChoice::ChaChaPoly).unwrap().name(), "ChaChaPoly");
+        assert!(resolver.resolve_dh(&DHChoice::Curve25519).is_none());
+        assert!(resolver.resolve_rng().is_none());
+
+        let filled = FallbackResolver::new(resolver, DefaultResolver);
+        assert_eq!(filled.resolve_dh(&DHChoice::Curve25519).unwrap().name(), "25519");
+    }
+}
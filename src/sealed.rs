@@ -0,0 +1,192 @@
+//! One-shot "sealed box" encryption to a recipient's static public key, built on the one-way
+//! `N`, `X` and `K` handshake patterns.
+//!
+//! A sealed box is the one-way handshake message, followed by as many transport messages as are
+//! needed to carry the rest of the payload. Each message is prefixed by its 2-byte big-endian
+//! length. The total payload length is encrypted inside the handshake message, so a box that has
+//! been truncated or extended fails to open.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::NoiseBuilder;
+//! # use snow::sealed;
+//! # use snow::types::Dh;
+//! # use snow::wrappers::crypto_wrapper::Dh25519;
+//! let params = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+//! let recipient_private = NoiseBuilder::new("Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap())
+//!                                      .generate_private_key()
+//!                                      .unwrap();
+//! let mut recipient = Dh25519::default();
+//! recipient.set(&recipient_private);
+//!
+//! let sealed = sealed::seal(&params, recipient.pubkey(), None, b"attack at dawn").unwrap();
+//! let (payload, sender) = sealed::open(&params, &recipient_private, &sealed).unwrap();
+//! assert_eq!(payload, b"attack at dawn");
+//! assert!(sender.is_none());
+//! ```
+
+use byteorder::{ByteOrder, BigEndian};
+use constants::{MAXDHLEN, MAXMSGLEN, TAGLEN};
+use error::{ErrorKind, Result, InitStage, InputProblem, MessagePart};
+use noise::NoiseBuilder;
+use params::NoiseParams;
+
+/// The length of the prefix in front of every message in a sealed box.
+const LENGTH_PREFIX_LEN: usize = 2;
+
+/// The length of the total payload length carried in the handshake message.
+const TOTAL_LEN_LEN: usize = 8;
+
+/// The most payload that fits in the handshake message, leaving room for the largest handshake
+/// tokens any one-way pattern uses (`e`, and an encrypted `s`).
+const FIRST_CHUNK_LEN: usize = MAXMSGLEN - (2 * MAXDHLEN + 2 * TAGLEN) - TOTAL_LEN_LEN;
+
+/// The most payload that fits in each transport message.
+const CHUNK_LEN: usize = MAXMSGLEN - TAGLEN;
+
+/// Encrypt `payload` to `recipient_public`.
+///
+/// `sender_private` is your static private key, which is required for the `X` and `K` patterns and
+/// ignored for `N`.
+///
+/// # Errors
+///
+/// Will result in `ErrorKind::Init(InitStage::ValidateHandshakePattern)` if `params` isn't a
+/// one-way pattern, or `ErrorKind::Prereq` if `sender_private` is needed but missing.
+pub fn seal(params: &NoiseParams,
+            recipient_public: &[u8],
+            sender_private: Option<&[u8]>,
+            payload: &[u8]) -> Result<Vec<u8>> {
+    if !params.handshake.pattern.is_oneway() {
+        bail!(ErrorKind::Init(InitStage::ValidateHandshakePattern));
+    }
+
+    let mut builder = NoiseBuilder::new(params.clone()).remote_public_key(recipient_public);
+    if let Some(key) = sender_private {
+        if params.handshake.pattern.needs_local_static_key(true) {
+            builder = builder.local_private_key(key);
+        }
+    }
+    let mut session = builder.build_initiator()?;
+
+    let first_len = if payload.len() < FIRST_CHUNK_LEN { payload.len() } else { FIRST_CHUNK_LEN };
+    let mut first = vec![0u8; TOTAL_LEN_LEN + first_len];
+    BigEndian::write_u64(&mut first[..TOTAL_LEN_LEN], payload.len() as u64);
+    first[TOTAL_LEN_LEN..].copy_from_slice(&payload[..first_len]);
+
+    let mut buf = vec![0u8; MAXMSGLEN];
+    let mut sealed = Vec::new();
+    let len = session.write_message(&first, &mut buf)?;
+    push_message(&mut sealed, &buf[..len]);
+
+    let mut session = session.into_transport_mode()?;
+    for chunk in payload[first_len..].chunks(CHUNK_LEN) {
+        let len = session.write_message(chunk, &mut buf)?;
+        push_message(&mut sealed, &buf[..len]);
+    }
+    Ok(sealed)
+}
+
+/// Decrypt a sealed box with your static private key, returning the payload and, for the `X`
+/// pattern, the sender's static public key.
+///
+/// The sender's key must be checked against the keys you expect to hear from, since `X` boxes
+/// can be sealed by anyone. The `K` pattern requires knowing the sender's key up front, so use
+/// [`open_from`](fn.open_from.html) for it instead.
+///
+/// # Errors
+///
+/// Will result in `ErrorKind::Input` if the box is malformed, truncated or extended, and
+/// `ErrorKind::Decrypt`, naming the message, if any message doesn't decrypt.
+pub fn open(params: &NoiseParams,
+            recipient_private: &[u8],
+            sealed: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+    open_inner(params, recipient_private, None, sealed)
+}
+
+/// Decrypt a sealed box that must have been sealed by `sender_public`.
+///
+/// This is required for the `K` pattern, and for `X` rejects boxes from any other sender.
+///
+/// # Errors
+///
+/// As [`open`](fn.open.html), and `ErrorKind::Decrypt` if an `X` box was sealed by someone else.
+pub fn open_from(params: &NoiseParams,
+                 recipient_private: &[u8],
+                 sender_public: &[u8],
+                 sealed: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+    open_inner(params, recipient_private, Some(sender_public), sealed)
+}
+
+fn open_inner(params: &NoiseParams,
+              recipient_private: &[u8],
+              sender_public: Option<&[u8]>,
+              sealed: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+    if !params.handshake.pattern.is_oneway() {
+        bail!(ErrorKind::Init(InitStage::ValidateHandshakePattern));
+    }
+
+    let mut builder = NoiseBuilder::new(params.clone()).local_private_key(recipient_private);
+    if let Some(key) = sender_public {
+        if params.handshake.pattern.need_known_remote_pubkey(false) {
+            builder = builder.remote_public_key(key);
+        }
+    }
+    let mut session = builder.build_responder()?;
+
+    let mut buf = vec![0u8; MAXMSGLEN];
+    let mut remaining = sealed;
+    let len = session.read_message(next_message(&mut remaining)?, &mut buf)?;
+    if len < TOTAL_LEN_LEN {
+        bail!(ErrorKind::Input(InputProblem::Malformed("sealed box")));
+    }
+    let total_len = BigEndian::read_u64(&buf[..TOTAL_LEN_LEN]);
+    if total_len > sealed.len() as u64 {
+        bail!(ErrorKind::Input(InputProblem::Malformed("sealed box")));
+    }
+    let mut payload = Vec::with_capacity(total_len as usize);
+    payload.extend_from_slice(&buf[TOTAL_LEN_LEN..len]);
+
+    let sender = session.get_remote_static().map(|k| k.to_vec());
+    if let (Some(expected), Some(actual)) = (sender_public, sender.as_ref()) {
+        if expected != &actual[..] {
+            bail!(ErrorKind::Decrypt(MessagePart::Other("sender's static key")));
+        }
+    }
+
+    let mut session = session.into_transport_mode()?;
+    while !remaining.is_empty() {
+        let len = session.read_message(next_message(&mut remaining)?, &mut buf)?;
+        payload.extend_from_slice(&buf[..len]);
+        if payload.len() as u64 > total_len {
+            bail!(ErrorKind::Input(InputProblem::Malformed("sealed box")));
+        }
+    }
+    if payload.len() as u64 != total_len {
+        bail!(ErrorKind::Input(InputProblem::Malformed("sealed box")));
+    }
+
+    let sender = if params.handshake.pattern.needs_local_static_key(true) { sender } else { None };
+    Ok((payload, sender))
+}
+
+fn push_message(sealed: &mut Vec<u8>, message: &[u8]) {
+    let mut prefix = [0u8; LENGTH_PREFIX_LEN];
+    BigEndian::write_u16(&mut prefix, message.len() as u16);
+    sealed.extend_from_slice(&prefix);
+    sealed.extend_from_slice(message);
+}
+
+fn next_message<'a>(remaining: &mut &'a [u8]) -> Result<&'a [u8]> {
+    if remaining.len() < LENGTH_PREFIX_LEN {
+        bail!(ErrorKind::Input(InputProblem::Malformed("sealed box")));
+    }
+    let len = BigEndian::read_u16(&remaining[..LENGTH_PREFIX_LEN]) as usize;
+    if remaining.len() < LENGTH_PREFIX_LEN + len {
+        bail!(ErrorKind::Input(InputProblem::Malformed("sealed box")));
+    }
+    let message = &remaining[LENGTH_PREFIX_LEN..LENGTH_PREFIX_LEN + len];
+    *remaining = &remaining[LENGTH_PREFIX_LEN + len..];
+    Ok(message)
+}
@@ -0,0 +1,256 @@
+//! Storage for long-term secrets: static private keys and PSKs.
+//!
+//! A `SecretBuf` starts out as an inline buffer that is zeroized when dropped, which is all an
+//! ephemeral key needs. With the `secure-memory` feature (on Unix), `lock` moves the value into
+//! its own `mmap`ed pages, which are `mlock`ed so they're never swapped out, excluded from core
+//! dumps with `MADV_DONTDUMP` (on Linux), and fenced by inaccessible guard pages on either side.
+//! The builder does this for static keys and PSKs only. The locked copy is still zeroized before
+//! its pages are unlocked and unmapped.
+
+use std::ops::{Deref, DerefMut};
+use utils::{Zeroize, Zeroizing};
+
+/// A secret that is zeroized on drop, and can be moved into locked memory with `lock`.
+pub struct SecretBuf<T: Zeroize> {
+    inline: Zeroizing<T>,
+    #[cfg(all(feature = "secure-memory", unix))]
+    locked: Option<locked::Locked<T>>,
+}
+
+impl<T: Zeroize> SecretBuf<T> {
+    #[cfg(not(all(feature = "secure-memory", unix)))]
+    pub fn new(value: T) -> Self {
+        SecretBuf { inline: Zeroizing::new(value) }
+    }
+
+    #[cfg(all(feature = "secure-memory", unix))]
+    pub fn new(value: T) -> Self {
+        SecretBuf { inline: Zeroizing::new(value), locked: None }
+    }
+}
+
+impl<T: Zeroize + Default + Copy> SecretBuf<T> {
+    /// Without `secure-memory` there's nowhere better to keep the value, so this does nothing.
+    #[cfg(not(all(feature = "secure-memory", unix)))]
+    pub fn lock(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+
+    /// Move the value into locked memory, wiping the inline copy.
+    ///
+    /// Returns `Err(())` if the memory can't be mapped or locked, e.g. because `RLIMIT_MEMLOCK`
+    /// has been reached, in which case the value stays inline.
+    #[cfg(all(feature = "secure-memory", unix))]
+    pub fn lock(&mut self) -> Result<(), ()> {
+        if self.locked.is_none() {
+            let mut locked = locked::Locked::try_new(T::default()).map_err(|_| ())?;
+            *locked = *self.inline;
+            self.inline.zeroize();
+            self.locked = Some(locked);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Zeroize + Default> Default for SecretBuf<T> {
+    fn default() -> Self {
+        SecretBuf::new(T::default())
+    }
+}
+
+impl<T: Zeroize> Deref for SecretBuf<T> {
+    type Target = T;
+
+    #[cfg(not(all(feature = "secure-memory", unix)))]
+    fn deref(&self) -> &T {
+        &self.inline
+    }
+
+    #[cfg(all(feature = "secure-memory", unix))]
+    fn deref(&self) -> &T {
+        match self.locked {
+            Some(ref locked) => locked,
+            None => &self.inline,
+        }
+    }
+}
+
+impl<T: Zeroize> DerefMut for SecretBuf<T> {
+    #[cfg(not(all(feature = "secure-memory", unix)))]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inline
+    }
+
+    #[cfg(all(feature = "secure-memory", unix))]
+    fn deref_mut(&mut self) -> &mut T {
+        match self.locked {
+            Some(ref mut locked) => locked,
+            None => &mut self.inline,
+        }
+    }
+}
+
+#[cfg(all(feature = "secure-memory", unix))]
+mod locked {
+    use libc;
+    use std::io;
+    use std::mem;
+    use std::ops::{Deref, DerefMut};
+    use std::ptr;
+    use utils::Zeroize;
+
+    /// A value in locked, guard-paged memory that is excluded from core dumps.
+    pub struct Locked<T: Zeroize> {
+        value: *mut T,
+        region: *mut u8,
+        region_len: usize,
+    }
+
+    // A `Locked` owns its value exclusively, just like a `Box`.
+    unsafe impl<T: Zeroize + Send> Send for Locked<T> {}
+    unsafe impl<T: Zeroize + Sync> Sync for Locked<T> {}
+
+    fn page_size() -> usize {
+        unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn exclude_from_dumps(region: *mut u8, len: usize) -> libc::c_int {
+        libc::madvise(region as *mut libc::c_void, len, libc::MADV_DONTDUMP)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    unsafe fn exclude_from_dumps(_region: *mut u8, _len: usize) -> libc::c_int {
+        0
+    }
+
+    impl<T: Zeroize> Locked<T> {
+        /// Move `value` into freshly mapped, locked memory, or return the OS error.
+        pub fn try_new(value: T) -> io::Result<Self> {
+            let page = page_size();
+            let data_len = (mem::size_of::<T>().max(1) + page - 1) & !(page - 1);
+            let region_len = data_len + 2 * page;
+            unsafe {
+                let region = libc::mmap(ptr::null_mut(), region_len, libc::PROT_NONE,
+                                        libc::MAP_PRIVATE | libc::MAP_ANON, -1, 0);
+                if region == libc::MAP_FAILED {
+                    return Err(io::Error::last_os_error());
+                }
+                let region = region as *mut u8;
+                let data = region.add(page);
+                if libc::mprotect(data as *mut libc::c_void, data_len, libc::PROT_READ | libc::PROT_WRITE) != 0
+                    || libc::mlock(data as *const libc::c_void, data_len) != 0
+                    || exclude_from_dumps(region, region_len) != 0
+                {
+                    let err = io::Error::last_os_error();
+                    libc::munmap(region as *mut libc::c_void, region_len);
+                    return Err(err);
+                }
+                let value_ptr = data as *mut T;
+                ptr::write(value_ptr, value);
+                Ok(Locked {
+                    value: value_ptr,
+                    region: region,
+                    region_len: region_len,
+                })
+            }
+        }
+    }
+
+    impl<T: Zeroize> Deref for Locked<T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            unsafe { &*self.value }
+        }
+    }
+
+    impl<T: Zeroize> DerefMut for Locked<T> {
+        fn deref_mut(&mut self) -> &mut T {
+            unsafe { &mut *self.value }
+        }
+    }
+
+    impl<T: Zeroize> Drop for Locked<T> {
+        fn drop(&mut self) {
+            unsafe {
+                (*self.value).zeroize();
+                ptr::drop_in_place(self.value);
+                let page = page_size();
+                libc::munlock(self.region.add(page) as *const libc::c_void, self.region_len - 2 * page);
+                libc::munmap(self.region as *mut libc::c_void, self.region_len);
+            }
+        }
+    }
+
+    #[cfg(all(test, target_os = "linux"))]
+    mod tests {
+        use super::*;
+        use std::fs::File;
+        use std::io::Read;
+
+        /// The permissions and `VmFlags` of the mapping containing `addr`, from `/proc/self/smaps`.
+        fn mapping(addr: usize) -> (String, String) {
+            let mut smaps = String::new();
+            File::open("/proc/self/smaps").unwrap().read_to_string(&mut smaps).unwrap();
+            let mut current = None;
+            for line in smaps.lines() {
+                let mut fields = line.split_whitespace();
+                let first = fields.next().unwrap_or("");
+                if let Some(dash) = first.find('-') {
+                    if let (Ok(start), Ok(end)) = (usize::from_str_radix(&first[..dash], 16),
+                                                   usize::from_str_radix(&first[dash + 1..], 16)) {
+                        current = if start <= addr && addr < end {
+                            Some(fields.next().unwrap().to_owned())
+                        } else {
+                            None
+                        };
+                        continue;
+                    }
+                }
+                if first == "VmFlags:" {
+                    if let Some(perms) = current.take() {
+                        return (perms, line.to_owned());
+                    }
+                }
+            }
+            panic!("no mapping contains {:x}", addr);
+        }
+
+        #[test]
+        fn test_locked_roundtrip() {
+            let mut secret = Locked::try_new([0u8; 32]).unwrap();
+            secret[..].copy_from_slice(&[0xaa; 32]);
+            assert_eq!(*secret, [0xaa; 32]);
+        }
+
+        #[test]
+        fn test_secret_buf_locks_on_request() {
+            let mut secret = ::secmem::SecretBuf::new([0xaau8; 32]);
+            let inline = &*secret as *const _ as usize;
+            secret.lock().unwrap();
+            let addr = &*secret as *const _ as usize;
+            assert!(addr != inline);
+            assert_eq!(*secret, [0xaa; 32]);
+            let (_, flags) = mapping(addr);
+            assert!(flags.split_whitespace().any(|f| f == "lo"), "not locked: {}", flags);
+        }
+
+        #[test]
+        fn test_locked_memory_flags() {
+            let secret = Locked::try_new([0xaau8; 32]).unwrap();
+            let addr = &*secret as *const _ as usize;
+            let page = page_size();
+
+            let (perms, flags) = mapping(addr);
+            assert!(perms.starts_with("rw"));
+            assert!(flags.split_whitespace().any(|f| f == "lo"), "not locked: {}", flags);
+            assert!(flags.split_whitespace().any(|f| f == "dd"), "not excluded from dumps: {}", flags);
+
+            let (before, _) = mapping(addr - page);
+            let (after, _) = mapping(addr + page);
+            assert!(before.starts_with("---"));
+            assert!(after.starts_with("---"));
+        }
+    }
+}
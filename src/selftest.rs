@@ -0,0 +1,199 @@
+//! Known-answer self-tests, for running before any crypto is used.
+
+use error::{ErrorKind, Result};
+use kat::{self, cipher_name, dh_name, hash_name, exchange, CIPHERS, DHS, HASHES};
+use noise::{CryptoResolver, NoiseBuilder};
+use params::NoiseParams;
+use std::prelude::v1::*;
+
+/// The outcome of [`self_test`](fn.self_test.html).
+#[derive(Clone, Debug, Default)]
+pub struct SelfTestReport {
+    /// The tests that passed.
+    pub passed: Vec<String>,
+    /// The tests that couldn't be run, and why.
+    pub skipped: Vec<(String, String)>,
+    /// The tests that failed, and why.
+    pub failures: Vec<(String, String)>,
+}
+
+impl SelfTestReport {
+    fn record(&mut self, test: String, result: ::std::result::Result<(), String>) {
+        match result {
+            Ok(())      => self.passed.push(test),
+            Err(reason) => self.failures.push((test, reason)),
+        }
+    }
+}
+
+/// Run known-answer tests against every primitive `resolver` supports, and one full `Noise_XX`
+/// handshake with its own RNG.
+///
+/// The hash tests cover `hmac` and `hkdf` as well as the digest. Panics in the resolver's
+/// primitives are caught and reported as failures.
+///
+/// # Errors
+///
+/// Will result in `ErrorKind::SelfTest`, carrying the full report, if any test fails.
+///
+/// # Examples
+///
+/// ```
+/// # use snow::DefaultResolver;
+/// let report = snow::self_test(&DefaultResolver).unwrap();
+/// assert!(report.failures.is_empty());
+/// ```
+pub fn self_test(resolver: &CryptoResolver) -> Result<SelfTestReport> {
+    let mut report = SelfTestReport::default();
+
+    for dh in &DHS {
+        let test = format!("{} known answers", dh_name(dh));
+        match resolver.resolve_dh(dh) {
+            Some(mut imp) => match kat::check_dh(dh, &mut *imp) {
+                Some(result) => report.record(test, result),
+                None         => report.skipped.push((test, "none available".to_owned())),
+            },
+            None => report.skipped.push((test, "not supported".to_owned())),
+        }
+    }
+    for hash in &HASHES {
+        let test = format!("{} known answers", hash_name(hash));
+        match resolver.resolve_hash(hash) {
+            Some(mut imp) => report.record(test, kat::check_hash(hash, &mut *imp)),
+            None          => report.skipped.push((test, "not supported".to_owned())),
+        }
+    }
+    for cipher in &CIPHERS {
+        let test = format!("{} known answers", cipher_name(cipher));
+        match resolver.resolve_cipher(cipher) {
+            Some(mut imp) => report.record(test, kat::check_cipher(cipher, &mut *imp)),
+            None          => report.skipped.push((test, "not supported".to_owned())),
+        }
+    }
+
+    match handshake_params(resolver) {
+        Some(params) => {
+            let test = format!("{} handshake", params.name);
+            report.record(test, kat::guard(|| handshake(resolver, &params)));
+        },
+        None => report.skipped.push(("Noise_XX handshake".to_owned(), "no supported DH, cipher and hash".to_owned())),
+    }
+
+    if report.failures.is_empty() {
+        Ok(report)
+    } else {
+        bail!(ErrorKind::SelfTest(report))
+    }
+}
+
+/// The first `Noise_XX` protocol `resolver` can run.
+fn handshake_params(resolver: &CryptoResolver) -> Option<NoiseParams> {
+    let dh = DHS.iter().find(|dh| resolver.resolve_dh(dh).is_some())?;
+    let cipher = CIPHERS.iter().find(|cipher| resolver.resolve_cipher(cipher).is_some())?;
+    let hash = HASHES.iter().find(|hash| resolver.resolve_hash(hash).is_some())?;
+    format!("Noise_XX_{}_{}_{}", dh_name(dh), cipher_name(cipher), hash_name(hash)).parse().ok()
+}
+
+fn handshake(resolver: &CryptoResolver, params: &NoiseParams) -> ::std::result::Result<(), String> {
+    let builder = NoiseBuilder::with_resolver(params.clone(), Box::new(resolver));
+    let init_s = builder.generate_private_key().map_err(|e| e.to_string())?;
+    let resp_s = builder.generate_private_key().map_err(|e| e.to_string())?;
+    let public = |private: &[u8]| -> ::std::result::Result<Vec<u8>, String> {
+        let mut dh = resolver.resolve_dh(&params.dh).ok_or("DH not supported")?;
+        dh.set(private);
+        Ok(dh.pubkey().to_vec())
+    };
+
+    let mut init = NoiseBuilder::with_resolver(params.clone(), Box::new(resolver))
+        .local_private_key(&init_s)
+        .build_initiator()
+        .map_err(|e| e.to_string())?;
+    let mut resp = NoiseBuilder::with_resolver(params.clone(), Box::new(resolver))
+        .local_private_key(&resp_s)
+        .build_responder()
+        .map_err(|e| e.to_string())?;
+
+    exchange(&mut init, &mut resp, b"")?;
+    exchange(&mut resp, &mut init, b"")?;
+    exchange(&mut init, &mut resp, b"")?;
+    if init.get_remote_static() != Some(&public(&resp_s)?[..])
+        || resp.get_remote_static() != Some(&public(&init_s)?[..]) {
+        return Err("static keys weren't exchanged".to_owned());
+    }
+
+    let mut init = init.into_transport_mode().map_err(|e| e.to_string())?;
+    let mut resp = resp.into_transport_mode().map_err(|e| e.to_string())?;
+    exchange(&mut init, &mut resp, b"self-test")?;
+    exchange(&mut resp, &mut init, b"self-test")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::{Error, ErrorKind};
+    use noise::DefaultResolver;
+    use params::{CipherChoice, DHChoice, HashChoice};
+    use types::{Cipher, Dh, Hash, Random};
+
+    /// Hands out a cipher that fails every decryption.
+    struct BrokenCipherResolver;
+
+    struct BrokenCipher;
+
+    impl Cipher for BrokenCipher {
+        fn name(&self) -> &'static str {
+            "ChaChaPoly"
+        }
+
+        fn set(&mut self, _key: &[u8]) {}
+
+        fn encrypt(&self, _nonce: u64, _authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+            out[..plaintext.len()].copy_from_slice(plaintext);
+            plaintext.len() + 16
+        }
+
+        fn decrypt(&self, _nonce: u64, _authtext: &[u8], _ciphertext: &[u8], _out: &mut [u8]) -> ::std::result::Result<usize, ()> {
+            Err(())
+        }
+    }
+
+    impl CryptoResolver for BrokenCipherResolver {
+        fn resolve_rng(&self) -> Option<Box<Random>> {
+            DefaultResolver.resolve_rng()
+        }
+
+        fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+            DefaultResolver.resolve_dh(choice)
+        }
+
+        fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+            DefaultResolver.resolve_hash(choice)
+        }
+
+        fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+            match *choice {
+                CipherChoice::ChaChaPoly => Some(Box::new(BrokenCipher)),
+                _                        => None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_resolver_passes() {
+        let report = self_test(&DefaultResolver).unwrap();
+        assert_eq!(report.passed.len(), 1 + 4 + 2 + 1);
+        assert_eq!(report.skipped.len(), 3);
+    }
+
+    #[test]
+    fn test_broken_cipher_reported() {
+        match self_test(&BrokenCipherResolver) {
+            Err(Error(ErrorKind::SelfTest(report), _)) => {
+                let failed: Vec<&str> = report.failures.iter().map(|&(ref test, _)| &test[..]).collect();
+                assert_eq!(failed, vec!["ChaChaPoly known answers", "Noise_XX_25519_ChaChaPoly_SHA256 handshake"]);
+            },
+            _ => panic!("broken cipher wasn't caught"),
+        }
+    }
+}
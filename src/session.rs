@@ -3,6 +3,7 @@ use handshakestate::HandshakeState;
 #[cfg(feature = "nightly")] use std::convert::{TryFrom, TryInto};
 #[cfg(not(feature = "nightly"))] use utils::{TryFrom, TryInto};
 use transportstate::*;
+use types::{Cipher, Dh, Hash, Random};
 
 /// A state machine for the entire Noise session.
 ///
@@ -48,6 +49,15 @@ impl Session {
         }
     }
 
+    /// The remote party's static public key, if it was provided to the builder or has been
+    /// received during the handshake.
+    pub fn get_remote_static(&self) -> Option<&[u8]> {
+        match *self {
+            Session::Handshake(ref state) => state.get_remote_static(),
+            Session::Transport(ref state) => state.get_remote_static(),
+        }
+    }
+
     /// Construct a message from `payload` (and pending handshake tokens if in handshake state),
     /// and writes it to the `output` buffer.
     ///
@@ -83,6 +93,20 @@ impl Session {
         }
     }
 
+    /// With BOLT 8 framing, decrypt the length prefix of the next transport message and return the
+    /// length of the rest of it, which can then be passed alone to `read_message`. Useful for
+    /// reading from a stream.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `NoiseError::StateError` if not in transport mode, or not using BOLT 8.
+    pub fn read_bolt8_length(&mut self, header: &[u8]) -> Result<usize> {
+        match *self {
+            Session::Handshake(_) => Err(ErrorKind::State(StateProblem::HandshakeNotFinished).into()),
+            Session::Transport(ref mut state) => state.read_bolt8_length(header),
+        }
+    }
+
     /// Set a new key for the one or both of the initiator-egress and responder-egress symmetric ciphers.
     ///
     /// # Errors
@@ -180,13 +204,11 @@ impl Into<Session> for HandshakeState {
     }
 }
 
-impl TryFrom<HandshakeState> for TransportState {
+impl<D: Dh, C: Cipher, H: Hash, R: Random> TryFrom<HandshakeState<D, C, H, R>> for TransportState<C, R> {
     type Error = Error;
 
-    fn try_from(old: HandshakeState) -> Result<Self> {
-        let initiator = old.is_initiator();
-        let (cipherstates, handshake) = old.finish()?;
-        Ok(TransportState::new(cipherstates, handshake.pattern, initiator))
+    fn try_from(old: HandshakeState<D, C, H, R>) -> Result<Self> {
+        old.finish()
     }
 }
 
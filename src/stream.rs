@@ -0,0 +1,309 @@
+//! Streaming encryption to a recipient's static public key, for payloads too large to hold in
+//! memory.
+//!
+//! A stream starts with a versioned magic header and the message of a one-way (`N`, `X` or `K`)
+//! handshake, followed by fixed-size transport message chunks. Every chunk's plaintext starts with
+//! a flag byte marking whether it is the final chunk, so (along with the transport nonces) chunks
+//! can't be reordered, dropped, or have data truncated or appended without being detected.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::NoiseBuilder;
+//! # use snow::stream::{Encryptor, Decryptor};
+//! # use snow::types::Dh;
+//! # use snow::wrappers::crypto_wrapper::Dh25519;
+//! # use std::io::{Read, Write};
+//! let params = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+//! # let recipient_private = NoiseBuilder::new("Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap())
+//! #                                     .generate_private_key()
+//! #                                     .unwrap();
+//! # let mut recipient = Dh25519::default();
+//! # recipient.set(&recipient_private);
+//! let mut encryptor = Encryptor::new(&params, recipient.pubkey(), None, Vec::new()).unwrap();
+//! encryptor.write_all(b"a very large backup").unwrap();
+//! let encrypted = encryptor.finish().unwrap();
+//!
+//! let mut decryptor = Decryptor::new(&params, &recipient_private, &encrypted[..]).unwrap();
+//! let mut backup = Vec::new();
+//! decryptor.read_to_end(&mut backup).unwrap();
+//! assert_eq!(backup, b"a very large backup");
+//! ```
+
+use byteorder::{ByteOrder, BigEndian};
+use constants::{MAXMSGLEN, TAGLEN};
+use error::{self, ErrorKind, InitStage, InputProblem, MessagePart};
+use noise::NoiseBuilder;
+use params::NoiseParams;
+use session::Session;
+use std::io::{self, Read, Write};
+
+/// The magic bytes at the start of every stream.
+pub const MAGIC: &'static [u8] = b"snowstrm";
+
+/// The version of the stream format written by `Encryptor`.
+pub const VERSION: u8 = 1;
+
+/// The amount of plaintext carried by every chunk but the last.
+pub const CHUNK_LEN: usize = MAXMSGLEN - TAGLEN - 1;
+
+const FLAG_MORE: u8 = 0;
+const FLAG_FINAL: u8 = 1;
+
+fn prologue() -> Vec<u8> {
+    let mut prologue = MAGIC.to_vec();
+    prologue.push(VERSION);
+    prologue
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+fn to_io_error(err: error::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+/// Encrypts everything written to it into a stream for a single recipient.
+///
+/// [`finish`](#method.finish) must be called once all of the plaintext has been written, or the
+/// stream will fail to decrypt as truncated.
+pub struct Encryptor<W: Write> {
+    inner: W,
+    session: Session,
+    // The plaintext of the pending chunk, with its flag byte at the front.
+    buf: Vec<u8>,
+    message: Vec<u8>,
+}
+
+impl<W: Write> Encryptor<W> {
+    /// Start a stream to `recipient_public`, writing the header to `inner`.
+    ///
+    /// `sender_private` is your static private key, which is required for the `X` and `K` patterns
+    /// and ignored for `N`.
+    pub fn new(params: &NoiseParams,
+               recipient_public: &[u8],
+               sender_private: Option<&[u8]>,
+               mut inner: W) -> error::Result<Self> {
+        if !params.handshake.pattern.is_oneway() {
+            bail!(ErrorKind::Init(InitStage::ValidateHandshakePattern));
+        }
+
+        let prologue = prologue();
+        let mut builder = NoiseBuilder::new(params.clone())
+            .remote_public_key(recipient_public)
+            .prologue(&prologue);
+        if let Some(key) = sender_private {
+            if params.handshake.pattern.needs_local_static_key(true) {
+                builder = builder.local_private_key(key);
+            }
+        }
+        let mut session = builder.build_initiator()?;
+
+        let mut message = vec![0u8; MAXMSGLEN];
+        let len = session.write_message(&[], &mut message)?;
+        let mut header = prologue;
+        let mut len_bytes = [0u8; 2];
+        BigEndian::write_u16(&mut len_bytes, len as u16);
+        header.extend_from_slice(&len_bytes);
+        header.extend_from_slice(&message[..len]);
+        inner.write_all(&header)?;
+
+        Ok(Encryptor {
+            inner: inner,
+            session: session.into_transport_mode()?,
+            buf: vec![FLAG_MORE],
+            message: message,
+        })
+    }
+
+    fn write_chunk(&mut self, flag: u8) -> io::Result<()> {
+        self.buf[0] = flag;
+        let len = self.session.write_message(&self.buf, &mut self.message).map_err(to_io_error)?;
+        self.buf.truncate(1);
+        self.inner.write_all(&self.message[..len])
+    }
+
+    /// Write the final chunk, and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_chunk(FLAG_FINAL)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Encryptor<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // A full chunk is only written once more data arrives, since the last chunk must be
+        // written by `finish` with the final flag.
+        if self.buf.len() == CHUNK_LEN + 1 && !data.is_empty() {
+            self.write_chunk(FLAG_MORE)?;
+        }
+        let len = ::std::cmp::min(CHUNK_LEN + 1 - self.buf.len(), data.len());
+        self.buf.extend_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a stream written by an [`Encryptor`](struct.Encryptor.html).
+///
+/// Reads fail with `io::ErrorKind::InvalidData` if the stream was tampered with, truncated, or
+/// has trailing data, and every read after that fails the same way. Errors from the underlying
+/// reader while a chunk is being read (such as `WouldBlock`) can be retried.
+pub struct Decryptor<R: Read> {
+    inner: R,
+    session: Session,
+    sender: Option<Vec<u8>>,
+    message: Vec<u8>,
+    // How much of the next chunk's ciphertext is in `message`.
+    filled: usize,
+    buf: Vec<u8>,
+    // The next chunk is decrypted here, and only swapped into `buf` once it has been checked.
+    scratch: Vec<u8>,
+    pos: usize,
+    finished: bool,
+    failed: Option<(io::ErrorKind, String)>,
+}
+
+impl<R: Read> Decryptor<R> {
+    /// Read the stream header from `inner` and complete the handshake with your static private key.
+    pub fn new(params: &NoiseParams, recipient_private: &[u8], inner: R) -> error::Result<Self> {
+        Self::new_inner(params, recipient_private, None, inner)
+    }
+
+    /// As [`new`](#method.new), for streams that must have been written by `sender_public`. This
+    /// is required for the `K` pattern.
+    pub fn new_from(params: &NoiseParams,
+                    recipient_private: &[u8],
+                    sender_public: &[u8],
+                    inner: R) -> error::Result<Self> {
+        Self::new_inner(params, recipient_private, Some(sender_public), inner)
+    }
+
+    fn new_inner(params: &NoiseParams,
+                 recipient_private: &[u8],
+                 sender_public: Option<&[u8]>,
+                 mut inner: R) -> error::Result<Self> {
+        if !params.handshake.pattern.is_oneway() {
+            bail!(ErrorKind::Init(InitStage::ValidateHandshakePattern));
+        }
+
+        let prologue = prologue();
+        let mut header = vec![0u8; prologue.len() + 2];
+        inner.read_exact(&mut header)?;
+        if header[..MAGIC.len()] != *MAGIC || header[MAGIC.len()] != VERSION {
+            bail!(ErrorKind::Input(InputProblem::Malformed("stream header")));
+        }
+
+        let mut builder = NoiseBuilder::new(params.clone())
+            .local_private_key(recipient_private)
+            .prologue(&prologue);
+        if let Some(key) = sender_public {
+            if params.handshake.pattern.need_known_remote_pubkey(false) {
+                builder = builder.remote_public_key(key);
+            }
+        }
+        let mut session = builder.build_responder()?;
+
+        let mut message = vec![0u8; MAXMSGLEN];
+        let len = BigEndian::read_u16(&header[prologue.len()..]) as usize;
+        inner.read_exact(&mut message[..len])?;
+        let mut payload = vec![0u8; MAXMSGLEN];
+        session.read_message(&message[..len], &mut payload)?;
+
+        let sender = session.get_remote_static().map(|k| k.to_vec());
+        if let (Some(expected), Some(actual)) = (sender_public, sender.as_ref()) {
+            if expected != &actual[..] {
+                bail!(ErrorKind::Decrypt(MessagePart::Other("sender's static key")));
+            }
+        }
+        let sender = if params.handshake.pattern.needs_local_static_key(true) { sender } else { None };
+
+        Ok(Decryptor {
+            inner: inner,
+            session: session.into_transport_mode()?,
+            sender: sender,
+            message: message,
+            filled: 0,
+            buf: Vec::new(),
+            scratch: vec![0u8; MAXMSGLEN],
+            pos: 0,
+            finished: false,
+            failed: None,
+        })
+    }
+
+    /// The sender's static public key, for the `X` and `K` patterns.
+    pub fn sender_public(&self) -> Option<&[u8]> {
+        self.sender.as_ref().map(|k| &k[..])
+    }
+
+    /// Read up to a full chunk of ciphertext, stopping early only at the end of the stream. What
+    /// was read before an error is kept, so the read can be retried.
+    fn read_ciphertext(&mut self) -> io::Result<usize> {
+        let full_len = CHUNK_LEN + 1 + TAGLEN;
+        while self.filled < full_len {
+            match self.inner.read(&mut self.message[self.filled..full_len]) {
+                Ok(0) => break,
+                Ok(n) => self.filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(::std::mem::replace(&mut self.filled, 0))
+    }
+
+    fn decrypt_chunk(&mut self, len: usize) -> io::Result<()> {
+        if len == 0 {
+            return Err(invalid_data("stream truncated"));
+        }
+        self.scratch.resize(MAXMSGLEN, 0);
+        let plain_len = self.session.read_message(&self.message[..len], &mut self.scratch).map_err(to_io_error)?;
+        if plain_len == 0 {
+            return Err(invalid_data("missing chunk flag"));
+        }
+        match self.scratch[0] {
+            FLAG_FINAL => self.finished = true,
+            FLAG_MORE if plain_len == CHUNK_LEN + 1 => {},
+            FLAG_MORE => return Err(invalid_data("stream truncated")),
+            _ => return Err(invalid_data("invalid chunk flag")),
+        }
+        self.scratch.truncate(plain_len);
+        ::std::mem::swap(&mut self.buf, &mut self.scratch);
+        self.pos = 1;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decryptor<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if let Some((kind, ref reason)) = self.failed {
+            return Err(io::Error::new(kind, reason.clone()));
+        }
+        while self.pos >= self.buf.len() {
+            let result = if self.finished {
+                // The end of the stream is only reported once the reader is known to be empty.
+                let mut trailing = [0u8; 1];
+                match self.inner.read(&mut trailing)? {
+                    0 => return Ok(0),
+                    _ => Err(invalid_data("trailing data after final chunk")),
+                }
+            } else {
+                let len = self.read_ciphertext()?;
+                self.decrypt_chunk(len)
+            };
+            if let Err(e) = result {
+                self.failed = Some((e.kind(), e.to_string()));
+                return Err(e);
+            }
+        }
+        let len = ::std::cmp::min(out.len(), self.buf.len() - self.pos);
+        out[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
@@ -2,8 +2,12 @@ use utils::*;
 use constants::*;
 use types::*;
 use cipherstate::*;
+use trace::{Secret, TraceEvent, Tracer};
+use std::prelude::v1::*;
 
 pub trait SymmetricStateType {
+    type Cipher: Cipher;
+
     fn cipher_name(&self) -> &'static str;
     fn hash_name(&self) -> &'static str;
     fn initialize(&mut self, handshake_name: &str);
@@ -13,19 +17,20 @@ pub trait SymmetricStateType {
     fn has_key(&self) -> bool;
     fn encrypt_and_mix_hash(&mut self, plaintext: &[u8], out: &mut [u8]) -> usize;
     fn decrypt_and_mix_hash(&mut self, data: &[u8], out: &mut [u8]) -> Result<usize, ()>;
-    fn split(&mut self, child1: &mut CipherState, child2: &mut CipherState);
+    fn split(&mut self, child1: &mut CipherState<Self::Cipher>, child2: &mut CipherState<Self::Cipher>);
 }
 
-pub struct SymmetricState {
-    cipherstate : CipherState,
-    hasher: Box<Hash>,
+pub struct SymmetricState<C: Cipher, H: Hash> {
+    cipherstate : CipherState<C>,
+    hasher: H,
     h : [u8; MAXHASHLEN],
     ck: [u8; MAXHASHLEN],
     has_key: bool,
+    tracer: Option<Box<Tracer>>,
 }
 
-impl SymmetricState {
-    pub fn new(cipherstate: CipherState, hasher: Box<Hash>) -> SymmetricState
+impl<C: Cipher, H: Hash> SymmetricState<C, H> {
+    pub fn new(cipherstate: CipherState<C>, hasher: H, tracer: Option<Box<Tracer>>) -> Self
     {
         SymmetricState {
             cipherstate: cipherstate,
@@ -33,11 +38,49 @@ impl SymmetricState {
             h: [0u8; MAXHASHLEN],
             ck : [0u8; MAXHASHLEN],
             has_key: false,
+            tracer: tracer,
+        }
+    }
+
+    /// `MixHash` over the concatenation of `parts`, without allocating to join them.
+    pub fn mix_hash_parts(&mut self, parts: &[&[u8]]) {
+        let hash_len = self.hasher.hash_len();
+        self.hasher.reset();
+        self.hasher.input(&self.h[..hash_len]);
+        for part in parts {
+            self.hasher.input(part);
         }
+        self.hasher.result(&mut self.h);
+        trace(&mut self.tracer, &TraceEvent::MixHash { h: &self.h[..hash_len] });
+    }
+
+    /// The current chaining key.
+    pub fn chaining_key(&self) -> &[u8] {
+        &self.ck[..self.hasher.hash_len()]
+    }
+
+    /// Report a step of the handshake to the tracer, if there is one.
+    pub fn trace(&mut self, event: &TraceEvent) {
+        trace(&mut self.tracer, event);
+    }
+}
+
+impl<C: Cipher, H: Hash> Drop for SymmetricState<C, H> {
+    fn drop(&mut self) {
+        self.h.zeroize();
+        self.ck.zeroize();
+    }
+}
+
+fn trace(tracer: &mut Option<Box<Tracer>>, event: &TraceEvent) {
+    if let Some(ref mut tracer) = *tracer {
+        tracer.trace(event);
     }
 }
 
-impl SymmetricStateType for SymmetricState {
+impl<C: Cipher, H: Hash> SymmetricStateType for SymmetricState<C, H> {
+    type Cipher = C;
+
 
     fn cipher_name(&self) -> &'static str {
         self.cipherstate.name()
@@ -58,6 +101,11 @@ impl SymmetricStateType for SymmetricState {
         }
         copy_memory(&self.h, &mut self.ck);
         self.has_key = false;
+        let hash_len = self.hasher.hash_len();
+        trace(&mut self.tracer, &TraceEvent::Initialize {
+            h: &self.h[..hash_len],
+            ck: Secret::new(&self.ck[..hash_len]),
+        });
     }
 
     fn mix_key(&mut self, data: &[u8]) {
@@ -67,14 +115,16 @@ impl SymmetricStateType for SymmetricState {
         copy_memory(&hkdf_output.0, &mut self.ck);
         self.cipherstate.set(&hkdf_output.1[..CIPHERKEYLEN], 0);
         self.has_key = true;
+        trace(&mut self.tracer, &TraceEvent::MixKey {
+            ck: Secret::new(&self.ck[..hash_len]),
+            k: Secret::new(&hkdf_output.1[..CIPHERKEYLEN]),
+        });
+        hkdf_output.0.zeroize();
+        hkdf_output.1.zeroize();
     }
 
     fn mix_hash(&mut self, data: &[u8]) {
-        let hash_len = self.hasher.hash_len();
-        self.hasher.reset();
-        self.hasher.input(&self.h[..hash_len]);
-        self.hasher.input(data);
-        self.hasher.result(&mut self.h);
+        self.mix_hash_parts(&[data]);
     }
 
     fn mix_key_and_hash(&mut self, data: &[u8]) {
@@ -84,6 +134,14 @@ impl SymmetricStateType for SymmetricState {
         copy_memory(&hkdf_output.0, &mut self.ck);
         self.mix_hash(&hkdf_output.1[..hash_len]);
         self.cipherstate.set(&hkdf_output.2[..CIPHERKEYLEN], 0);
+        trace(&mut self.tracer, &TraceEvent::MixKeyAndHash {
+            ck: Secret::new(&self.ck[..hash_len]),
+            h: &self.h[..hash_len],
+            k: Secret::new(&hkdf_output.2[..CIPHERKEYLEN]),
+        });
+        hkdf_output.0.zeroize();
+        hkdf_output.1.zeroize();
+        hkdf_output.2.zeroize();
     }
 
     fn has_key(&self) -> bool {
@@ -93,29 +151,34 @@ impl SymmetricStateType for SymmetricState {
     /// Encrypt a message and mixes in the hash of the output
     fn encrypt_and_mix_hash(&mut self, plaintext: &[u8], out: &mut [u8]) -> usize {
         let hash_len = self.hasher.hash_len();
+        let nonce = if self.has_key { Some(self.cipherstate.nonce()) } else { None };
         let output_len = if self.has_key {
             self.cipherstate.encrypt_ad(&self.h[..hash_len], plaintext, out)
         } else {
             copy_memory(plaintext, out);
             plaintext.len()
         };
+        trace(&mut self.tracer, &TraceEvent::Encrypt { nonce: nonce, len: output_len });
         self.mix_hash(&out[..output_len]);
         output_len
     }
 
     fn decrypt_and_mix_hash(&mut self, data: &[u8], out: &mut [u8]) -> Result<usize, ()> {
         let hash_len = self.hasher.hash_len();
-        let payload_len = if self.has_key {
-            self.cipherstate.decrypt_ad(&self.h[..hash_len], data, out)?
+        let nonce = if self.has_key { Some(self.cipherstate.nonce()) } else { None };
+        let result = if self.has_key {
+            self.cipherstate.decrypt_ad(&self.h[..hash_len], data, out)
         } else {
             copy_memory(data, out);
-            data.len()
+            Ok(data.len())
         };
+        trace(&mut self.tracer, &TraceEvent::Decrypt { nonce: nonce, len: data.len(), ok: result.is_ok() });
+        let payload_len = result?;
         self.mix_hash(data);
         Ok(payload_len)
     }
 
-    fn split(&mut self, child1: &mut CipherState, child2: &mut CipherState) {
+    fn split(&mut self, child1: &mut CipherState<C>, child2: &mut CipherState<C>) {
         let hash_len = self.hasher.hash_len();
         let mut hkdf_output = ([0u8; MAXHASHLEN], [0u8; MAXHASHLEN]);
         self.hasher.hkdf(&self.ck[..hash_len], &[0u8; 0], 2,
@@ -124,6 +187,32 @@ impl SymmetricStateType for SymmetricState {
                          &mut []);
         child1.set(&hkdf_output.0[..CIPHERKEYLEN], 0);
         child2.set(&hkdf_output.1[..CIPHERKEYLEN], 0);
+        trace(&mut self.tracer, &TraceEvent::Split {
+            k1: Secret::new(&hkdf_output.0[..CIPHERKEYLEN]),
+            k2: Secret::new(&hkdf_output.1[..CIPHERKEYLEN]),
+        });
+        hkdf_output.0.zeroize();
+        hkdf_output.1.zeroize();
     }
 
 }
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::mem::ManuallyDrop;
+    use std::ptr;
+    use wrappers::crypto_wrapper::{CipherChaChaPoly, HashSHA256};
+
+    #[test]
+    fn test_chaining_key_cleared_on_drop() {
+        let cipherstate = CipherState::new(CipherChaChaPoly::default());
+        let mut state = ManuallyDrop::new(SymmetricState::new(cipherstate, HashSHA256::default(), None));
+        state.initialize("Noise_NN_25519_ChaChaPoly_SHA256");
+        state.mix_key(&[7u8; 32]);
+        assert!(state.ck.iter().any(|&b| b != 0));
+        unsafe { ptr::drop_in_place(&mut *state) };
+        assert_eq!(&state.ck[..], &[0u8; MAXHASHLEN][..]);
+        assert_eq!(&state.h[..], &[0u8; MAXHASHLEN][..]);
+    }
+}
@@ -0,0 +1,347 @@
+//! A conformance suite for third-party `CryptoResolver`s.
+//!
+//! [`check_resolver`](fn.check_resolver.html) runs known-answer tests against every primitive the
+//! resolver supplies, then runs a handshake and a couple of transport messages for every pattern
+//! using the resolver's primitives and compares the transcript with the one produced by
+//! `DefaultResolver`. When a transcript diverges, each of the resolver's primitives is swapped in
+//! on its own to find the one to blame.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::DefaultResolver;
+//! # use snow::testing::conformance;
+//! let report = conformance::check_resolver(&DefaultResolver);
+//! for failure in &report.failures {
+//!     println!("{}", failure);
+//! }
+//! assert!(report.is_success());
+//! ```
+
+use kat::{self, cipher_name, dh_name, hash_name, exchange, CIPHERS, DHS, HASHES};
+use noise::{CryptoResolver, DefaultResolver, NoiseBuilder};
+use params::{CipherChoice, DHChoice, HandshakePattern, HashChoice, NoiseParams};
+use std::fmt;
+use super::SeededRandom;
+use types::{Cipher, Dh, Hash, Random};
+
+const PATTERNS: [HandshakePattern; 15] = [
+    HandshakePattern::N, HandshakePattern::X, HandshakePattern::K,
+    HandshakePattern::NN, HandshakePattern::NK, HandshakePattern::NX,
+    HandshakePattern::XN, HandshakePattern::XK, HandshakePattern::XX,
+    HandshakePattern::KN, HandshakePattern::KK, HandshakePattern::KX,
+    HandshakePattern::IN, HandshakePattern::IK, HandshakePattern::IX,
+];
+
+/// A primitive a resolver supplies.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Primitive {
+    Rng,
+    Dh(DHChoice),
+    Hash(HashChoice),
+    Cipher(CipherChoice),
+}
+
+impl fmt::Display for Primitive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Primitive::Rng            => write!(f, "RNG"),
+            Primitive::Dh(ref dh)     => write!(f, "DH {}", dh_name(dh)),
+            Primitive::Hash(ref hash) => write!(f, "hash {}", hash_name(hash)),
+            Primitive::Cipher(ref c)  => write!(f, "cipher {}", cipher_name(c)),
+        }
+    }
+}
+
+/// A failed check, and the primitives it was blamed on.
+#[derive(Clone, Debug)]
+pub struct ConformanceFailure {
+    /// The primitives that diverged. More than one means the divergence only shows up when they're
+    /// used together.
+    pub primitives: Vec<Primitive>,
+    /// The check that failed: a primitive's known-answer test, or a protocol name.
+    pub check: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ConformanceFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let blamed: Vec<String> = self.primitives.iter().map(|p| p.to_string()).collect();
+        write!(f, "{} ({}): {}", self.check, blamed.join(", "), self.reason)
+    }
+}
+
+/// The outcome of [`check_resolver`](fn.check_resolver.html).
+#[derive(Clone, Debug, Default)]
+pub struct ConformanceReport {
+    pub passed: usize,
+    /// The checks that couldn't be run, because either the resolver or `DefaultResolver` doesn't
+    /// support a primitive.
+    pub skipped: Vec<String>,
+    pub failures: Vec<ConformanceFailure>,
+}
+
+impl ConformanceReport {
+    /// Whether no check failed.
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    fn record(&mut self, primitives: Vec<Primitive>, check: String, result: Result<(), String>) {
+        match result {
+            Ok(())      => self.passed += 1,
+            Err(reason) => self.failures.push(ConformanceFailure {
+                primitives: primitives,
+                check: check,
+                reason: reason,
+            }),
+        }
+    }
+}
+
+/// Run the whole suite against `resolver`.
+///
+/// Panics in the resolver's primitives are caught and reported as failures.
+pub fn check_resolver(resolver: &CryptoResolver) -> ConformanceReport {
+    let mut report = ConformanceReport::default();
+
+    match resolver.resolve_rng() {
+        Some(mut rng) => report.record(vec![Primitive::Rng], "RNG output".to_owned(), check_rng(&mut *rng)),
+        None          => report.skipped.push("RNG output: not supported".to_owned()),
+    }
+
+    let dhs: Vec<DHChoice> = DHS.iter().filter(|&dh| {
+        match resolver.resolve_dh(dh) {
+            Some(mut imp) => match kat::check_dh(dh, &mut *imp) {
+                Some(result) => report.record(vec![Primitive::Dh(dh.clone())], format!("{} known answers", dh_name(dh)), result),
+                None         => report.skipped.push(format!("{} known answers: none available", dh_name(dh))),
+            },
+            None => report.skipped.push(format!("{} known answers: not supported", dh_name(dh))),
+        }
+        supported(&mut report, resolver.resolve_dh(dh).is_some(), DefaultResolver.resolve_dh(dh).is_some(), dh_name(dh))
+    }).cloned().collect();
+
+    let hashes: Vec<HashChoice> = HASHES.iter().filter(|&hash| {
+        match resolver.resolve_hash(hash) {
+            Some(mut imp) => {
+                let result = kat::check_hash(hash, &mut *imp);
+                report.record(vec![Primitive::Hash(hash.clone())], format!("{} known answers", hash_name(hash)), result);
+            },
+            None => report.skipped.push(format!("{} known answers: not supported", hash_name(hash))),
+        }
+        supported(&mut report, resolver.resolve_hash(hash).is_some(), true, hash_name(hash))
+    }).cloned().collect();
+
+    let ciphers: Vec<CipherChoice> = CIPHERS.iter().filter(|&cipher| {
+        match resolver.resolve_cipher(cipher) {
+            Some(mut imp) => {
+                let result = kat::check_cipher(cipher, &mut *imp);
+                report.record(vec![Primitive::Cipher(cipher.clone())], format!("{} known answers", cipher_name(cipher)), result);
+            },
+            None => report.skipped.push(format!("{} known answers: not supported", cipher_name(cipher))),
+        }
+        supported(&mut report, resolver.resolve_cipher(cipher).is_some(), true, cipher_name(cipher))
+    }).cloned().collect();
+
+    for pattern in &PATTERNS {
+        for dh in &dhs {
+            for cipher in &ciphers {
+                for hash in &hashes {
+                    let name = format!("Noise_{}_{}_{}_{}",
+                                       pattern.as_str(), dh_name(dh), cipher_name(cipher), hash_name(hash));
+                    let params: NoiseParams = name.parse().expect("built from valid names");
+                    let (blamed, result) = differential(resolver, &params);
+                    report.record(blamed, name, result);
+                }
+            }
+        }
+    }
+
+    report
+}
+
+/// Whether a primitive can be used in the differential handshakes, noting why not if it can't.
+fn supported(report: &mut ConformanceReport, candidate: bool, oracle: bool, name: &str) -> bool {
+    if candidate && !oracle {
+        report.skipped.push(format!("{} handshakes: no reference implementation", name));
+    }
+    candidate && oracle
+}
+
+fn check_rng(rng: &mut Random) -> Result<(), String> {
+    kat::guard(|| {
+        let (mut first, mut second) = ([0u8; 64], [0u8; 64]);
+        rng.fill_bytes(&mut first);
+        rng.fill_bytes(&mut second);
+        if first[..] == [0u8; 64][..] || first[..] == second[..] {
+            Err("output is constant".to_owned())
+        } else {
+            Ok(())
+        }
+    })
+}
+
+/// A resolver mixing the candidate's primitives with the default ones. The default RNG is always
+/// used, since every key is fixed.
+struct Hybrid<'a> {
+    candidate: &'a CryptoResolver,
+    dh: bool,
+    hash: bool,
+    cipher: bool,
+}
+
+impl<'a> CryptoResolver for Hybrid<'a> {
+    fn resolve_rng(&self) -> Option<Box<Random>> {
+        DefaultResolver.resolve_rng()
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+        if self.dh { self.candidate.resolve_dh(choice) } else { DefaultResolver.resolve_dh(choice) }
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+        if self.hash { self.candidate.resolve_hash(choice) } else { DefaultResolver.resolve_hash(choice) }
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+        if self.cipher { self.candidate.resolve_cipher(choice) } else { DefaultResolver.resolve_cipher(choice) }
+    }
+}
+
+/// Compare the candidate's transcript for `params` with the default one, returning the primitives
+/// to blame if they differ.
+fn differential(candidate: &CryptoResolver, params: &NoiseParams) -> (Vec<Primitive>, Result<(), String>) {
+    let all = vec![Primitive::Dh(params.dh.clone()), Primitive::Hash(params.hash.clone()), Primitive::Cipher(params.cipher.clone())];
+    let run = |dh, hash, cipher| {
+        let hybrid = Hybrid { candidate: candidate, dh: dh, hash: hash, cipher: cipher };
+        kat::guard(|| transcript(&hybrid, params))
+    };
+
+    let expected = match run(false, false, false) {
+        Ok(expected) => expected,
+        Err(reason)  => return (all, Err(format!("reference handshake failed: {}", reason))),
+    };
+    let compare = |actual: Result<Vec<Vec<u8>>, String>| {
+        match actual {
+            Ok(ref actual) if *actual == expected => Ok(()),
+            Ok(_)                                  => Err("transcript differs from the reference".to_owned()),
+            Err(reason)                            => Err(reason),
+        }
+    };
+
+    let result = compare(run(true, true, true));
+    if result.is_ok() {
+        return (all, result);
+    }
+    let blamed: Vec<Primitive> = all.iter().cloned().zip(&[(true, false, false), (false, true, false), (false, false, true)])
+        .filter(|&(_, &(dh, hash, cipher))| compare(run(dh, hash, cipher)).is_err())
+        .map(|(primitive, _)| primitive)
+        .collect();
+    (if blamed.is_empty() { all } else { blamed }, result)
+}
+
+/// Run a handshake and two transport messages with fixed keys, returning every message sent.
+fn transcript(resolver: &CryptoResolver, params: &NoiseParams) -> Result<Vec<Vec<u8>>, String> {
+    let pattern = &params.handshake.pattern;
+    let priv_len = resolver.resolve_dh(&params.dh).ok_or("DH not supported")?.priv_len();
+    let key = |seed| {
+        let mut key = vec![0u8; priv_len];
+        SeededRandom::new(seed).fill_bytes(&mut key);
+        key
+    };
+    let public = |private: &[u8]| -> Result<Vec<u8>, String> {
+        let mut dh = resolver.resolve_dh(&params.dh).ok_or("DH not supported")?;
+        dh.set(private);
+        Ok(dh.pubkey().to_vec())
+    };
+    let (init_s, resp_s, init_e, resp_e) = (key(1), key(2), key(3), key(4));
+    let (init_s_pub, resp_s_pub) = (public(&init_s)?, public(&resp_s)?);
+
+    let mut init = NoiseBuilder::with_resolver(params.clone(), Box::new(resolver))
+        .prologue(b"snow conformance")
+        .fixed_ephemeral_key(&init_e);
+    let mut resp = NoiseBuilder::with_resolver(params.clone(), Box::new(resolver))
+        .prologue(b"snow conformance")
+        .fixed_ephemeral_key(&resp_e);
+    if pattern.needs_local_static_key(true) {
+        init = init.local_private_key(&init_s);
+    }
+    if pattern.needs_local_static_key(false) {
+        resp = resp.local_private_key(&resp_s);
+    }
+    if pattern.need_known_remote_pubkey(true) {
+        init = init.remote_public_key(&resp_s_pub);
+    }
+    if pattern.need_known_remote_pubkey(false) {
+        resp = resp.remote_public_key(&init_s_pub);
+    }
+    let mut init = init.build_initiator().map_err(|e| e.to_string())?;
+    let mut resp = resp.build_responder().map_err(|e| e.to_string())?;
+
+    let mut transcript = vec![];
+    let mut initiator_sends = true;
+    while !(init.is_handshake_finished() && resp.is_handshake_finished()) {
+        let payload = format!("handshake message {}", transcript.len());
+        let (send, recv) = if initiator_sends { (&mut init, &mut resp) } else { (&mut resp, &mut init) };
+        transcript.push(exchange(send, recv, payload.as_bytes())?);
+        initiator_sends = !initiator_sends;
+    }
+
+    let mut init = init.into_transport_mode().map_err(|e| e.to_string())?;
+    let mut resp = resp.into_transport_mode().map_err(|e| e.to_string())?;
+    transcript.push(exchange(&mut init, &mut resp, b"first transport message")?);
+    if pattern.is_oneway() {
+        transcript.push(exchange(&mut init, &mut resp, b"second transport message")?);
+    } else {
+        transcript.push(exchange(&mut resp, &mut init, b"second transport message")?);
+    }
+    Ok(transcript)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out SHA-512 when asked for BLAKE2b, which has the same lengths.
+    struct MismatchedResolver;
+
+    impl CryptoResolver for MismatchedResolver {
+        fn resolve_rng(&self) -> Option<Box<Random>> {
+            DefaultResolver.resolve_rng()
+        }
+
+        fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+            DefaultResolver.resolve_dh(choice)
+        }
+
+        fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+            match *choice {
+                HashChoice::Blake2b => DefaultResolver.resolve_hash(&HashChoice::SHA512),
+                _                   => DefaultResolver.resolve_hash(choice),
+            }
+        }
+
+        fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+            DefaultResolver.resolve_cipher(choice)
+        }
+    }
+
+    #[test]
+    fn test_default_resolver_conforms() {
+        let report = check_resolver(&DefaultResolver);
+        assert!(report.is_success(), "{:?}", report.failures);
+        // RNG, 25519, 4 hashes, 2 ciphers, then 15 patterns for each of 8 combinations.
+        assert_eq!(report.passed, 1 + 1 + 4 + 2 + 15 * 8);
+    }
+
+    #[test]
+    fn test_mismatched_primitive_blamed() {
+        let report = check_resolver(&MismatchedResolver);
+        assert!(!report.is_success());
+        for failure in &report.failures {
+            assert_eq!(failure.primitives, vec![Primitive::Hash(HashChoice::Blake2b)], "{}", failure);
+        }
+        // The known-answer test, and every handshake using BLAKE2b.
+        assert_eq!(report.failures.len(), 1 + 15 * 2);
+    }
+}
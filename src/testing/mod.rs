@@ -0,0 +1,154 @@
+//! Deterministic crypto for writing reproducible protocol tests.
+//!
+//! Nothing in this module is suitable for real keys: the randomness it hands out is entirely
+//! determined by a seed.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::NoiseBuilder;
+//! # use snow::testing::SeededResolver;
+//! let params = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+//! let mut noise = NoiseBuilder::with_resolver(params, Box::new(SeededResolver::new(42)))
+//!                              .build_initiator()
+//!                              .unwrap();
+//!
+//! // The first message carries an ephemeral key drawn from the seeded RNG, so it's the same on
+//! // every run.
+//! let mut buf = [0u8; 65535];
+//! let len = noise.write_message(&[], &mut buf).unwrap();
+//! ```
+
+extern crate rand;
+
+pub mod conformance;
+
+use self::rand::{ChaChaRng, Rng, SeedableRng};
+use noise::{CryptoResolver, DefaultResolver};
+use params::{CipherChoice, DHChoice, HashChoice};
+use std::cell::Cell;
+use types::{Cipher, Dh, Hash, Random};
+
+/// A deterministic `Random`, seeded with a `u64`.
+pub struct SeededRandom {
+    rng: ChaChaRng,
+}
+
+impl SeededRandom {
+    /// Create the generator for `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self::stream(seed, 0)
+    }
+
+    fn stream(seed: u64, stream: u64) -> Self {
+        let key = [seed as u32, (seed >> 32) as u32, stream as u32, (stream >> 32) as u32];
+        SeededRandom { rng: ChaChaRng::from_seed(&key) }
+    }
+}
+
+impl Random for SeededRandom {
+    fn fill_bytes(&mut self, out: &mut [u8]) {
+        self.rng.fill_bytes(out);
+    }
+}
+
+/// A resolver that hands out `SeededRandom`s, and defers everything else to another resolver.
+///
+/// Each call to `resolve_rng` gets a different (but still deterministic) stream, the first being
+/// `SeededRandom::new(seed)`, so an initiator and responder built from the same resolver don't
+/// share ephemeral keys.
+pub struct SeededResolver {
+    seed: u64,
+    count: Cell<u64>,
+    parent: Box<CryptoResolver>,
+}
+
+impl SeededResolver {
+    /// Create a resolver for `seed`, using the default pure-rust primitives.
+    pub fn new(seed: u64) -> Self {
+        Self::wrap(seed, Box::new(DefaultResolver))
+    }
+
+    /// Create a resolver for `seed`, using `parent`'s primitives.
+    pub fn wrap(seed: u64, parent: Box<CryptoResolver>) -> Self {
+        SeededResolver {
+            seed: seed,
+            count: Cell::new(0),
+            parent: parent,
+        }
+    }
+}
+
+impl CryptoResolver for SeededResolver {
+    fn resolve_rng(&self) -> Option<Box<Random>> {
+        let count = self.count.get();
+        self.count.set(count + 1);
+        Some(Box::new(SeededRandom::stream(self.seed, count)))
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+        self.parent.resolve_dh(choice)
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+        self.parent.resolve_hash(choice)
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+        self.parent.resolve_cipher(choice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noise::NoiseBuilder;
+    use params::NoiseParams;
+
+    fn first_message(resolver: SeededResolver, fixed_e: Option<&[u8]>) -> Vec<u8> {
+        let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let mut builder = NoiseBuilder::with_resolver(params, Box::new(resolver));
+        if let Some(key) = fixed_e {
+            builder = builder.fixed_ephemeral_key(key);
+        }
+        let mut noise = builder.build_initiator().unwrap();
+        let mut buf = [0u8; 1024];
+        let len = noise.write_message(&[], &mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn test_seeded_random_streams() {
+        let (mut a, mut b, mut c) = ([0u8; 32], [0u8; 32], [0u8; 32]);
+        SeededRandom::new(1).fill_bytes(&mut a);
+        SeededRandom::new(1).fill_bytes(&mut b);
+        SeededRandom::new(2).fill_bytes(&mut c);
+        assert_eq!(a, b);
+        assert!(a != c);
+
+        let resolver = SeededResolver::new(1);
+        resolver.resolve_rng().unwrap().fill_bytes(&mut b);
+        resolver.resolve_rng().unwrap().fill_bytes(&mut c);
+        assert_eq!(a, b);
+        assert!(a != c);
+    }
+
+    #[test]
+    fn test_seeded_handshake_is_reproducible() {
+        assert_eq!(first_message(SeededResolver::new(9), None), first_message(SeededResolver::new(9), None));
+        assert!(first_message(SeededResolver::new(9), None) != first_message(SeededResolver::new(10), None));
+    }
+
+    #[test]
+    fn test_fixed_ephemeral_overrides_rng() {
+        let key = [7u8; 32];
+        assert_eq!(first_message(SeededResolver::new(9), Some(&key)),
+                   first_message(SeededResolver::new(10), Some(&key)));
+    }
+
+    #[test]
+    fn test_fixed_ephemeral_length_checked() {
+        let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        assert!(NoiseBuilder::new(params).fixed_ephemeral_key(&[0u8; 31]).build_responder().is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Opt-in tracing of the handshake's internal state, for debugging interop failures.
+//!
+//! A [`Tracer`](trait.Tracer.html) given to [`NoiseBuilder::tracer`](../struct.NoiseBuilder.html#method.tracer)
+//! sees every token processed and the symmetric state after every operation on it, in the order
+//! the spec's `HandshakeState` and `SymmetricState` perform them, so a trace can be diffed line by
+//! line against another implementation's.
+//!
+//! The chaining key and cipher keys are wrapped in [`Secret`](struct.Secret.html) and redacted
+//! unless the `trace-secrets` feature is enabled. Never enable it in production builds.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::NoiseBuilder;
+//! # use snow::trace::TraceEvent;
+//! let mut noise = NoiseBuilder::new("Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap())
+//!                              .tracer(Box::new(|event: &TraceEvent| eprintln!("{}", event)))
+//!                              .build_initiator()
+//!                              .unwrap();
+//!
+//! let mut buf = [0u8; 65535];
+//! noise.write_message(&[], &mut buf).unwrap();
+//! ```
+
+use params::Token;
+use std::fmt;
+
+/// Receives handshake trace events.
+///
+/// Implemented for closures taking a `&TraceEvent`.
+pub trait Tracer {
+    fn trace(&mut self, event: &TraceEvent);
+}
+
+impl<F: FnMut(&TraceEvent)> Tracer for F {
+    fn trace(&mut self, event: &TraceEvent) {
+        self(event)
+    }
+}
+
+/// A step in the handshake, and the state after it.
+#[derive(Debug)]
+pub enum TraceEvent<'a> {
+    /// `InitializeSymmetric` with the protocol name.
+    Initialize { h: &'a [u8], ck: Secret<'a> },
+    /// A handshake message is about to be written.
+    WriteMessage,
+    /// A handshake message is about to be read.
+    ReadMessage,
+    /// A token from the message pattern is about to be processed.
+    Token(Token),
+    MixHash { h: &'a [u8] },
+    MixKey { ck: Secret<'a>, k: Secret<'a> },
+    MixKeyAndHash { ck: Secret<'a>, h: &'a [u8], k: Secret<'a> },
+    /// `EncryptAndHash`, with the nonce used if there was a cipher key.
+    Encrypt { nonce: Option<u64>, len: usize },
+    /// `DecryptAndHash`, with the nonce used if there was a cipher key.
+    Decrypt { nonce: Option<u64>, len: usize, ok: bool },
+    /// `Split`, with the initiator's and responder's transport keys.
+    Split { k1: Secret<'a>, k2: Secret<'a> },
+}
+
+fn write_hex(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    for byte in bytes {
+        write!(f, "{:02x}", byte)?;
+    }
+    Ok(())
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl<'a> fmt::Display for Hex<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_hex(f, self.0)
+    }
+}
+
+/// A secret value in a trace, which is only revealed with the `trace-secrets` feature.
+#[derive(Clone, Copy)]
+pub struct Secret<'a>(&'a [u8]);
+
+impl<'a> Secret<'a> {
+    #[doc(hidden)]
+    pub fn new(value: &'a [u8]) -> Self {
+        Secret(value)
+    }
+
+    /// The secret, if the `trace-secrets` feature is enabled.
+    pub fn expose(&self) -> Option<&'a [u8]> {
+        if cfg!(feature = "trace-secrets") { Some(self.0) } else { None }
+    }
+}
+
+impl<'a> fmt::Display for Secret<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.expose() {
+            Some(value) => write_hex(f, value),
+            None        => write!(f, "<redacted>"),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Secret<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret({})", self)
+    }
+}
+
+impl<'a> fmt::Display for TraceEvent<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TraceEvent::Initialize { h, ck }          => write!(f, "initialize h={} ck={}", Hex(h), ck),
+            TraceEvent::WriteMessage                  => write!(f, "write message"),
+            TraceEvent::ReadMessage                   => write!(f, "read message"),
+            TraceEvent::Token(token)                  => write!(f, "token {}", token),
+            TraceEvent::MixHash { h }                 => write!(f, "mix_hash h={}", Hex(h)),
+            TraceEvent::MixKey { ck, k }              => write!(f, "mix_key ck={} k={}", ck, k),
+            TraceEvent::MixKeyAndHash { ck, h, k }    => write!(f, "mix_key_and_hash ck={} h={} k={}", ck, Hex(h), k),
+            TraceEvent::Encrypt { nonce, len }        => match nonce {
+                Some(n) => write!(f, "encrypt_and_hash n={} len={}", n, len),
+                None    => write!(f, "encrypt_and_hash no key len={}", len),
+            },
+            TraceEvent::Decrypt { nonce, len, ok }    => match nonce {
+                Some(n) => write!(f, "decrypt_and_hash n={} len={} {}", n, len, if ok { "ok" } else { "failed" }),
+                None    => write!(f, "decrypt_and_hash no key len={}", len),
+            },
+            TraceEvent::Split { k1, k2 }              => write!(f, "split k1={} k2={}", k1, k2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noise::NoiseBuilder;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn trace_nn() -> Vec<String> {
+        let lines = Rc::new(RefCell::new(vec![]));
+        let sink = lines.clone();
+        let mut init = NoiseBuilder::new("Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap())
+            .tracer(Box::new(move |event: &TraceEvent| sink.borrow_mut().push(event.to_string())))
+            .build_initiator()
+            .unwrap();
+        let mut resp = NoiseBuilder::new("Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap())
+            .build_responder()
+            .unwrap();
+        let (mut msg, mut buf) = ([0u8; 1024], [0u8; 1024]);
+        let len = init.write_message(b"hi", &mut msg).unwrap();
+        resp.read_message(&msg[..len], &mut buf).unwrap();
+        let len = resp.write_message(b"hi", &mut msg).unwrap();
+        init.read_message(&msg[..len], &mut buf).unwrap();
+        let lines = lines.borrow().clone();
+        lines
+    }
+
+    #[test]
+    fn test_trace_follows_pattern() {
+        let lines = trace_nn();
+        let steps: Vec<&str> = lines.iter().map(|line| line.split(' ').next().unwrap()).collect();
+        assert_eq!(steps, vec!["initialize", "mix_hash",
+                               "write", "token", "mix_hash", "encrypt_and_hash", "mix_hash",
+                               "read", "token", "mix_hash", "token", "mix_key",
+                               "decrypt_and_hash", "mix_hash", "split"]);
+        assert_eq!(lines[3], "token e");
+        assert_eq!(lines[12], "decrypt_and_hash n=0 len=18 ok");
+    }
+
+    #[cfg(not(feature = "trace-secrets"))]
+    #[test]
+    fn test_secrets_redacted() {
+        let lines = trace_nn();
+        assert!(lines[11].starts_with("mix_key ck=<redacted> k=<redacted>"));
+        assert!(Secret::new(&[1, 2, 3]).expose().is_none());
+    }
+}
@@ -1,43 +1,89 @@
 extern crate arrayvec;
 
+use bolt8::Framing;
 use params::HandshakePattern;
-use error::{ErrorKind, Result, StateProblem};
+use error::{ErrorKind, Result, StateProblem, InputProblem, MessagePart};
 use cipherstate::CipherStates;
-use constants::{MAXMSGLEN, TAGLEN};
+use constants::{MAXDHLEN, MAXMSGLEN, TAGLEN};
+use padding::{self, PaddingPolicy};
+use types::{Cipher, Random};
+use utils::Toggle;
+use std::prelude::v1::*;
 
 /// A state machine encompassing the transport phase of a Noise session, using the two
 /// `CipherState`s (for sending and receiving) that were spawned from the `SymmetricState`'s
 /// `Split()` method, called after a handshake has been finished.
 ///
+/// Like `HandshakeState`, the cipher and RNG are type parameters, defaulting to boxed ones.
+///
 /// See: http://noiseprotocol.org/noise.html#the-handshakestate-object
-pub struct TransportState {
-    pub cipherstates: CipherStates,
+pub struct TransportState<C: Cipher = Box<Cipher>, R: Random = Box<Random>> {
+    cipherstates: CipherStates<C>,
     pattern: HandshakePattern,
     initiator: bool,
+    rng: R,
+    padding: Option<PaddingPolicy>,
+    bolt8: Option<Framing>,
+    rs: Toggle<[u8; MAXDHLEN]>,
+    dh_len: usize,
 }
 
-impl TransportState {
-    pub fn new(cipherstates: CipherStates, pattern: HandshakePattern, initiator: bool) -> Self {
+impl<C: Cipher, R: Random> TransportState<C, R> {
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    pub(crate) fn new(cipherstates: CipherStates<C>,
+                      pattern: HandshakePattern,
+                      initiator: bool,
+                      rng: R,
+                      padding: Option<PaddingPolicy>,
+                      bolt8: Option<Framing>,
+                      rs: Toggle<[u8; MAXDHLEN]>,
+                      dh_len: usize) -> Self {
         TransportState {
             cipherstates: cipherstates,
             pattern: pattern,
             initiator: initiator,
+            rng: rng,
+            padding: padding,
+            bolt8: bolt8,
+            rs: rs,
+            dh_len: dh_len,
         }
     }
 
+    /// Encrypt `payload` into `message`, returning the message length.
     pub fn write_transport_message(&mut self,
                                    payload: &[u8],
                                    message: &mut [u8]) -> Result<usize> {
         if !self.initiator && self.pattern.is_oneway() {
             bail!(ErrorKind::State(StateProblem::OneWay));
-        } else if payload.len() + TAGLEN > MAXMSGLEN || payload.len() + TAGLEN > message.len() {
-            bail!(ErrorKind::Input);
         }
 
         let cipher = if self.initiator { &mut self.cipherstates.0 } else { &mut self.cipherstates.1 };
-        Ok(cipher.encrypt(payload, message))
+        if let Some(ref mut framing) = self.bolt8 {
+            return framing.write(cipher, payload, message);
+        }
+        match self.padding {
+            Some(ref policy) => {
+                let padded_len = policy.padded_len(payload.len(), &mut self.rng)?;
+                if padded_len + TAGLEN > message.len() {
+                    bail!(ErrorKind::Input(InputProblem::BufferTooSmall));
+                }
+                let mut framed = vec![0u8; padded_len];
+                padding::frame(payload, &mut framed);
+                Ok(cipher.encrypt(&framed, message))
+            },
+            None => {
+                if payload.len() + TAGLEN > MAXMSGLEN {
+                    bail!(ErrorKind::Input(InputProblem::MessageTooLong));
+                } else if payload.len() + TAGLEN > message.len() {
+                    bail!(ErrorKind::Input(InputProblem::BufferTooSmall));
+                }
+                Ok(cipher.encrypt(payload, message))
+            }
+        }
     }
 
+    /// Decrypt `payload` into `message`, returning the payload length.
     pub fn read_transport_message(&mut self,
                                    payload: &[u8],
                                    message: &mut [u8]) -> Result<usize> {
@@ -45,13 +91,52 @@ impl TransportState {
             bail!(ErrorKind::State(StateProblem::OneWay));
         }
         let cipher = if self.initiator { &mut self.cipherstates.1 } else { &mut self.cipherstates.0 };
-        cipher.decrypt(payload, message).map_err(|_| ErrorKind::Decrypt.into())
+        if let Some(ref mut framing) = self.bolt8 {
+            return framing.read(cipher, payload, message);
+        }
+        let part = MessagePart::Transport { nonce: cipher.nonce() };
+        if payload.len() > MAXMSGLEN {
+            bail!(ErrorKind::Input(InputProblem::MessageTooLong));
+        } else if payload.len() < TAGLEN {
+            bail!(ErrorKind::Input(InputProblem::Truncated(part)));
+        }
+        let len = cipher.decrypt(payload, message).map_err(|_| ErrorKind::Decrypt(part))?;
+        match self.padding {
+            Some(_) => padding::unframe(&mut message[..len]),
+            None    => Ok(len),
+        }
+    }
+
+    /// With BOLT 8 framing, decrypt the length prefix at the start of the next transport message
+    /// (its first [`bolt8::LENGTH_HEADER_LEN`](bolt8/constant.LENGTH_HEADER_LEN.html) bytes),
+    /// returning the length of the rest of it. The rest can then be given to
+    /// `read_transport_message` alone.
+    pub fn read_bolt8_length(&mut self, header: &[u8]) -> Result<usize> {
+        if self.initiator && self.pattern.is_oneway() {
+            bail!(ErrorKind::State(StateProblem::OneWay));
+        }
+        let cipher = if self.initiator { &mut self.cipherstates.1 } else { &mut self.cipherstates.0 };
+        match self.bolt8 {
+            Some(ref mut framing) => framing.read_length(cipher, header),
+            None => bail!(ErrorKind::State(StateProblem::NotBolt8)),
+        }
+    }
+
+    /// The remote party's static public key, if it was known or received during the handshake.
+    pub fn get_remote_static(&self) -> Option<&[u8]> {
+        if self.rs.is_on() {
+            Some(&self.rs[..self.dh_len])
+        } else {
+            None
+        }
     }
 
+    /// Rekey the initiator-to-responder cipher.
     pub fn rekey_initiator(&mut self, key: &[u8]) {
         self.cipherstates.rekey_initiator(key)
     }
 
+    /// Rekey the responder-to-initiator cipher.
     pub fn rekey_responder(&mut self, key: &[u8]) {
         self.cipherstates.rekey_responder(key)
     }
@@ -74,6 +159,7 @@ impl TransportState {
         }
     }
 
+    /// Gets the *sending* CipherState's nonce.
     pub fn sending_nonce(&self) -> u64 {
         if self.initiator {
             self.cipherstates.0.nonce()
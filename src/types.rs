@@ -2,6 +2,7 @@
 
 use utils::*;
 use constants::*;
+use std::prelude::v1::*;
 
 /// Provides randomness
 pub trait Random {
@@ -18,7 +19,86 @@ pub trait Dh {
     fn generate(&mut self, rng: &mut Random);
     fn pubkey(&self) -> &[u8];
     fn privkey(&self) -> &[u8];
-    fn dh(&self, pubkey: &[u8], out: &mut [u8]);
+
+    /// The length of the shared secret written by `dh`, where it differs from the public key's
+    /// (e.g. secp256k1's compressed points are 33 bytes, but its shared secret is 32).
+    fn dh_len(&self) -> usize {
+        self.pub_len()
+    }
+
+    /// Compute the shared secret with `pubkey` into `out`.
+    ///
+    /// Returns `Err(())` if `pubkey` is a known low-order point or the shared secret is all zeros.
+    /// The shared secret is still written to `out` in that case, for protocols that opt out of
+    /// the check.
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()>;
+
+    /// Whether the private key last given to `set` is usable. Curves whose scalars have to be
+    /// below the group order (secp256k1, P-256) return `false` for keys that aren't.
+    fn priv_valid(&self) -> bool {
+        true
+    }
+
+    /// Whether `pubkey` encodes a point on the curve. Unlike low-order points, which
+    /// `NoiseBuilder::allow_low_order_points` lets through, invalid public keys always fail the
+    /// handshake. Curves where every encoding is a point (25519, 448) keep the default.
+    fn pub_valid(&self, _pubkey: &[u8]) -> bool {
+        true
+    }
+
+    /// Move the private key into locked memory, where the implementation supports it (see the
+    /// `secure-memory` feature). The builder calls this on static keys before setting them;
+    /// ephemeral keys are left inline.
+    ///
+    /// Returns `Err(())` if the memory couldn't be locked.
+    fn lock(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+/// A key pair generated for a single handshake. Unlike `Dh`, it has no way to import or export
+/// the private key: the handshake only uses its ephemeral key through this trait.
+pub trait EphemeralDh {
+    fn name(&self) -> &'static str;
+    fn pub_len(&self) -> usize;
+
+    /// As [`Dh::dh_len`](trait.Dh.html#method.dh_len).
+    fn dh_len(&self) -> usize {
+        self.pub_len()
+    }
+
+    fn generate(&mut self, rng: &mut Random);
+    fn pubkey(&self) -> &[u8];
+
+    /// As [`Dh::pub_valid`](trait.Dh.html#method.pub_valid).
+    fn pub_valid(&self, _pubkey: &[u8]) -> bool {
+        true
+    }
+
+    /// As [`Dh::dh`](trait.Dh.html#method.dh).
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()>;
+}
+
+/// A static key pair that is only used through `dh`, so its private key can stay in an HSM or a
+/// key agent. Given to the builder with `NoiseBuilder::static_key_provider`.
+pub trait StaticKeyProvider {
+    /// The name of the DH function, which must match the `NoiseParams`.
+    fn name(&self) -> &'static str;
+    fn pub_len(&self) -> usize;
+    fn pubkey(&self) -> &[u8];
+
+    /// As [`Dh::dh_len`](trait.Dh.html#method.dh_len).
+    fn dh_len(&self) -> usize {
+        self.pub_len()
+    }
+
+    /// Compute the shared secret with `pubkey` into `out`.
+    ///
+    /// Returns `Err(())` only if the result couldn't be computed, e.g. because an agent is
+    /// unreachable. That always fails the handshake with `ErrorKind::KeyProvider`, even with
+    /// `allow_low_order_points()`. Low-order points don't need reporting here: the handshake
+    /// rejects the all-zero results they produce itself.
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()>;
 }
 
 /// Provides cipher operations
@@ -63,28 +143,142 @@ pub trait Hash {
         self.input(&opad[..block_len]);
         self.input(&inner_output[..hash_len]);
         self.result(out);
+        ipad.zeroize();
+        opad.zeroize();
+        inner_output.zeroize();
     }
 
     fn hkdf(&mut self, chaining_key: &[u8], input_key_material: &[u8], outputs: usize, out1: &mut [u8], out2: &mut [u8], out3: &mut [u8]) {
         let hash_len = self.hash_len();
-        let mut temp_key = [0u8; MAXHASHLEN];
-        self.hmac(chaining_key, input_key_material, &mut temp_key);
-        self.hmac(&temp_key, &[1u8], out1);
+        let mut temp_key = Zeroizing::new([0u8; MAXHASHLEN]);
+        self.hmac(chaining_key, input_key_material, &mut temp_key[..]);
+        self.hmac(&temp_key[..], &[1u8], out1);
         if outputs == 1 {
             return;
         }
 
-        let mut in2 = [0u8; MAXHASHLEN+1];
-        copy_memory(&out1[0..hash_len], &mut in2);
+        let mut in2 = Zeroizing::new([0u8; MAXHASHLEN+1]);
+        copy_memory(&out1[0..hash_len], &mut in2[..]);
         in2[hash_len] = 2;
-        self.hmac(&temp_key, &in2[..hash_len+1], out2);
+        self.hmac(&temp_key[..], &in2[..hash_len+1], out2);
         if outputs == 2 {
             return;
         }
 
-        let mut in3 = [0u8; MAXHASHLEN+1];
-        copy_memory(&out2[0..hash_len], &mut in3);
+        let mut in3 = Zeroizing::new([0u8; MAXHASHLEN+1]);
+        copy_memory(&out2[0..hash_len], &mut in3[..]);
         in3[hash_len] = 3;
-        self.hmac(&temp_key, &in3[..hash_len+1], out3);
+        self.hmac(&temp_key[..], &in3[..hash_len+1], out3);
+    }
+}
+
+// Boxed primitives, as handed out by a `CryptoResolver`, are primitives too, so the boxed
+// `Session` is just the generic state machines over boxes.
+
+impl<T: Random + ?Sized> Random for Box<T> {
+    fn fill_bytes(&mut self, out: &mut [u8]) {
+        (**self).fill_bytes(out)
+    }
+}
+
+impl<T: Dh + ?Sized> Dh for Box<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn pub_len(&self) -> usize {
+        (**self).pub_len()
+    }
+
+    fn priv_len(&self) -> usize {
+        (**self).priv_len()
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        (**self).set(privkey)
+    }
+
+    fn generate(&mut self, rng: &mut Random) {
+        (**self).generate(rng)
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        (**self).pubkey()
+    }
+
+    fn privkey(&self) -> &[u8] {
+        (**self).privkey()
+    }
+
+    fn dh_len(&self) -> usize {
+        (**self).dh_len()
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        (**self).dh(pubkey, out)
+    }
+
+    fn priv_valid(&self) -> bool {
+        (**self).priv_valid()
+    }
+
+    fn pub_valid(&self, pubkey: &[u8]) -> bool {
+        (**self).pub_valid(pubkey)
+    }
+
+    fn lock(&mut self) -> Result<(), ()> {
+        (**self).lock()
+    }
+}
+
+impl<T: Cipher + ?Sized> Cipher for Box<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn set(&mut self, key: &[u8]) {
+        (**self).set(key)
+    }
+
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut[u8]) -> usize {
+        (**self).encrypt(nonce, authtext, plaintext, out)
+    }
+
+    fn decrypt(&self, nonce: u64, authtext: &[u8], ciphertext: &[u8], out: &mut[u8]) -> Result<usize, ()> {
+        (**self).decrypt(nonce, authtext, ciphertext, out)
+    }
+}
+
+impl<T: Hash + ?Sized> Hash for Box<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn block_len(&self) -> usize {
+        (**self).block_len()
+    }
+
+    fn hash_len(&self) -> usize {
+        (**self).hash_len()
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn input(&mut self, data: &[u8]) {
+        (**self).input(data)
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        (**self).result(out)
+    }
+
+    fn hmac(&mut self, key: &[u8], data: &[u8], out: &mut [u8]) {
+        (**self).hmac(key, data, out)
+    }
+
+    fn hkdf(&mut self, chaining_key: &[u8], input_key_material: &[u8], outputs: usize, out1: &mut [u8], out2: &mut [u8], out3: &mut [u8]) {
+        (**self).hkdf(chaining_key, input_key_material, outputs, out1, out2, out3)
     }
 }
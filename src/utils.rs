@@ -1,4 +1,6 @@
 use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
 
 pub fn copy_memory(input: &[u8], out: &mut [u8]) -> usize {
     for count in 0..input.len() {out[count] = input[count];}
@@ -55,6 +57,86 @@ impl<T> DerefMut for Toggle<T> {
     }
 }
 
+/// Secret material that can be wiped in place.
+///
+/// The writes are volatile and fenced so they aren't optimised away, even when the value is about
+/// to be dropped.
+pub trait Zeroize {
+    fn zeroize(&mut self);
+}
+
+impl Zeroize for u8 {
+    fn zeroize(&mut self) {
+        unsafe { ptr::write_volatile(self, 0) };
+    }
+}
+
+impl<T: Zeroize> Zeroize for [T] {
+    fn zeroize(&mut self) {
+        for item in self.iter_mut() {
+            item.zeroize();
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl<T: Zeroize, const N: usize> Zeroize for [T; N] {
+    fn zeroize(&mut self) {
+        self[..].zeroize();
+    }
+}
+
+impl<T: Zeroize> Zeroize for Option<T> {
+    fn zeroize(&mut self) {
+        if let Some(ref mut inner) = *self {
+            inner.zeroize();
+        }
+    }
+}
+
+/// Owns a value that is zeroized when dropped, for stack buffers and fields holding secrets.
+pub struct Zeroizing<T: Zeroize>(T);
+
+impl<T: Zeroize> Zeroizing<T> {
+    pub fn new(inner: T) -> Self {
+        Zeroizing(inner)
+    }
+}
+
+impl<T: Zeroize> Deref for Zeroizing<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> DerefMut for Zeroizing<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zeroize + Default> Default for Zeroizing<T> {
+    fn default() -> Self {
+        Zeroizing(T::default())
+    }
+}
+
+impl<T: Zeroize> Drop for Zeroizing<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Replace `dst` with `fresh` using a volatile write, to scrub the state of primitives that can't
+/// be zeroized byte by byte. The old value is forgotten rather than dropped, so this is only for
+/// plain-data types that own no allocations.
+pub fn overwrite_volatile<T>(dst: &mut T, fresh: T) {
+    unsafe { ptr::write_volatile(dst, fresh) };
+    compiler_fence(Ordering::SeqCst);
+}
+
 #[cfg(not(feature = "nightly"))]
 pub trait TryInto<T>: Sized {
     type Error;
@@ -78,3 +160,23 @@ impl<T, U> TryInto<U> for T where U: TryFrom<T>
         U::try_from(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::ManuallyDrop;
+
+    #[test]
+    fn test_zeroize_nested() {
+        let mut psks = [Some([0xaau8; 32]), None, Some([0x55u8; 32])];
+        psks.zeroize();
+        assert_eq!(psks, [Some([0u8; 32]), None, Some([0u8; 32])]);
+    }
+
+    #[test]
+    fn test_zeroizing_clears_on_drop() {
+        let mut secret = ManuallyDrop::new(Zeroizing::new([0xaau8; 64]));
+        unsafe { ptr::drop_in_place(&mut *secret) };
+        assert_eq!(&secret.0[..], &[0u8; 64][..]);
+    }
+}
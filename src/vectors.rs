@@ -0,0 +1,421 @@
+//! Generation and verification of test vectors, in the JSON format used by
+//! [cacophony](https://github.com/centromere/cacophony) and
+//! [noise-c](https://github.com/rweather/noise-c).
+//!
+//! Vectors are generated deterministically from a seed, so the same `NoiseParams` and seed always
+//! produce the same file.
+//!
+//! # Examples
+//!
+//! ```
+//! # use snow::vectors::{TestVector, TestVectors, Verification};
+//! let params = "Noise_XXpsk3_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+//! let vector = TestVector::generate(&params, 42).unwrap();
+//! assert_eq!(vector.verify(), Verification::Passed);
+//!
+//! let json = TestVectors { vectors: vec![vector] }.to_json();
+//! let report = TestVectors::from_json(&json).unwrap().verify();
+//! assert!(report.is_success());
+//! ```
+
+use hex::ToHex;
+pub use hexbytes::HexBytes;
+use noise::{default_resolver, CryptoResolver, NoiseBuilder};
+use params::*;
+use serde_json;
+use session::Session;
+use testing::SeededResolver;
+use std::fmt;
+use utils::*;
+use constants::MAXMSGLEN;
+use error::{ErrorKind, Result, InitStage};
+
+/// One message of a test vector: the payload, and the ciphertext it must encrypt to.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TestMessage {
+    pub payload: HexBytes,
+    pub ciphertext: HexBytes,
+}
+
+impl fmt::Debug for TestMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Message")
+    }
+}
+
+/// A single test vector.
+///
+/// Cacophony identifies the protocol with `protocol_name`, while noise-c may instead give it in
+/// `name` or split it up into `pattern`, `dh`, `cipher` and `hash`, and may give a single PSK as
+/// `init_psk`/`resp_psk`. All of these are accepted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestVector {
+    #[serde(default, skip_serializing_if="Option::is_none")] pub name: Option<String>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub protocol_name: Option<String>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub pattern: Option<String>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub dh: Option<String>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub cipher: Option<String>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub hash: Option<String>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub hybrid: Option<String>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub fail: Option<bool>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub fallback: Option<bool>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub fallback_pattern: Option<String>,
+    pub init_prologue: HexBytes,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub init_psk: Option<HexBytes>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub init_psks: Option<Vec<HexBytes>>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub init_static: Option<HexBytes>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub init_ephemeral: Option<HexBytes>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub init_remote_static: Option<HexBytes>,
+    pub resp_prologue: HexBytes,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub resp_psk: Option<HexBytes>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub resp_psks: Option<Vec<HexBytes>>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub resp_static: Option<HexBytes>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub resp_ephemeral: Option<HexBytes>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub resp_remote_static: Option<HexBytes>,
+    #[serde(default, skip_serializing_if="Option::is_none")] pub handshake_hash: Option<HexBytes>,
+    pub messages: Vec<TestMessage>,
+}
+
+/// A file of test vectors.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestVectors {
+    pub vectors: Vec<TestVector>,
+}
+
+impl TestVectors {
+    /// Parse a JSON vectors file.
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Serialize the vectors as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("vectors always serialize")
+    }
+
+    /// Verify every vector, collecting the results.
+    pub fn verify(&self) -> VerificationReport {
+        self.verify_with(&*default_resolver())
+    }
+
+    /// Verify every vector using primitives from `resolver`, collecting the results.
+    pub fn verify_with(&self, resolver: &CryptoResolver) -> VerificationReport {
+        let mut report = VerificationReport::default();
+        for vector in &self.vectors {
+            match vector.verify_with(resolver) {
+                Verification::Passed => report.passed += 1,
+                Verification::Ignored(_) => report.ignored += 1,
+                Verification::Failed(reason) => {
+                    report.failures.push((vector.protocol_name().unwrap_or_default(), reason))
+                },
+            }
+        }
+        report
+    }
+}
+
+/// The outcome of verifying a single vector.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Verification {
+    Passed,
+    /// The vector uses a feature or primitive that isn't supported, with the reason.
+    Ignored(String),
+    /// The vector failed, with a description of the first mismatch.
+    Failed(String),
+}
+
+/// The outcome of verifying a file of vectors.
+#[derive(Default, Clone, Debug)]
+pub struct VerificationReport {
+    pub passed: usize,
+    pub ignored: usize,
+    /// The protocol name and failure description of each failed vector.
+    pub failures: Vec<(String, String)>,
+}
+
+impl VerificationReport {
+    /// Whether no vector failed.
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl TestVector {
+    /// The full protocol name, from whichever of the supported fields the vector uses.
+    pub fn protocol_name(&self) -> Option<String> {
+        if let Some(ref name) = self.protocol_name {
+            return Some(name.clone());
+        }
+        if let (&Some(ref pattern), &Some(ref dh), &Some(ref cipher), &Some(ref hash))
+            = (&self.pattern, &self.dh, &self.cipher, &self.hash) {
+            return Some(format!("Noise_{}_{}_{}_{}", pattern, dh, cipher, hash));
+        }
+        self.name.clone()
+    }
+
+    fn psks(single: &Option<HexBytes>, list: &Option<Vec<HexBytes>>) -> Vec<HexBytes> {
+        match (single, list) {
+            (_, &Some(ref list)) => list.clone(),
+            (&Some(ref psk), _) => vec![psk.clone()],
+            _ => vec![],
+        }
+    }
+
+    /// Generate a vector for `params` with keys, PSKs and payloads drawn from a
+    /// [`SeededResolver`](../testing/struct.SeededResolver.html).
+    ///
+    /// The vector covers the whole handshake, plus two transport messages.
+    ///
+    /// # Errors
+    ///
+    /// Will result in `ErrorKind::Init` if the default resolver doesn't support `params`.
+    pub fn generate(params: &NoiseParams, seed: u64) -> Result<TestVector> {
+        let resolver = SeededResolver::new(seed);
+        let mut rng = resolver.resolve_rng().ok_or(ErrorKind::Init(InitStage::GetRngImpl))?;
+        let mut keys = vec![];
+        for _ in 0..4 {
+            let mut dh = resolver.resolve_dh(&params.dh).ok_or(ErrorKind::Init(InitStage::GetDhImpl))?;
+            dh.generate(&mut *rng);
+            keys.push(dh);
+        }
+        let (is, ie, rs, re) = (&keys[0], &keys[1], &keys[2], &keys[3]);
+        let mut random = |len: usize| -> HexBytes {
+            let mut bytes = vec![0u8; len];
+            rng.fill_bytes(&mut bytes);
+            bytes.into()
+        };
+
+        let pattern = params.handshake.pattern;
+        let psk_count = params.handshake.modifiers.list.iter()
+            .filter(|m| if let HandshakeModifier::Psk(_) = **m { true } else { false })
+            .count();
+        let psks: Vec<HexBytes> = (0..psk_count).map(|_| random(32)).collect();
+        let prologue: HexBytes = b"There is no right and wrong. There's only fun and boring.".to_vec().into();
+        let init_static = if pattern.needs_local_static_key(true) { Some(is.privkey().to_vec().into()) } else { None };
+        let resp_static = if pattern.needs_local_static_key(false) { Some(rs.privkey().to_vec().into()) } else { None };
+        let init_remote_static = if pattern.need_known_remote_pubkey(true) { Some(rs.pubkey().to_vec().into()) } else { None };
+        let resp_remote_static = if pattern.need_known_remote_pubkey(false) { Some(is.pubkey().to_vec().into()) } else { None };
+
+        let tokens = HandshakeTokens::try_from(params.handshake.clone())
+            .map_err(|_| ErrorKind::Init(InitStage::ValidateHandshakePattern))?;
+        let payloads: Vec<HexBytes> = (0..tokens.msg_patterns.len() + 2).map(|_| random(32)).collect();
+
+        let mut vector = TestVector {
+            name: None,
+            protocol_name: Some(params.name.clone()),
+            pattern: None,
+            dh: None,
+            cipher: None,
+            hash: None,
+            hybrid: None,
+            fail: None,
+            fallback: None,
+            fallback_pattern: None,
+            init_prologue: prologue.clone(),
+            init_psk: None,
+            init_psks: if psk_count > 0 { Some(psks.clone()) } else { None },
+            init_static: init_static,
+            init_ephemeral: Some(ie.privkey().to_vec().into()),
+            init_remote_static: init_remote_static,
+            resp_prologue: prologue,
+            resp_psk: None,
+            resp_psks: if psk_count > 0 { Some(psks) } else { None },
+            resp_static: resp_static,
+            resp_ephemeral: Some(re.privkey().to_vec().into()),
+            resp_remote_static: resp_remote_static,
+            handshake_hash: None,
+            messages: payloads.into_iter()
+                .map(|payload| TestMessage { payload: payload, ciphertext: Vec::new().into() })
+                .collect(),
+        };
+
+        let ciphertexts = vector.run(params, &*default_resolver())?;
+        for (message, ciphertext) in vector.messages.iter_mut().zip(ciphertexts) {
+            message.ciphertext = ciphertext.into();
+        }
+        Ok(vector)
+    }
+
+    fn build_session_pair(&self, params: &NoiseParams, resolver: &CryptoResolver) -> Result<(Session, Session)> {
+        let init_psks = Self::psks(&self.init_psk, &self.init_psks);
+        let resp_psks = Self::psks(&self.resp_psk, &self.resp_psks);
+
+        let mut init_builder = NoiseBuilder::with_resolver(params.clone(), Box::new(resolver));
+        let mut resp_builder = NoiseBuilder::with_resolver(params.clone(), Box::new(resolver));
+        let mut psk_index = 0;
+        for modifier in &params.handshake.modifiers.list {
+            if let HandshakeModifier::Psk(n) = *modifier {
+                if let Some(psk) = init_psks.get(psk_index) {
+                    init_builder = init_builder.psk(n, psk);
+                }
+                if let Some(psk) = resp_psks.get(psk_index) {
+                    resp_builder = resp_builder.psk(n, psk);
+                }
+                psk_index += 1;
+            }
+        }
+
+        if let Some(ref init_s) = self.init_static {
+            init_builder = init_builder.local_private_key(init_s);
+        }
+        if let Some(ref resp_s) = self.resp_static {
+            resp_builder = resp_builder.local_private_key(resp_s);
+        }
+        if let Some(ref init_remote_static) = self.init_remote_static {
+            init_builder = init_builder.remote_public_key(init_remote_static);
+        }
+        if let Some(ref resp_remote_static) = self.resp_remote_static {
+            resp_builder = resp_builder.remote_public_key(resp_remote_static);
+        }
+        if let Some(ref init_e) = self.init_ephemeral {
+            init_builder = init_builder.fixed_ephemeral_key(init_e);
+        }
+        if let Some(ref resp_e) = self.resp_ephemeral {
+            resp_builder = resp_builder.fixed_ephemeral_key(resp_e);
+        }
+
+        let init = init_builder.prologue(&self.init_prologue).build_initiator()?;
+        let resp = resp_builder.prologue(&self.resp_prologue).build_responder()?;
+        Ok((init, resp))
+    }
+
+    /// Send every message's payload through a fresh session pair, returning the ciphertexts.
+    fn run(&self, params: &NoiseParams, resolver: &CryptoResolver) -> Result<Vec<Vec<u8>>> {
+        let (mut init, mut resp) = self.build_session_pair(params, resolver)?;
+        let is_oneway = params.handshake.pattern.is_oneway();
+        let (mut sendbuf, mut recvbuf) = (vec![0u8; MAXMSGLEN], vec![0u8; MAXMSGLEN]);
+        let mut ciphertexts = vec![];
+        let mut in_handshake = true;
+        for (i, message) in self.messages.iter().enumerate() {
+            if in_handshake && init.is_handshake_finished() && resp.is_handshake_finished() {
+                init = init.into_transport_mode()?;
+                resp = resp.into_transport_mode()?;
+                in_handshake = false;
+            }
+            let (send, recv) = if (is_oneway && !in_handshake) || i % 2 == 0 {
+                (&mut init, &mut resp)
+            } else {
+                (&mut resp, &mut init)
+            };
+            let len = send.write_message(&message.payload, &mut sendbuf)?;
+            recv.read_message(&sendbuf[..len], &mut recvbuf)?;
+            ciphertexts.push(sendbuf[..len].to_vec());
+        }
+        Ok(ciphertexts)
+    }
+
+    /// Check that this implementation produces the vector's ciphertexts (or, for vectors marked
+    /// `fail`, that it rejects them).
+    pub fn verify(&self) -> Verification {
+        self.verify_with(&*default_resolver())
+    }
+
+    /// Like `verify`, but using primitives from `resolver`.
+    pub fn verify_with(&self, resolver: &CryptoResolver) -> Verification {
+        let name = match self.protocol_name() {
+            Some(name) => name,
+            None => return Verification::Failed("no protocol name".to_owned()),
+        };
+        let params: NoiseParams = match name.parse() {
+            Ok(params) => params,
+            Err(_) => return Verification::Ignored(format!("unsupported protocol {}", name)),
+        };
+        if self.hybrid.is_some() || self.fallback == Some(true) || params.handshake.is_fallback() {
+            return Verification::Ignored("hybrid and fallback handshakes are unsupported".to_owned());
+        }
+        if resolver.resolve_dh(&params.dh).is_none() {
+            return Verification::Ignored(format!("unsupported DH function in {}", name));
+        }
+        if resolver.resolve_cipher(&params.cipher).is_none() {
+            return Verification::Ignored(format!("unsupported cipher in {}", name));
+        }
+        if resolver.resolve_hash(&params.hash).is_none() {
+            return Verification::Ignored(format!("unsupported hash function in {}", name));
+        }
+
+        let result = self.run(&params, resolver).map_err(|e| e.to_string()).and_then(|ciphertexts| {
+            for (i, (message, actual)) in self.messages.iter().zip(ciphertexts).enumerate() {
+                if actual != **message.ciphertext {
+                    return Err(format!("message {}\nplaintext: {}\nexpected:  {}\nactual:    {}",
+                                       i, message.payload.to_hex(), message.ciphertext.to_hex(), actual.to_hex()));
+                }
+            }
+            Ok(())
+        });
+        match (result, self.fail == Some(true)) {
+            (Ok(()), false) | (Err(_), true) => Verification::Passed,
+            (Err(reason), false) => Verification::Failed(reason),
+            (Ok(()), true) => Verification::Failed("vector marked as failing succeeded".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generation_is_deterministic() {
+        let params: NoiseParams = "Noise_IKpsk2_25519_AESGCM_SHA256".parse().unwrap();
+        let a = TestVectors { vectors: vec![TestVector::generate(&params, 7).unwrap()] }.to_json();
+        let b = TestVectors { vectors: vec![TestVector::generate(&params, 7).unwrap()] }.to_json();
+        let c = TestVectors { vectors: vec![TestVector::generate(&params, 8).unwrap()] }.to_json();
+        assert_eq!(a, b);
+        assert!(a != c);
+    }
+
+    #[test]
+    fn test_tampered_vector_fails() {
+        let params: NoiseParams = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let mut vector = TestVector::generate(&params, 1).unwrap();
+        assert_eq!(vector.verify(), Verification::Passed);
+        let mut ciphertext = vector.messages[2].ciphertext.to_vec();
+        ciphertext[0] ^= 1;
+        vector.messages[2].ciphertext = ciphertext.into();
+        match vector.verify() {
+            Verification::Failed(reason) => assert!(reason.starts_with("message 2")),
+            other => panic!("unexpected {:?}", other),
+        }
+        vector.fail = Some(true);
+        assert_eq!(vector.verify(), Verification::Passed);
+    }
+
+    #[test]
+    fn test_noise_c_style_names() {
+        let params: NoiseParams = "Noise_NNpsk0_25519_ChaChaPoly_SHA512".parse().unwrap();
+        let mut vector = TestVector::generate(&params, 3).unwrap();
+        vector.protocol_name = None;
+        vector.pattern = Some("NNpsk0".to_owned());
+        vector.dh = Some("25519".to_owned());
+        vector.cipher = Some("ChaChaPoly".to_owned());
+        vector.hash = Some("SHA512".to_owned());
+        vector.init_psk = vector.init_psks.take().map(|mut psks| psks.remove(0));
+        vector.resp_psk = vector.resp_psks.take().map(|mut psks| psks.remove(0));
+        assert_eq!(vector.protocol_name().unwrap(), params.name);
+        assert_eq!(vector.verify(), Verification::Passed);
+    }
+
+    #[test]
+    fn test_unsupported_dh_is_ignored() {
+        let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA512".parse().unwrap();
+        let mut vector = TestVector::generate(&params, 3).unwrap();
+        vector.protocol_name = Some("Noise_NN_448_ChaChaPoly_SHA512".to_owned());
+        match vector.verify() {
+            Verification::Ignored(_) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unsupported_cipher_and_hash_are_ignored() {
+        let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA512".parse().unwrap();
+        let mut vector = TestVector::generate(&params, 3).unwrap();
+        for name in &["Noise_NN_25519_XChaChaPoly_SHA512", "Noise_NN_25519_ChaChaPoly_SHA3"] {
+            vector.protocol_name = Some(name.to_string());
+            match vector.verify() {
+                Verification::Ignored(_) => {},
+                other => panic!("unexpected {:?} for {}", other, name),
+            }
+        }
+    }
+}
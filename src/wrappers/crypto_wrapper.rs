@@ -15,12 +15,45 @@ use byteorder::{ByteOrder, BigEndian, LittleEndian};
 
 use types::*;
 use constants::*;
-use utils::copy_memory;
+use utils::{copy_memory, overwrite_volatile, Zeroize};
+use secmem::SecretBuf;
 use std::io::{Cursor, Write};
 
+/// The Curve25519 points of order 1, 2, 4 and 8, plus the non-canonical encodings of 0 and 1,
+/// ignoring the unused top bit. See https://cr.yp.to/ecdh.html#validate.
+const LOW_ORDER_25519: [[u8; 32]; 7] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0xe0, 0xeb, 0x7a, 0x7c, 0x3b, 0x41, 0xb8, 0xae, 0x16, 0x56, 0xe3, 0xfa, 0xf1, 0x9f, 0xc4, 0x6a,
+     0xda, 0x09, 0x8d, 0xeb, 0x9c, 0x32, 0xb1, 0xfd, 0x86, 0x62, 0x05, 0x16, 0x5f, 0x49, 0xb8, 0x00],
+    [0x5f, 0x9c, 0x95, 0xbc, 0xa3, 0x50, 0x8c, 0x24, 0xb1, 0xd0, 0xb1, 0x55, 0x9c, 0x83, 0xef, 0x5b,
+     0x04, 0x44, 0x5c, 0xc4, 0x58, 0x1c, 0x8e, 0x86, 0xd8, 0x22, 0x4e, 0xdd, 0xd0, 0x9f, 0x11, 0x57],
+    [0xec, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+     0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+    [0xed, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+     0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+    [0xee, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+     0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+];
+
+/// Whether `pubkey` is one of `LOW_ORDER_25519`, without branching on its contents.
+fn is_low_order_25519(pubkey: &[u8]) -> bool {
+    let mut found = 0u8;
+    for point in &LOW_ORDER_25519 {
+        let mut diff = (pubkey[31] & 0x7f) ^ point[31];
+        for i in 0..31 {
+            diff |= pubkey[i] ^ point[i];
+        }
+        found |= (diff == 0) as u8;
+    }
+    found == 1
+}
+
 #[derive(Default)]
 pub struct Dh25519 {
-    privkey: [u8; 32],
+    privkey: SecretBuf<[u8; 32]>,
     pubkey:  [u8; 32],
 }
 
@@ -66,17 +99,17 @@ impl Dh for Dh25519 {
     }
 
     fn set(&mut self, privkey: &[u8]) {
-        copy_memory(privkey, &mut self.privkey); /* RUSTSUCKS: Why can't I convert slice -> array? */
-        let pubkey = curve25519_base(&self.privkey);
+        copy_memory(privkey, &mut self.privkey[..]); /* RUSTSUCKS: Why can't I convert slice -> array? */
+        let pubkey = curve25519_base(&self.privkey[..]);
         copy_memory(&pubkey, &mut self.pubkey);
     }
 
     fn generate(&mut self, rng: &mut Random) {
-        rng.fill_bytes(&mut self.privkey);
+        rng.fill_bytes(&mut self.privkey[..]);
         self.privkey[0]  &= 248;
         self.privkey[31] &= 127;
         self.privkey[31] |= 64;
-        let pubkey = curve25519_base(&self.privkey);
+        let pubkey = curve25519_base(&self.privkey[..]);
         copy_memory(&pubkey, &mut self.pubkey);
     }
 
@@ -85,14 +118,61 @@ impl Dh for Dh25519 {
     }
 
     fn privkey(&self) -> &[u8] {
-        &self.privkey
+        &self.privkey[..]
     }
 
-    fn dh(&self, pubkey: &[u8], out: &mut [u8]) {
-        let result = curve25519(&self.privkey, pubkey);
+    fn lock(&mut self) -> Result<(), ()> {
+        self.privkey.lock()
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        let pubkey = &pubkey[..32];
+        let mut result = curve25519(&self.privkey[..], pubkey);
         copy_memory(&result, out);
+        let degenerate = is_low_order_25519(pubkey) | (result.iter().fold(0, |acc, b| acc | b) == 0);
+        result.zeroize();
+        if degenerate { Err(()) } else { Ok(()) }
+    }
+
+}
+
+impl Drop for CipherAESGCM {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl Drop for CipherChaChaPoly {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+// The hashers are plain data, so their (possibly key-derived) state is scrubbed by overwriting it
+// with a fresh one.
+
+impl Drop for HashSHA256 {
+    fn drop(&mut self) {
+        overwrite_volatile(&mut self.hasher, Sha256::new());
+    }
+}
+
+impl Drop for HashSHA512 {
+    fn drop(&mut self) {
+        overwrite_volatile(&mut self.hasher, Sha512::new());
     }
+}
 
+impl Drop for HashBLAKE2b {
+    fn drop(&mut self) {
+        overwrite_volatile(&mut self.hasher, Blake2b::new(64));
+    }
+}
+
+impl Drop for HashBLAKE2s {
+    fn drop(&mut self) {
+        overwrite_volatile(&mut self.hasher, Blake2s::new(32));
+    }
 }
 
 impl Cipher for CipherAESGCM {
@@ -326,6 +406,8 @@ mod tests {
     use self::hex::{FromHex, ToHex};
     use super::crypto::poly1305::Poly1305;
     use super::crypto::mac::Mac;
+    use std::mem::ManuallyDrop;
+    use std::ptr;
 
     #[test]
     fn test_sha256() {
@@ -383,13 +465,49 @@ mod tests {
     // Curve25519 test - draft-curves-10
         let mut keypair:Dh25519 = Default::default();
         let scalar = Vec::<u8>::from_hex("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4").unwrap();
-        copy_memory(&scalar, &mut keypair.privkey);
+        copy_memory(&scalar, &mut keypair.privkey[..]);
         let public = Vec::<u8>::from_hex("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c").unwrap();
         let mut output = [0u8; 32];
-        keypair.dh(&public, &mut output);
+        keypair.dh(&public, &mut output).unwrap();
         assert!(output.to_hex() == "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552");
     }
 
+    #[test]
+    fn test_private_key_cleared_on_drop() {
+        let mut keypair = ManuallyDrop::new(Dh25519::default());
+        keypair.set(&[0xaau8; 32]);
+        unsafe { ptr::drop_in_place(&mut *keypair) };
+        assert_eq!(*keypair.privkey, [0u8; 32]);
+    }
+
+    #[test]
+    fn test_cipher_keys_cleared_on_drop() {
+        let mut aesgcm = ManuallyDrop::new(CipherAESGCM::default());
+        aesgcm.set(&[0xaau8; 32]);
+        unsafe { ptr::drop_in_place(&mut *aesgcm) };
+        assert_eq!(aesgcm.key, [0u8; 32]);
+
+        let mut chachapoly = ManuallyDrop::new(CipherChaChaPoly::default());
+        chachapoly.set(&[0xaau8; 32]);
+        unsafe { ptr::drop_in_place(&mut *chachapoly) };
+        assert_eq!(chachapoly.key, [0u8; 32]);
+    }
+
+    #[test]
+    fn test_curve25519_rejects_low_order_points() {
+        let mut keypair: Dh25519 = Default::default();
+        keypair.generate(&mut ::wrappers::rand_wrapper::RandomOs::default());
+        let mut output = [0xffu8; 32];
+        for point in &LOW_ORDER_25519 {
+            assert!(keypair.dh(point, &mut output).is_err());
+            assert_eq!(output, [0u8; 32]);
+
+            let mut high_bit = *point;
+            high_bit[31] |= 0x80;
+            assert!(keypair.dh(&high_bit, &mut output).is_err());
+        }
+    }
+
     #[test]
     fn test_aes256_gcm() {
     //AES256-GCM tests - gcm-spec.pdf
@@ -3,3 +3,4 @@
 pub mod crypto_wrapper;
 pub mod rand_wrapper;
 #[cfg(feature = "ring-resolver")] pub mod ring_wrapper;
+#[cfg(feature = "rustcrypto-resolver")] pub mod rustcrypto_wrapper;
@@ -1,10 +1,13 @@
 use byteorder::{ByteOrder, BigEndian, LittleEndian};
-use ring::aead;
-use ring::digest;
+use ring::{aead, agreement, digest, error, rand};
+use ring::rand::SecureRandom;
+use untrusted;
 use constants::TAGLEN;
 use noise::{CryptoResolver, DefaultResolver};
 use params::{DHChoice, HashChoice, CipherChoice};
+use secmem::SecretBuf;
 use types::{Random, Dh, Hash, Cipher};
+use utils::{copy_memory, overwrite_volatile};
 
 pub struct RingAcceleratedResolver {
     parent: DefaultResolver,
@@ -19,11 +22,14 @@ impl RingAcceleratedResolver {
 #[cfg(feature = "ring")]
 impl CryptoResolver for RingAcceleratedResolver {
     fn resolve_rng(&self) -> Option<Box<Random>> {
-        self.parent.resolve_rng()
+        Some(Box::new(RandomRing::default()))
     }
 
     fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
-        self.parent.resolve_dh(choice)
+        match *choice {
+            DHChoice::Curve25519 => Some(Box::new(DhX25519::default())),
+            _ => self.parent.resolve_dh(choice),
+        }
     }
 
     fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
@@ -38,7 +44,112 @@ impl CryptoResolver for RingAcceleratedResolver {
         match *choice {
             CipherChoice::AESGCM => Some(Box::new(CipherAESGCM::default())),
             CipherChoice::ChaChaPoly => Some(Box::new(CipherChaChaPoly::default())),
+            _ => self.parent.resolve_cipher(choice),
+        }
+    }
+}
+
+pub struct RandomRing {
+    rng: rand::SystemRandom,
+}
+
+impl Default for RandomRing {
+    fn default() -> Self {
+        RandomRing { rng: rand::SystemRandom::new() }
+    }
+}
+
+impl Random for RandomRing {
+    fn fill_bytes(&mut self, out: &mut [u8]) {
+        self.rng.fill(out).unwrap();
+    }
+}
+
+/// Feeds a stored private key to `EphemeralPrivateKey::generate`.
+///
+/// ring only does X25519 through `agree_ephemeral`, which consumes its key so it can't be used
+/// twice. Static keys (and ephemeral keys used in more than one DH of a handshake) are kept as
+/// bytes instead, and rebuilt into a single-use ring key for every `dh()` by handing ring this
+/// "RNG", which fills the key with the stored bytes. ring's X25519 private key is exactly the
+/// 32 bytes it asks the RNG for.
+struct StoredKey<'a>(&'a [u8]);
+
+impl<'a> SecureRandom for StoredKey<'a> {
+    fn fill(&self, dest: &mut [u8]) -> Result<(), error::Unspecified> {
+        if dest.len() != self.0.len() {
+            return Err(error::Unspecified);
         }
+        dest.copy_from_slice(self.0);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct DhX25519 {
+    privkey: SecretBuf<[u8; 32]>,
+    pubkey:  [u8; 32],
+}
+
+impl DhX25519 {
+    fn ring_key(&self) -> agreement::EphemeralPrivateKey {
+        agreement::EphemeralPrivateKey::generate(&agreement::X25519, &StoredKey(&self.privkey[..])).unwrap()
+    }
+
+    fn derive_pubkey(&mut self) {
+        let key = self.ring_key();
+        key.compute_public_key(&mut self.pubkey).unwrap();
+    }
+}
+
+impl Dh for DhX25519 {
+    fn name(&self) -> &'static str {
+        "25519"
+    }
+
+    fn pub_len(&self) -> usize {
+        32
+    }
+
+    fn priv_len(&self) -> usize {
+        32
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        copy_memory(privkey, &mut self.privkey[..]);
+        self.derive_pubkey();
+    }
+
+    fn generate(&mut self, rng: &mut Random) {
+        rng.fill_bytes(&mut self.privkey[..]);
+        self.privkey[0]  &= 248;
+        self.privkey[31] &= 127;
+        self.privkey[31] |= 64;
+        self.derive_pubkey();
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn privkey(&self) -> &[u8] {
+        &self.privkey[..]
+    }
+
+    fn lock(&mut self) -> Result<(), ()> {
+        self.privkey.lock()
+    }
+
+    // ring refuses all-zero results itself, which is exactly what low-order points produce.
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        let peer = untrusted::Input::from(&pubkey[..32]);
+        let result = agreement::agree_ephemeral(self.ring_key(), &agreement::X25519, peer, (), |shared| {
+            copy_memory(shared, out);
+            Ok(())
+        });
+        if result.is_err() {
+            copy_memory(&[0u8; 32], out);
+        }
+        result
     }
 }
 
@@ -62,8 +173,8 @@ impl Cipher for CipherAESGCM {
     }
 
     fn set(&mut self, key: &[u8]) {
-        self.sealing = aead::SealingKey::new(&aead::AES_256_GCM, key).unwrap();
-        self.opening = aead::OpeningKey::new(&aead::AES_256_GCM, key).unwrap();
+        overwrite_volatile(&mut self.sealing, aead::SealingKey::new(&aead::AES_256_GCM, key).unwrap());
+        overwrite_volatile(&mut self.opening, aead::OpeningKey::new(&aead::AES_256_GCM, key).unwrap());
     }
 
     fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
@@ -90,6 +201,15 @@ impl Cipher for CipherAESGCM {
     }
 }
 
+// ring's key schedules can't be zeroized directly, so they're scrubbed by overwriting them with
+// the schedule of the all-zero key.
+impl Drop for CipherAESGCM {
+    fn drop(&mut self) {
+        overwrite_volatile(&mut self.sealing, aead::SealingKey::new(&aead::AES_256_GCM, &[0u8; 32]).unwrap());
+        overwrite_volatile(&mut self.opening, aead::OpeningKey::new(&aead::AES_256_GCM, &[0u8; 32]).unwrap());
+    }
+}
+
 pub struct CipherChaChaPoly {
     sealing: aead::SealingKey,
     opening: aead::OpeningKey,
@@ -110,8 +230,8 @@ impl Cipher for CipherChaChaPoly {
     }
 
     fn set(&mut self, key: &[u8]) {
-        self.sealing = aead::SealingKey::new(&aead::CHACHA20_POLY1305, key).unwrap();
-        self.opening = aead::OpeningKey::new(&aead::CHACHA20_POLY1305, key).unwrap();
+        overwrite_volatile(&mut self.sealing, aead::SealingKey::new(&aead::CHACHA20_POLY1305, key).unwrap());
+        overwrite_volatile(&mut self.opening, aead::OpeningKey::new(&aead::CHACHA20_POLY1305, key).unwrap());
     }
 
     fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
@@ -137,6 +257,13 @@ impl Cipher for CipherChaChaPoly {
         Ok(out0.len())
     }
 }
+impl Drop for CipherChaChaPoly {
+    fn drop(&mut self) {
+        overwrite_volatile(&mut self.sealing, aead::SealingKey::new(&aead::CHACHA20_POLY1305, &[0u8; 32]).unwrap());
+        overwrite_volatile(&mut self.opening, aead::OpeningKey::new(&aead::CHACHA20_POLY1305, &[0u8; 32]).unwrap());
+    }
+}
+
 pub struct HashSHA256 {
     context: digest::Context,
 }
@@ -173,6 +300,12 @@ impl Hash for HashSHA256 {
     }
 }
 
+impl Drop for HashSHA256 {
+    fn drop(&mut self) {
+        overwrite_volatile(&mut self.context, digest::Context::new(&digest::SHA256));
+    }
+}
+
 pub struct HashSHA512 {
     context: digest::Context,
 }
@@ -209,4 +342,111 @@ impl Hash for HashSHA512 {
     }
 }
 
+impl Drop for HashSHA512 {
+    fn drop(&mut self) {
+        overwrite_volatile(&mut self.context, digest::Context::new(&digest::SHA512));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate hex;
+
+    use super::*;
+    use self::hex::{FromHex, ToHex};
+    use std::cell::RefCell;
+    use std::mem::ManuallyDrop;
+    use std::ptr;
+    use wrappers::crypto_wrapper::Dh25519;
+    use wrappers::rand_wrapper::RandomOs;
+
+    /// Records the size of every fill ring asks for.
+    struct RecordingRandom(RefCell<Vec<usize>>);
+
+    impl SecureRandom for RecordingRandom {
+        fn fill(&self, dest: &mut [u8]) -> Result<(), error::Unspecified> {
+            self.0.borrow_mut().push(dest.len());
+            for byte in dest.iter_mut() {
+                *byte = 0x42;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_ring_draws_one_x25519_key() {
+        // `StoredKey` only works if ring draws the whole private key in a single 32-byte fill.
+        let rng = RecordingRandom(RefCell::new(vec![]));
+        agreement::EphemeralPrivateKey::generate(&agreement::X25519, &rng).unwrap();
+        assert_eq!(*rng.0.borrow(), vec![32]);
+    }
+
+    #[test]
+    fn test_x25519_rfc7748() {
+        // RFC 7748 section 6.1
+        let mut alice = DhX25519::default();
+        alice.set(&Vec::<u8>::from_hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a").unwrap());
+        assert_eq!(alice.pubkey().to_hex(), "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a");
+
+        let mut bob = DhX25519::default();
+        bob.set(&Vec::<u8>::from_hex("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb").unwrap());
+        let mut output = [0u8; 32];
+        alice.dh(bob.pubkey(), &mut output).unwrap();
+        assert_eq!(output.to_hex(), "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+
+        // The same key keeps working after its first agreement.
+        let mut again = [0u8; 32];
+        alice.dh(bob.pubkey(), &mut again).unwrap();
+        assert_eq!(output, again);
+    }
 
+    #[test]
+    fn test_x25519_matches_default() {
+        let mut rng = RandomOs::default();
+        let mut ring_key = DhX25519::default();
+        ring_key.generate(&mut rng);
+        let mut default_key = Dh25519::default();
+        default_key.set(ring_key.privkey());
+        assert_eq!(ring_key.pubkey(), default_key.pubkey());
+
+        let mut peer = Dh25519::default();
+        peer.generate(&mut rng);
+        let (mut ring_out, mut default_out) = ([0u8; 32], [0u8; 32]);
+        ring_key.dh(peer.pubkey(), &mut ring_out).unwrap();
+        default_key.dh(peer.pubkey(), &mut default_out).unwrap();
+        assert_eq!(ring_out, default_out);
+    }
+
+    #[test]
+    fn test_x25519_rejects_low_order_points() {
+        let mut key = DhX25519::default();
+        key.generate(&mut RandomRing::default());
+        let mut output = [0xffu8; 32];
+        assert!(key.dh(&[0u8; 32], &mut output).is_err());
+        assert_eq!(output, [0u8; 32]);
+        let mut order_8 = [0u8; 32];
+        order_8.copy_from_slice(&Vec::<u8>::from_hex("e0eb7a7c3b41b8ae1656e3faf19fc46ada098deb9c32b1fd866205165f49b800").unwrap());
+        assert!(key.dh(&order_8, &mut output).is_err());
+    }
+
+    fn seal_empty(cipher: &Cipher) -> [u8; TAGLEN] {
+        let mut tag = [0u8; TAGLEN];
+        cipher.encrypt(0, &[], &[], &mut tag);
+        tag
+    }
+
+    #[test]
+    fn test_key_schedule_cleared_on_drop() {
+        let mut aesgcm = ManuallyDrop::new(CipherAESGCM::default());
+        aesgcm.set(&[0xaau8; 32]);
+        assert!(seal_empty(&*aesgcm) != seal_empty(&CipherAESGCM::default()));
+        unsafe { ptr::drop_in_place(&mut *aesgcm) };
+        assert_eq!(seal_empty(&*aesgcm), seal_empty(&CipherAESGCM::default()));
+
+        let mut chachapoly = ManuallyDrop::new(CipherChaChaPoly::default());
+        chachapoly.set(&[0xaau8; 32]);
+        assert!(seal_empty(&*chachapoly) != seal_empty(&CipherChaChaPoly::default()));
+        unsafe { ptr::drop_in_place(&mut *chachapoly) };
+        assert_eq!(seal_empty(&*chachapoly), seal_empty(&CipherChaChaPoly::default()));
+    }
+}
@@ -0,0 +1,594 @@
+extern crate x25519_dalek;
+extern crate k256;
+extern crate p256;
+extern crate aes_gcm;
+extern crate chacha20poly1305;
+extern crate sha2;
+extern crate blake2;
+
+use self::x25519_dalek::{PublicKey, StaticSecret};
+use self::k256::elliptic_curve::sec1::ToEncodedPoint;
+use self::aes_gcm::Aes256Gcm;
+use self::aes_gcm::aead::{AeadInPlace, KeyInit};
+use self::aes_gcm::aead::generic_array::GenericArray;
+use self::chacha20poly1305::ChaCha20Poly1305;
+use self::sha2::{Digest, Sha256, Sha512};
+use self::blake2::{Blake2b512, Blake2s256};
+
+use byteorder::{ByteOrder, BigEndian, LittleEndian};
+use constants::TAGLEN;
+use noise::{CryptoResolver, DefaultResolver};
+use params::{DHChoice, HashChoice, CipherChoice};
+use secmem::SecretBuf;
+use types::{Random, Dh, Hash, Cipher};
+use utils::{copy_memory, overwrite_volatile, Zeroizing};
+
+/// Resolves every primitive with the RustCrypto and dalek crates, except the RNG, which comes from
+/// `DefaultResolver`. It's also the resolver for secp256k1 and P-256.
+pub struct RustCryptoResolver {
+    parent: DefaultResolver,
+}
+
+impl RustCryptoResolver {
+    pub fn new() -> Self {
+        RustCryptoResolver { parent: DefaultResolver }
+    }
+}
+
+impl CryptoResolver for RustCryptoResolver {
+    fn resolve_rng(&self) -> Option<Box<Random>> {
+        self.parent.resolve_rng()
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+        match *choice {
+            DHChoice::Curve25519 => Some(Box::new(Dh25519::default())),
+            DHChoice::Secp256k1  => Some(Box::new(DhSecp256k1::default())),
+            DHChoice::P256       => Some(Box::new(DhP256::default())),
+            _ => None,
+        }
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+        match *choice {
+            HashChoice::SHA256  => Some(Box::new(HashSHA256::default())),
+            HashChoice::SHA512  => Some(Box::new(HashSHA512::default())),
+            HashChoice::Blake2s => Some(Box::new(HashBLAKE2s::default())),
+            HashChoice::Blake2b => Some(Box::new(HashBLAKE2b::default())),
+            _                   => None,
+        }
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+        match *choice {
+            CipherChoice::AESGCM     => Some(Box::new(CipherAESGCM::default())),
+            CipherChoice::ChaChaPoly => Some(Box::new(CipherChaChaPoly::default())),
+            _                        => None,
+        }
+    }
+}
+
+/// X25519 via `x25519-dalek`. The private key is kept in a `SecretBuf`, and only turned into a
+/// `StaticSecret` (which zeroizes itself) for the length of each `dh()`.
+#[derive(Default)]
+pub struct Dh25519 {
+    privkey: SecretBuf<[u8; 32]>,
+    pubkey:  [u8; 32],
+}
+
+impl Dh25519 {
+    fn secret(&self) -> StaticSecret {
+        let bytes = Zeroizing::new(*self.privkey);
+        StaticSecret::from(*bytes)
+    }
+
+    fn derive_pubkey(&mut self) {
+        self.pubkey = *PublicKey::from(&self.secret()).as_bytes();
+    }
+}
+
+impl Dh for Dh25519 {
+    fn name(&self) -> &'static str {
+        "25519"
+    }
+
+    fn pub_len(&self) -> usize {
+        32
+    }
+
+    fn priv_len(&self) -> usize {
+        32
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        copy_memory(privkey, &mut self.privkey[..]);
+        self.derive_pubkey();
+    }
+
+    fn generate(&mut self, rng: &mut Random) {
+        rng.fill_bytes(&mut self.privkey[..]);
+        self.privkey[0]  &= 248;
+        self.privkey[31] &= 127;
+        self.privkey[31] |= 64;
+        self.derive_pubkey();
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn privkey(&self) -> &[u8] {
+        &self.privkey[..]
+    }
+
+    fn lock(&mut self) -> Result<(), ()> {
+        self.privkey.lock()
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        let mut peer = [0u8; 32];
+        copy_memory(&pubkey[..32], &mut peer);
+        let shared = self.secret().diffie_hellman(&PublicKey::from(peer));
+        copy_memory(shared.as_bytes(), out);
+        if shared.was_contributory() { Ok(()) } else { Err(()) }
+    }
+}
+
+/// ECDH over secp256k1 via `k256`, as Lightning uses it: public keys are compressed points, and
+/// the shared secret is the SHA-256 of the compressed shared point.
+pub struct DhSecp256k1 {
+    privkey: SecretBuf<[u8; 32]>,
+    pubkey:  [u8; 33],
+}
+
+impl Default for DhSecp256k1 {
+    fn default() -> Self {
+        DhSecp256k1 { privkey: SecretBuf::default(), pubkey: [0u8; 33] }
+    }
+}
+
+impl DhSecp256k1 {
+    /// The private key as a scalar, or `None` if it's zero or not below the group order.
+    fn secret(&self) -> Option<k256::SecretKey> {
+        k256::SecretKey::from_bytes(GenericArray::from_slice(&self.privkey[..])).ok()
+    }
+
+    fn derive_pubkey(&mut self) {
+        self.pubkey = [0u8; 33];
+        if let Some(secret) = self.secret() {
+            copy_memory(secret.public_key().to_encoded_point(true).as_bytes(), &mut self.pubkey);
+        }
+    }
+}
+
+impl Dh for DhSecp256k1 {
+    fn name(&self) -> &'static str {
+        "secp256k1"
+    }
+
+    fn pub_len(&self) -> usize {
+        33
+    }
+
+    fn priv_len(&self) -> usize {
+        32
+    }
+
+    fn dh_len(&self) -> usize {
+        32
+    }
+
+    // An invalid scalar leaves an all-zero public key and `priv_valid()` false; the builder
+    // refuses such keys.
+    fn set(&mut self, privkey: &[u8]) {
+        copy_memory(privkey, &mut self.privkey[..]);
+        self.derive_pubkey();
+    }
+
+    fn generate(&mut self, rng: &mut Random) {
+        loop {
+            rng.fill_bytes(&mut self.privkey[..]);
+            if self.secret().is_some() {
+                break;
+            }
+        }
+        self.derive_pubkey();
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn privkey(&self) -> &[u8] {
+        &self.privkey[..]
+    }
+
+    fn priv_valid(&self) -> bool {
+        self.secret().is_some()
+    }
+
+    fn pub_valid(&self, pubkey: &[u8]) -> bool {
+        pubkey.len() >= 33 && k256::PublicKey::from_sec1_bytes(&pubkey[..33]).is_ok()
+    }
+
+    fn lock(&mut self) -> Result<(), ()> {
+        self.privkey.lock()
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        copy_memory(&[0u8; 32], out);
+        let secret = self.secret().ok_or(())?;
+        let point = k256::PublicKey::from_sec1_bytes(&pubkey[..33]).map_err(|_| ())?;
+        let shared = (point.to_projective() * *secret.to_nonzero_scalar()).to_affine();
+        let mut compressed = Zeroizing::new([0u8; 33]);
+        copy_memory(shared.to_encoded_point(true).as_bytes(), &mut compressed[..]);
+        copy_memory(&Sha256::digest(&compressed[..]), out);
+        Ok(())
+    }
+}
+
+/// ECDH over NIST P-256 via `p256`. Public keys are compressed points, and the shared secret is
+/// the x-coordinate of the shared point, as in SP 800-56A.
+pub struct DhP256 {
+    privkey: SecretBuf<[u8; 32]>,
+    pubkey:  [u8; 33],
+}
+
+impl Default for DhP256 {
+    fn default() -> Self {
+        DhP256 { privkey: SecretBuf::default(), pubkey: [0u8; 33] }
+    }
+}
+
+impl DhP256 {
+    /// The private key as a scalar, or `None` if it's zero or not below the group order.
+    fn secret(&self) -> Option<p256::SecretKey> {
+        p256::SecretKey::from_bytes(GenericArray::from_slice(&self.privkey[..])).ok()
+    }
+
+    fn derive_pubkey(&mut self) {
+        self.pubkey = [0u8; 33];
+        if let Some(secret) = self.secret() {
+            copy_memory(secret.public_key().to_encoded_point(true).as_bytes(), &mut self.pubkey);
+        }
+    }
+}
+
+impl Dh for DhP256 {
+    fn name(&self) -> &'static str {
+        "P256"
+    }
+
+    fn pub_len(&self) -> usize {
+        33
+    }
+
+    fn priv_len(&self) -> usize {
+        32
+    }
+
+    fn dh_len(&self) -> usize {
+        32
+    }
+
+    // As with secp256k1, an invalid scalar leaves an all-zero public key and `priv_valid()` false.
+    fn set(&mut self, privkey: &[u8]) {
+        copy_memory(privkey, &mut self.privkey[..]);
+        self.derive_pubkey();
+    }
+
+    fn generate(&mut self, rng: &mut Random) {
+        loop {
+            rng.fill_bytes(&mut self.privkey[..]);
+            if self.secret().is_some() {
+                break;
+            }
+        }
+        self.derive_pubkey();
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn privkey(&self) -> &[u8] {
+        &self.privkey[..]
+    }
+
+    fn priv_valid(&self) -> bool {
+        self.secret().is_some()
+    }
+
+    fn pub_valid(&self, pubkey: &[u8]) -> bool {
+        pubkey.len() >= 33 && p256::PublicKey::from_sec1_bytes(&pubkey[..33]).is_ok()
+    }
+
+    fn lock(&mut self) -> Result<(), ()> {
+        self.privkey.lock()
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> {
+        copy_memory(&[0u8; 32], out);
+        let secret = self.secret().ok_or(())?;
+        let point = p256::PublicKey::from_sec1_bytes(&pubkey[..33]).map_err(|_| ())?;
+        let shared = p256::ecdh::diffie_hellman(secret.to_nonzero_scalar(), point.as_affine());
+        copy_memory(shared.raw_secret_bytes(), out);
+        Ok(())
+    }
+}
+
+pub struct CipherAESGCM {
+    cipher: Aes256Gcm,
+}
+
+impl Default for CipherAESGCM {
+    fn default() -> Self {
+        CipherAESGCM { cipher: Aes256Gcm::new(&GenericArray::default()) }
+    }
+}
+
+impl Cipher for CipherAESGCM {
+    fn name(&self) -> &'static str {
+        "AESGCM"
+    }
+
+    fn set(&mut self, key: &[u8]) {
+        overwrite_volatile(&mut self.cipher, Aes256Gcm::new(GenericArray::from_slice(&key[..32])));
+    }
+
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        let mut nonce_bytes = [0u8; 12];
+        BigEndian::write_u64(&mut nonce_bytes[4..], nonce);
+
+        out[..plaintext.len()].copy_from_slice(plaintext);
+        let tag = self.cipher.encrypt_in_place_detached(GenericArray::from_slice(&nonce_bytes), authtext,
+                                                        &mut out[..plaintext.len()]).unwrap();
+        out[plaintext.len()..plaintext.len() + TAGLEN].copy_from_slice(&tag);
+        plaintext.len() + TAGLEN
+    }
+
+    fn decrypt(&self, nonce: u64, authtext: &[u8], ciphertext: &[u8], out: &mut [u8]) -> Result<usize, ()> {
+        let mut nonce_bytes = [0u8; 12];
+        BigEndian::write_u64(&mut nonce_bytes[4..], nonce);
+
+        let text_len = ciphertext.len().checked_sub(TAGLEN).ok_or(())?;
+        out[..text_len].copy_from_slice(&ciphertext[..text_len]);
+        self.cipher.decrypt_in_place_detached(GenericArray::from_slice(&nonce_bytes), authtext,
+                                              &mut out[..text_len], GenericArray::from_slice(&ciphertext[text_len..]))
+            .map_err(|_| ())?;
+        Ok(text_len)
+    }
+}
+
+// Like ring's, these key schedules are scrubbed by overwriting them with the all-zero key's.
+impl Drop for CipherAESGCM {
+    fn drop(&mut self) {
+        overwrite_volatile(&mut self.cipher, Aes256Gcm::new(&GenericArray::default()));
+    }
+}
+
+pub struct CipherChaChaPoly {
+    cipher: ChaCha20Poly1305,
+}
+
+impl Default for CipherChaChaPoly {
+    fn default() -> Self {
+        CipherChaChaPoly { cipher: ChaCha20Poly1305::new(&GenericArray::default()) }
+    }
+}
+
+impl Cipher for CipherChaChaPoly {
+    fn name(&self) -> &'static str {
+        "ChaChaPoly"
+    }
+
+    fn set(&mut self, key: &[u8]) {
+        overwrite_volatile(&mut self.cipher, ChaCha20Poly1305::new(GenericArray::from_slice(&key[..32])));
+    }
+
+    fn encrypt(&self, nonce: u64, authtext: &[u8], plaintext: &[u8], out: &mut [u8]) -> usize {
+        let mut nonce_bytes = [0u8; 12];
+        LittleEndian::write_u64(&mut nonce_bytes[4..], nonce);
+
+        out[..plaintext.len()].copy_from_slice(plaintext);
+        let tag = self.cipher.encrypt_in_place_detached(GenericArray::from_slice(&nonce_bytes), authtext,
+                                                        &mut out[..plaintext.len()]).unwrap();
+        out[plaintext.len()..plaintext.len() + TAGLEN].copy_from_slice(&tag);
+        plaintext.len() + TAGLEN
+    }
+
+    fn decrypt(&self, nonce: u64, authtext: &[u8], ciphertext: &[u8], out: &mut [u8]) -> Result<usize, ()> {
+        let mut nonce_bytes = [0u8; 12];
+        LittleEndian::write_u64(&mut nonce_bytes[4..], nonce);
+
+        let text_len = ciphertext.len().checked_sub(TAGLEN).ok_or(())?;
+        out[..text_len].copy_from_slice(&ciphertext[..text_len]);
+        self.cipher.decrypt_in_place_detached(GenericArray::from_slice(&nonce_bytes), authtext,
+                                              &mut out[..text_len], GenericArray::from_slice(&ciphertext[text_len..]))
+            .map_err(|_| ())?;
+        Ok(text_len)
+    }
+}
+
+impl Drop for CipherChaChaPoly {
+    fn drop(&mut self) {
+        overwrite_volatile(&mut self.cipher, ChaCha20Poly1305::new(&GenericArray::default()));
+    }
+}
+
+/// Implements `Hash` for a RustCrypto `Digest`, scrubbing its state on drop by overwriting it with
+/// a fresh one.
+macro_rules! digest_hash {
+    ($name:ident, $digest:ty, $noise_name:expr, $block_len:expr, $hash_len:expr) => {
+        pub struct $name {
+            hasher: $digest,
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                $name { hasher: <$digest>::new() }
+            }
+        }
+
+        impl Hash for $name {
+            fn name(&self) -> &'static str {
+                $noise_name
+            }
+
+            fn block_len(&self) -> usize {
+                $block_len
+            }
+
+            fn hash_len(&self) -> usize {
+                $hash_len
+            }
+
+            fn reset(&mut self) {
+                self.hasher = <$digest>::new();
+            }
+
+            fn input(&mut self, data: &[u8]) {
+                Digest::update(&mut self.hasher, data);
+            }
+
+            fn result(&mut self, out: &mut [u8]) {
+                out[..$hash_len].copy_from_slice(&self.hasher.clone().finalize());
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                overwrite_volatile(&mut self.hasher, <$digest>::new());
+            }
+        }
+    }
+}
+
+digest_hash!(HashSHA256, Sha256, "SHA256", 64, 32);
+digest_hash!(HashSHA512, Sha512, "SHA512", 128, 64);
+digest_hash!(HashBLAKE2s, Blake2s256, "BLAKE2s", 64, 32);
+digest_hash!(HashBLAKE2b, Blake2b512, "BLAKE2b", 128, 64);
+
+#[cfg(test)]
+mod tests {
+    extern crate hex;
+
+    use super::*;
+    use self::hex::{FromHex, ToHex};
+    use wrappers::crypto_wrapper;
+
+    #[test]
+    fn test_hashes() {
+        let mut output = [0u8; 64];
+        let mut hasher = HashSHA256::default();
+        hasher.input(b"abc");
+        hasher.result(&mut output);
+        assert_eq!(output[..32].to_vec().to_hex(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        let mut hasher = HashBLAKE2s::default();
+        hasher.input(b"abc");
+        hasher.result(&mut output);
+        assert_eq!(output[..32].to_vec().to_hex(), "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982");
+
+        for (mut ours, mut theirs) in vec![(Box::new(HashSHA512::default()) as Box<Hash>,
+                                            Box::new(crypto_wrapper::HashSHA512::default()) as Box<Hash>),
+                                           (Box::new(HashBLAKE2b::default()),
+                                            Box::new(crypto_wrapper::HashBLAKE2b::default()))] {
+            let (mut a, mut b) = ([0u8; 64], [0u8; 64]);
+            ours.hmac(b"key", b"data", &mut a);
+            theirs.hmac(b"key", b"data", &mut b);
+            assert_eq!(&a[..], &b[..]);
+        }
+    }
+
+    #[test]
+    fn test_x25519_rfc7748() {
+        // RFC 7748 section 6.1
+        let mut alice = Dh25519::default();
+        alice.set(&Vec::<u8>::from_hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a").unwrap());
+        assert_eq!(alice.pubkey().to_hex(), "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a");
+
+        let mut bob = Dh25519::default();
+        bob.set(&Vec::<u8>::from_hex("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb").unwrap());
+        let mut output = [0u8; 32];
+        alice.dh(bob.pubkey(), &mut output).unwrap();
+        assert_eq!(output.to_hex(), "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+
+        assert!(alice.dh(&[0u8; 32], &mut output).is_err());
+        assert_eq!(output, [0u8; 32]);
+    }
+
+    #[test]
+    fn test_secp256k1() {
+        let mut alice = DhSecp256k1::default();
+        alice.set(&[0x11u8; 32]);
+        assert_eq!(alice.pubkey().to_hex(), "034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa");
+
+        let mut bob = DhSecp256k1::default();
+        bob.generate(&mut *DefaultResolver.resolve_rng().unwrap());
+        let (mut ab, mut ba) = ([0u8; 32], [0u8; 32]);
+        alice.dh(bob.pubkey(), &mut ab).unwrap();
+        bob.dh(alice.pubkey(), &mut ba).unwrap();
+        assert_eq!(ab, ba);
+
+        let mut malformed = alice.pubkey().to_vec();
+        malformed[0] = 0x04;
+        assert!(alice.dh(&malformed, &mut ab).is_err());
+        assert_eq!(ab, [0u8; 32]);
+
+        let mut invalid = DhSecp256k1::default();
+        invalid.set(&[0xffu8; 32]);
+        assert_eq!(&invalid.pubkey()[..], &[0u8; 33][..]);
+        assert!(!invalid.priv_valid() && alice.priv_valid());
+        assert!(invalid.dh(bob.pubkey(), &mut ab).is_err());
+    }
+
+    #[test]
+    fn test_p256() {
+        let (mut alice, mut bob) = (DhP256::default(), DhP256::default());
+        let mut rng = DefaultResolver.resolve_rng().unwrap();
+        alice.generate(&mut *rng);
+        bob.generate(&mut *rng);
+        assert!(alice.pubkey()[0] == 2 || alice.pubkey()[0] == 3);
+        let (mut ab, mut ba) = ([0u8; 32], [0u8; 32]);
+        alice.dh(bob.pubkey(), &mut ab).unwrap();
+        bob.dh(alice.pubkey(), &mut ba).unwrap();
+        assert_eq!(ab, ba);
+
+        let mut malformed = bob.pubkey().to_vec();
+        malformed[0] = 0x01;
+        assert!(alice.dh(&malformed, &mut ab).is_err());
+        assert_eq!(ab, [0u8; 32]);
+    }
+
+    #[test]
+    fn test_resolver_passes_self_test() {
+        let report = ::selftest::self_test(&RustCryptoResolver::new()).unwrap();
+        assert!(report.passed.iter().any(|test| test == "secp256k1 known answers"));
+        assert!(report.passed.iter().any(|test| test == "P256 known answers"));
+    }
+
+    #[test]
+    fn test_ciphers_match_default() {
+        let key = [0x42u8; 32];
+        let pairs: Vec<(Box<Cipher>, Box<Cipher>)> = vec![
+            (Box::new(CipherAESGCM::default()), Box::new(crypto_wrapper::CipherAESGCM::default())),
+            (Box::new(CipherChaChaPoly::default()), Box::new(crypto_wrapper::CipherChaChaPoly::default())),
+        ];
+        for (mut ours, mut theirs) in pairs {
+            ours.set(&key);
+            theirs.set(&key);
+            let (mut a, mut b) = ([0u8; 64], [0u8; 64]);
+            let len = ours.encrypt(7, b"ad", b"a secret message", &mut a);
+            assert_eq!(len, theirs.encrypt(7, b"ad", b"a secret message", &mut b));
+            assert_eq!(&a[..len], &b[..len]);
+
+            let mut plain = [0u8; 64];
+            assert_eq!(ours.decrypt(7, b"ad", &a[..len], &mut plain), Ok(16));
+            assert_eq!(&plain[..16], b"a secret message");
+            a[0] ^= 1;
+            assert!(ours.decrypt(7, b"ad", &a[..len], &mut plain).is_err());
+            assert!(ours.decrypt(7, b"ad", &a[..TAGLEN - 1], &mut plain).is_err());
+        }
+    }
+}
@@ -4,8 +4,12 @@ extern crate hex;
 extern crate snow;
 
 use hex::{FromHex, ToHex};
-use snow::{NoiseBuilder, CryptoResolver, DefaultResolver};
+use snow::{NoiseBuilder, CryptoResolver, DefaultResolver, PaddingPolicy, NegotiatingBuilder, ProtocolHint};
 use snow::params::*;
+use snow::envelope;
+use snow::sealed;
+use snow::stream::{self, Encryptor, Decryptor};
+use std::io::{Read, Write};
 use snow::types::*;
 use snow::wrappers::crypto_wrapper::Dh25519;
 
@@ -95,10 +99,8 @@ fn test_protocol_name() {
     assert_eq!(protocol_spec.cipher, CipherChoice::ChaChaPoly);
     assert_eq!(protocol_spec.hash, HashChoice::Blake2s);
 
-    let protocol_spec: Result<NoiseParams, _> = "Noise_NK_25519_ChaChaPoly_BLAKE2X".parse();
-    if protocol_spec.is_ok() {
-        panic!("invalid protocol was parsed inaccurately");
-    }
+    let protocol_spec: NoiseParams = "Noise_NK_25519_ChaChaPoly_BLAKE2X".parse().unwrap();
+    assert_eq!(protocol_spec.hash, HashChoice::Custom("BLAKE2X".to_owned()));
 
     let protocol_spec: Result<NoiseParams, _> = "Noise_NK_25519_ChaChaPoly".parse();
     if protocol_spec.is_ok() {
@@ -106,6 +108,151 @@ fn test_protocol_name() {
     }
 }
 
+/// Resolves the non-standard cipher name `ChaChaPoly12` (to plain ChaChaPoly, for the test's sake).
+struct CustomCipherResolver;
+
+impl CryptoResolver for CustomCipherResolver {
+    fn resolve_rng(&self) -> Option<Box<Random>> {
+        DefaultResolver.resolve_rng()
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<Dh>> {
+        DefaultResolver.resolve_dh(choice)
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<Hash>> {
+        DefaultResolver.resolve_hash(choice)
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<Cipher>> {
+        match *choice {
+            CipherChoice::Custom(ref name) if name == "ChaChaPoly12" => DefaultResolver.resolve_cipher(&CipherChoice::ChaChaPoly),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_custom_primitive_names() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly12_BLAKE2s".parse().unwrap();
+    match NoiseBuilder::new(params.clone()).build_initiator() {
+        Err(snow::Error(snow::ErrorKind::Init(snow::InitStage::GetCipherImpl), _)) => {},
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+
+    let mut h_i = NoiseBuilder::with_resolver(params.clone(), Box::new(CustomCipherResolver)).build_initiator().unwrap();
+    let mut h_r = NoiseBuilder::with_resolver(params, Box::new(CustomCipherResolver)).build_responder().unwrap();
+    let (mut buffer_msg, mut buffer_out) = ([0u8; 200], [0u8; 200]);
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(b"custom", &mut buffer_msg).unwrap();
+    let len = h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"custom");
+}
+
+#[test]
+fn test_fips_resolver_refuses_at_build() {
+    use snow::wrappers::crypto_wrapper::{CipherChaChaPoly, HashBLAKE2s};
+    use snow::wrappers::rand_wrapper::RandomOs;
+
+    let params: NoiseParams = "Noise_NN_P256_ChaChaPoly_SHA256".parse().unwrap();
+    match NoiseBuilder::with_resolver(params, Box::new(snow::FipsResolver::new(DefaultResolver))).build_responder() {
+        Err(snow::Error(snow::ErrorKind::Init(snow::InitStage::Disallowed(ref name)), _)) if name == "ChaChaPoly" => {},
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    match NoiseBuilder::with_resolver(params, Box::new(snow::FipsResolver::new(DefaultResolver)))
+        .build_static_initiator::<Dh25519, CipherChaChaPoly, HashBLAKE2s, _>(RandomOs::default()) {
+        Err(snow::Error(snow::ErrorKind::Init(snow::InitStage::Disallowed(ref name)), _)) if name == "25519" => {},
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+}
+
+#[cfg(feature = "rustcrypto-resolver")]
+#[test]
+fn test_fips_p256_session() {
+    use snow::{FipsResolver, RustCryptoResolver};
+
+    let params: NoiseParams = "Noise_XX_P256_AESGCM_SHA512".parse().unwrap();
+    let resolver = FipsResolver::new(RustCryptoResolver::new());
+    let static_i = NoiseBuilder::with_resolver(params.clone(), Box::new(&resolver)).generate_private_key().unwrap();
+    let static_r = NoiseBuilder::with_resolver(params.clone(), Box::new(&resolver)).generate_private_key().unwrap();
+    let mut h_i = NoiseBuilder::with_resolver(params.clone(), Box::new(&resolver))
+        .local_private_key(&static_i)
+        .build_initiator().unwrap();
+    let mut h_r = NoiseBuilder::with_resolver(params, Box::new(&resolver))
+        .local_private_key(&static_r)
+        .build_responder().unwrap();
+
+    let (mut buffer_msg, mut buffer_out) = ([0u8; 200], [0u8; 200]);
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    assert_eq!(len, 33);
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+    let len = h_i.write_message(b"approved", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"approved");
+    assert_eq!(h_r.get_remote_static().unwrap().len(), 33);
+}
+
+#[cfg(feature = "rustcrypto-resolver")]
+#[test]
+fn test_invalid_curve_points_refused_with_low_order_allowed() {
+    use snow::RustCryptoResolver;
+
+    for name in &["Noise_NN_secp256k1_ChaChaPoly_SHA256", "Noise_NN_P256_AESGCM_SHA256"] {
+        let params: NoiseParams = name.parse().unwrap();
+        let mut h_i = NoiseBuilder::with_resolver(params.clone(), Box::new(RustCryptoResolver::new()))
+            .allow_low_order_points()
+            .build_initiator().unwrap();
+        let mut h_r = NoiseBuilder::with_resolver(params, Box::new(RustCryptoResolver::new()))
+            .allow_low_order_points()
+            .build_responder().unwrap();
+
+        let (mut buffer_msg, mut buffer_out) = ([0u8; 200], [0u8; 200]);
+        let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+        assert_eq!(len, 33);
+        for (i, byte) in buffer_msg[..33].iter_mut().enumerate() {
+            *byte = 0x07 + i as u8;
+        }
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        match h_r.write_message(&[], &mut buffer_msg) {
+            Err(snow::Error(snow::ErrorKind::Dh(_), _)) => {},
+            other => panic!("unexpected result {:?} for {}", other, name),
+        }
+    }
+}
+
+#[cfg(feature = "rustcrypto-resolver")]
+#[test]
+fn test_invalid_curve_scalars_refused() {
+    use snow::RustCryptoResolver;
+
+    for name in &["Noise_XX_secp256k1_ChaChaPoly_SHA256", "Noise_XX_P256_AESGCM_SHA256"] {
+        let params: NoiseParams = name.parse().unwrap();
+        match NoiseBuilder::with_resolver(params.clone(), Box::new(RustCryptoResolver::new()))
+            .local_private_key(&[0xffu8; 32])
+            .build_initiator() {
+            Err(snow::Error(snow::ErrorKind::Init(snow::InitStage::ValidateField(snow::BuilderField::LocalPrivateKey)), _)) => {},
+            other => panic!("unexpected result {:?} for {}", other.map(|_| ()), name),
+        }
+        let params: NoiseParams = name.replace("_XX_", "_NN_").parse().unwrap();
+        match NoiseBuilder::with_resolver(params, Box::new(RustCryptoResolver::new()))
+            .fixed_ephemeral_key_for_testing_only(&[0u8; 32])
+            .build_initiator() {
+            Err(snow::Error(snow::ErrorKind::Init(snow::InitStage::ValidateField(snow::BuilderField::FixedEphemeralKey)), _)) => {},
+            other => panic!("unexpected result {:?} for {}", other.map(|_| ()), name),
+        }
+    }
+}
+
 #[test]
 fn test_noise_session_transition_change() {
     let params: NoiseParams = "Noise_NN_25519_AESGCM_SHA256".parse().unwrap();
@@ -368,7 +515,7 @@ fn test_handshake_message_undersized_output_buffer() {
 #[test]
 fn test_transport_message_exceeds_max_len() {
     let params: NoiseParams = "Noise_N_25519_AESGCM_SHA256".parse().unwrap();
-    let mut noise = NoiseBuilder::new(params).remote_public_key(&[0u8; 32]).build_initiator().unwrap();
+    let mut noise = NoiseBuilder::new(params).remote_public_key(&[1u8; 32]).build_initiator().unwrap();
 
     let mut buffer_out = [0u8; 65535*2];
     noise.write_message(&[0u8; 0], &mut buffer_out).unwrap();
@@ -379,7 +526,7 @@ fn test_transport_message_exceeds_max_len() {
 #[test]
 fn test_transport_message_undersized_output_buffer() {
     let params: NoiseParams = "Noise_N_25519_AESGCM_SHA256".parse().unwrap();
-    let mut noise = NoiseBuilder::new(params).remote_public_key(&[0u8; 32]).build_initiator().unwrap();
+    let mut noise = NoiseBuilder::new(params).remote_public_key(&[1u8; 32]).build_initiator().unwrap();
 
     let mut buffer_out = [0u8; 200];
     noise.write_message(&[0u8; 0], &mut buffer_out).unwrap();
@@ -387,10 +534,154 @@ fn test_transport_message_undersized_output_buffer() {
     assert!(noise.write_message(&[0u8; 300], &mut buffer_out).is_err());
 }
 
+#[test]
+fn test_decrypt_errors_name_message_part() {
+    let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let builder = NoiseBuilder::new(params.clone());
+    let (init_s, resp_s) = (builder.generate_private_key().unwrap(), builder.generate_private_key().unwrap());
+    let mut h_i = NoiseBuilder::new(params.clone()).local_private_key(&init_s).build_initiator().unwrap();
+    let mut h_r = NoiseBuilder::new(params).local_private_key(&resp_s).build_responder().unwrap();
+
+    let (mut buffer_msg, mut buffer_out) = ([0u8; 200], [0u8; 200]);
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    buffer_msg[40] ^= 1;
+    match h_i.read_message(&buffer_msg[..len], &mut buffer_out) {
+        Err(snow::Error(snow::ErrorKind::Decrypt(snow::MessagePart::Token { message: 1, token: Token::S }), _)) => {},
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+struct HeldKey(Dh25519);
+
+impl StaticKeyProvider for HeldKey {
+    fn name(&self) -> &'static str { "25519" }
+    fn pub_len(&self) -> usize { 32 }
+    fn pubkey(&self) -> &[u8] { self.0.pubkey() }
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), ()> { let _ = self.0.dh(pubkey, out); Ok(()) }
+}
+
+/// A provider whose agent has gone away.
+struct UnreachableKey([u8; 32]);
+
+impl StaticKeyProvider for UnreachableKey {
+    fn name(&self) -> &'static str { "25519" }
+    fn pub_len(&self) -> usize { 32 }
+    fn pubkey(&self) -> &[u8] { &self.0 }
+    fn dh(&self, _pubkey: &[u8], _out: &mut [u8]) -> Result<(), ()> { Err(()) }
+}
+
+#[test]
+fn test_static_key_provider_session() {
+    let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let builder = NoiseBuilder::new(params.clone());
+    let (init_s, resp_s) = (builder.generate_private_key().unwrap(), builder.generate_private_key().unwrap());
+    let mut held = Dh25519::default();
+    held.set(&init_s);
+    let init_pub = held.pubkey().to_vec();
+
+    let mut h_i = NoiseBuilder::new(params.clone()).static_key_provider(Box::new(HeldKey(held))).build_initiator().unwrap();
+    let mut h_r = NoiseBuilder::new(params.clone()).local_private_key(&resp_s).build_responder().unwrap();
+
+    let (mut buffer_msg, mut buffer_out) = ([0u8; 200], [0u8; 200]);
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(h_r.get_remote_static(), Some(&init_pub[..]));
+
+    let mut held = Dh25519::default();
+    held.set(&init_s);
+    let both = NoiseBuilder::new(params).local_private_key(&init_s).static_key_provider(Box::new(HeldKey(held)));
+    match both.build_initiator() {
+        Err(snow::Error(snow::ErrorKind::Init(snow::InitStage::ValidateField(snow::BuilderField::StaticKeyProvider)), _)) => {},
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_static_key_provider_failure_not_allowed_through() {
+    let params: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let resp_s = NoiseBuilder::new(params.clone()).generate_private_key().unwrap();
+    let mut h_i = NoiseBuilder::new(params.clone())
+        .static_key_provider(Box::new(UnreachableKey([9u8; 32])))
+        .allow_low_order_points()
+        .build_initiator().unwrap();
+    let mut h_r = NoiseBuilder::new(params).local_private_key(&resp_s).build_responder().unwrap();
+
+    let (mut buffer_msg, mut buffer_out) = ([0u8; 200], [0u8; 200]);
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    match h_i.write_message(&[], &mut buffer_msg) {
+        Err(snow::Error(snow::ErrorKind::KeyProvider(snow::MessagePart::Token { message: 2, token: Token::Dhse }), _)) => {},
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn test_low_order_ephemeral_rejected() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    for &allow in &[false, true] {
+        let mut builder = NoiseBuilder::new(params.clone());
+        if allow {
+            builder = builder.allow_low_order_points();
+        }
+        let mut h_i = builder.build_initiator().unwrap();
+        let mut h_r = NoiseBuilder::new(params.clone()).build_responder().unwrap();
+
+        let (mut buffer_msg, mut buffer_out) = ([0u8; 200], [0u8; 200]);
+        let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+        h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+        let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+        for byte in &mut buffer_msg[..32] {
+            *byte = 0;
+        }
+        match (allow, h_i.read_message(&buffer_msg[..len], &mut buffer_out)) {
+            (false, Err(snow::Error(snow::ErrorKind::Dh(snow::MessagePart::Token { message: 1, token: Token::Dhee }), _))) => {},
+            (true, Err(snow::Error(snow::ErrorKind::Decrypt(snow::MessagePart::Payload { message: 1 }), _))) => {},
+            (_, other) => panic!("unexpected result {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_transport_errors_name_nonce() {
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let resp_s = NoiseBuilder::new(params.clone()).generate_private_key().unwrap();
+    let mut dh = Dh25519::default();
+    dh.set(&resp_s);
+    let mut h_i = NoiseBuilder::new(params.clone()).remote_public_key(dh.pubkey()).build_initiator().unwrap();
+    let mut h_r = NoiseBuilder::new(params).local_private_key(&resp_s).build_responder().unwrap();
+
+    let (mut buffer_msg, mut buffer_out) = ([0u8; 200], [0u8; 200]);
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    buffer_msg[0] ^= 1;
+    match h_r.read_message(&buffer_msg[..len], &mut buffer_out) {
+        Err(snow::Error(snow::ErrorKind::Decrypt(snow::MessagePart::Transport { nonce: 1 }), _)) => {},
+        other => panic!("unexpected result {:?}", other),
+    }
+    match h_r.read_message(&buffer_msg[..4], &mut buffer_out) {
+        Err(snow::Error(snow::ErrorKind::Input(snow::InputProblem::Truncated(_)), _)) => {},
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
 #[test]
 fn test_oneway_initiator_enforcements() {
     let params: NoiseParams = "Noise_N_25519_AESGCM_SHA256".parse().unwrap();
-    let mut noise = NoiseBuilder::new(params).remote_public_key(&[0u8; 32]).build_initiator().unwrap();
+    let mut noise = NoiseBuilder::new(params).remote_public_key(&[1u8; 32]).build_initiator().unwrap();
 
     let mut buffer_out = [0u8; 1024];
     noise.write_message(&[0u8; 0], &mut buffer_out).unwrap();
@@ -449,3 +740,393 @@ fn test_set_nonce() {
     resp.read_message(&buffer_init[..len], &mut buffer_resp).unwrap();
 
 }
+
+#[test]
+fn test_padded_transport_hides_length() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let mut h_i = NoiseBuilder::new(params.clone())
+        .padding(PaddingPolicy::Buckets(vec![64, 256]))
+        .build_initiator().unwrap();
+    let mut h_r = NoiseBuilder::new(params)
+        .padding(PaddingPolicy::Buckets(vec![64, 256]))
+        .build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 1024];
+    let mut buffer_out = [0u8; 1024];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+
+    let short_len = h_i.write_message(b"hi", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..short_len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hi");
+
+    let long_len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..long_len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+
+    assert_eq!(short_len, 64 + 16);
+    assert_eq!(short_len, long_len);
+
+    let len = h_r.write_message(&[7u8; 100], &mut buffer_msg).unwrap();
+    assert_eq!(len, 256 + 16);
+    let len = h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], &[7u8; 100][..]);
+}
+
+#[test]
+fn test_padding_policy_mismatch_fails_handshake() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let mut h_i = NoiseBuilder::new(params.clone())
+        .padding(PaddingPolicy::Multiple(128))
+        .build_initiator().unwrap();
+    let mut h_r = NoiseBuilder::new(params).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 1024];
+    let mut buffer_out = [0u8; 1024];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    assert!(h_i.read_message(&buffer_msg[..len], &mut buffer_out).is_err());
+}
+
+#[test]
+fn test_invalid_padding_policy() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    assert!(NoiseBuilder::new(params).padding(PaddingPolicy::Multiple(0)).build_initiator().is_err());
+}
+
+#[test]
+fn test_negotiated_session() {
+    let chacha: NoiseParams = "Noise_XX_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let aes: NoiseParams = "Noise_XX_25519_AESGCM_SHA256".parse().unwrap();
+    let static_i = NoiseBuilder::new(aes.clone()).generate_private_key().unwrap();
+    let static_r = NoiseBuilder::new(aes.clone()).generate_private_key().unwrap();
+
+    let hint = ProtocolHint::new(&aes);
+    let mut h_i = NoiseBuilder::new(aes)
+        .local_private_key(&static_i)
+        .protocol_hint(hint)
+        .build_initiator().unwrap();
+    let received = ProtocolHint::from_bytes(hint.as_bytes()).unwrap();
+    let mut h_r = NegotiatingBuilder::new(vec![chacha, "Noise_XX_25519_AESGCM_SHA256".parse().unwrap()])
+        .local_private_key(&static_r)
+        .build_responder(&received).unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    h_i.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+
+    let mut h_i = h_i.into_transport_mode().unwrap();
+    let mut h_r = h_r.into_transport_mode().unwrap();
+
+    let len = h_i.write_message(b"hack the planet", &mut buffer_msg).unwrap();
+    let len = h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    assert_eq!(&buffer_out[..len], b"hack the planet");
+}
+
+#[test]
+fn test_negotiation_rejects_unaccepted_and_mismatched_hints() {
+    let chacha: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let aes: NoiseParams = "Noise_NN_25519_AESGCM_SHA256".parse().unwrap();
+
+    assert!(NegotiatingBuilder::new(vec![chacha.clone()]).build_responder(&ProtocolHint::new(&aes)).is_err());
+    assert!(NoiseBuilder::new(chacha.clone()).protocol_hint(ProtocolHint::new(&aes)).build_initiator().is_err());
+
+    // A responder that didn't see the hint the initiator used fails the handshake.
+    let mut h_i = NoiseBuilder::new(chacha.clone())
+        .protocol_hint(ProtocolHint::new(&chacha))
+        .build_initiator().unwrap();
+    let mut h_r = NoiseBuilder::new(chacha).build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 200];
+    let mut buffer_out = [0u8; 200];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    assert!(h_i.read_message(&buffer_msg[..len], &mut buffer_out).is_err());
+}
+
+#[test]
+fn test_sealed_box_patterns() {
+    let mut static_i: Dh25519 = Default::default();
+    let mut static_r: Dh25519 = Default::default();
+    static_i.set(&get_inc_key(0));
+    static_r.set(&get_inc_key(32));
+
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let boxed = sealed::seal(&params, static_r.pubkey(), None, b"abc").unwrap();
+    let (payload, sender) = sealed::open(&params, static_r.privkey(), &boxed).unwrap();
+    assert_eq!(payload, b"abc");
+    assert!(sender.is_none());
+
+    let params: NoiseParams = "Noise_X_25519_AESGCM_SHA256".parse().unwrap();
+    assert!(sealed::seal(&params, static_r.pubkey(), None, b"abc").is_err());
+    let boxed = sealed::seal(&params, static_r.pubkey(), Some(static_i.privkey()), b"abc").unwrap();
+    let (payload, sender) = sealed::open(&params, static_r.privkey(), &boxed).unwrap();
+    assert_eq!(payload, b"abc");
+    assert_eq!(sender.unwrap(), static_i.pubkey());
+    assert!(sealed::open_from(&params, static_r.privkey(), static_r.pubkey(), &boxed).is_err());
+
+    let params: NoiseParams = "Noise_K_25519_ChaChaPoly_SHA512".parse().unwrap();
+    let boxed = sealed::seal(&params, static_r.pubkey(), Some(static_i.privkey()), b"abc").unwrap();
+    assert!(sealed::open(&params, static_r.privkey(), &boxed).is_err());
+    let (payload, _) = sealed::open_from(&params, static_r.privkey(), static_i.pubkey(), &boxed).unwrap();
+    assert_eq!(payload, b"abc");
+
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    assert!(sealed::seal(&params, static_r.pubkey(), None, b"abc").is_err());
+}
+
+#[test]
+fn test_sealed_box_continuation() {
+    let mut static_r: Dh25519 = Default::default();
+    static_r.set(&get_inc_key(32));
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+
+    let payload: Vec<u8> = (0..200000).map(|i| i as u8).collect();
+    let boxed = sealed::seal(&params, static_r.pubkey(), None, &payload).unwrap();
+    let (opened, _) = sealed::open(&params, static_r.privkey(), &boxed).unwrap();
+    assert_eq!(opened, payload);
+
+    // Dropping the last message, or appending a stray one, must be detected.
+    let mut last_start = 0;
+    let mut offset = 0;
+    while offset < boxed.len() {
+        last_start = offset;
+        offset += 2 + ((boxed[offset] as usize) << 8 | boxed[offset + 1] as usize);
+    }
+    assert!(last_start > 0);
+    assert!(sealed::open(&params, static_r.privkey(), &boxed[..last_start]).is_err());
+    let mut extended = boxed.clone();
+    extended.extend_from_slice(&[0, 16]);
+    extended.extend_from_slice(&[0u8; 16]);
+    assert!(sealed::open(&params, static_r.privkey(), &extended).is_err());
+    assert!(sealed::open(&params, static_r.privkey(), &boxed[..10]).is_err());
+}
+
+fn encrypt_stream(params: &NoiseParams, recipient: &[u8], sender: Option<&[u8]>, plaintext: &[u8]) -> Vec<u8> {
+    let mut encryptor = Encryptor::new(params, recipient, sender, Vec::new()).unwrap();
+    for piece in plaintext.chunks(10007) {
+        encryptor.write_all(piece).unwrap();
+    }
+    encryptor.finish().unwrap()
+}
+
+#[test]
+fn test_stream_roundtrip() {
+    let mut static_i: Dh25519 = Default::default();
+    let mut static_r: Dh25519 = Default::default();
+    static_i.set(&get_inc_key(0));
+    static_r.set(&get_inc_key(32));
+    let params: NoiseParams = "Noise_X_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+
+    for size in &[0, 1, stream::CHUNK_LEN, stream::CHUNK_LEN + 1, 3 * stream::CHUNK_LEN + 17] {
+        let plaintext: Vec<u8> = (0..*size).map(|i| i as u8).collect();
+        let encrypted = encrypt_stream(&params, static_r.pubkey(), Some(static_i.privkey()), &plaintext);
+
+        let mut decryptor = Decryptor::new(&params, static_r.privkey(), &encrypted[..]).unwrap();
+        assert_eq!(decryptor.sender_public().unwrap(), static_i.pubkey());
+        let mut decrypted = Vec::new();
+        decryptor.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+}
+
+#[test]
+fn test_stream_tampering() {
+    let mut static_r: Dh25519 = Default::default();
+    static_r.set(&get_inc_key(32));
+    let params: NoiseParams = "Noise_N_25519_AESGCM_SHA256".parse().unwrap();
+    let plaintext = vec![7u8; 2 * stream::CHUNK_LEN + 5];
+    let encrypted = encrypt_stream(&params, static_r.pubkey(), None, &plaintext);
+    let header_len = encrypted.len() - (3 * (1 + 16) + plaintext.len());
+    let chunk_len = stream::CHUNK_LEN + 1 + 16;
+
+    let decrypt = |data: &[u8]| -> Result<Vec<u8>, ()> {
+        let mut decryptor = Decryptor::new(&params, static_r.privkey(), data).map_err(|_| ())?;
+        let mut decrypted = Vec::new();
+        decryptor.read_to_end(&mut decrypted).map_err(|_| ())?;
+        Ok(decrypted)
+    };
+    assert_eq!(decrypt(&encrypted).unwrap(), plaintext);
+
+    // Truncated at a chunk boundary.
+    assert!(decrypt(&encrypted[..header_len + 2 * chunk_len]).is_err());
+
+    // Trailing data.
+    let mut appended = encrypted.clone();
+    appended.push(0);
+    assert!(decrypt(&appended).is_err());
+
+    // Reordered chunks.
+    let mut reordered = encrypted[..header_len].to_vec();
+    reordered.extend_from_slice(&encrypted[header_len + chunk_len..header_len + 2 * chunk_len]);
+    reordered.extend_from_slice(&encrypted[header_len..header_len + chunk_len]);
+    reordered.extend_from_slice(&encrypted[header_len + 2 * chunk_len..]);
+    assert!(decrypt(&reordered).is_err());
+
+    // Bad magic.
+    let mut bad_magic = encrypted.clone();
+    bad_magic[0] ^= 1;
+    assert!(decrypt(&bad_magic).is_err());
+}
+
+/// Hands out at most `step` bytes per read, failing with `WouldBlock` before every other one.
+struct Trickle<'a> {
+    data: &'a [u8],
+    step: usize,
+    block: bool,
+}
+
+impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        self.block = !self.block;
+        if self.block {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        let len = std::cmp::min(std::cmp::min(out.len(), self.step), self.data.len());
+        out[..len].copy_from_slice(&self.data[..len]);
+        self.data = &self.data[len..];
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_stream_errors_are_sticky() {
+    let mut static_r: Dh25519 = Default::default();
+    static_r.set(&get_inc_key(32));
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let plaintext: Vec<u8> = (0..stream::CHUNK_LEN + 100).map(|i| i as u8).collect();
+    let encrypted = encrypt_stream(&params, static_r.pubkey(), None, &plaintext);
+
+    // A failed read doesn't hand out unauthenticated bytes afterwards.
+    let mut tampered = encrypted.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    let mut decryptor = Decryptor::new(&params, static_r.privkey(), &tampered[..]).unwrap();
+    let mut out = vec![0u8; stream::CHUNK_LEN];
+    decryptor.read_exact(&mut out).unwrap();
+    for _ in 0..2 {
+        let err = decryptor.read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    // Errors from the underlying reader keep the partial chunk, and can be retried.
+    let header_len = encrypted.len() - (2 * (1 + 16) + plaintext.len());
+    let reader = (&encrypted[..header_len]).chain(Trickle { data: &encrypted[header_len..], step: 4096, block: false });
+    let mut decryptor = Decryptor::new(&params, static_r.privkey(), reader).unwrap();
+    let mut decrypted = Vec::new();
+    loop {
+        match decryptor.read(&mut out) {
+            Ok(0) => break,
+            Ok(n) => decrypted.extend_from_slice(&out[..n]),
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {},
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+    assert_eq!(decrypted, plaintext);
+}
+
+#[cfg(feature = "rustcrypto-resolver")]
+#[test]
+fn test_envelope_with_resolver() {
+    use snow::RustCryptoResolver;
+
+    let params: NoiseParams = "Noise_X_secp256k1_ChaChaPoly_SHA256".parse().unwrap();
+    let resolver = RustCryptoResolver::new();
+    let keys: Vec<Vec<u8>> = (0..3)
+        .map(|_| NoiseBuilder::with_resolver(params.clone(), Box::new(&resolver)).generate_private_key().unwrap())
+        .collect();
+    let publics: Vec<Vec<u8>> = keys.iter().map(|k| {
+        let mut dh = resolver.resolve_dh(&params.dh).unwrap();
+        dh.set(k);
+        dh.pubkey().to_vec()
+    }).collect();
+
+    let sealed = envelope::seal_with_resolver(&resolver, &params, &[&publics[1], &publics[2]], Some(&keys[0]), b"abc").unwrap();
+    let (opened, sender) = envelope::open_with_resolver(&resolver, &params, &keys[2], &sealed).unwrap();
+    assert_eq!(opened, b"abc");
+    assert_eq!(sender.unwrap(), publics[0]);
+}
+
+#[test]
+fn test_envelope_multiple_recipients() {
+    let mut sender: Dh25519 = Default::default();
+    let mut alice: Dh25519 = Default::default();
+    let mut bob: Dh25519 = Default::default();
+    let mut eve: Dh25519 = Default::default();
+    sender.set(&get_inc_key(0));
+    alice.set(&get_inc_key(32));
+    bob.set(&get_inc_key(64));
+    eve.set(&get_inc_key(96));
+
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let payload: Vec<u8> = (0..150000).map(|i| i as u8).collect();
+    let sealed = envelope::seal(&params, &[alice.pubkey(), bob.pubkey()], None, &payload).unwrap();
+    for recipient in &[&alice, &bob] {
+        let (opened, sender) = envelope::open(&params, recipient.privkey(), &sealed).unwrap();
+        assert_eq!(opened, payload);
+        assert!(sender.is_none());
+    }
+    assert!(envelope::open(&params, eve.privkey(), &sealed).is_err());
+    assert!(envelope::open(&params, alice.privkey(), &sealed[..sealed.len() - 1]).is_err());
+    assert!(envelope::open(&params, alice.privkey(), &sealed[..sealed.len() - 65535]).is_err());
+
+    let params: NoiseParams = "Noise_X_25519_AESGCM_SHA256".parse().unwrap();
+    assert!(envelope::seal(&params, &[alice.pubkey()], None, b"abc").is_err());
+    let sealed = envelope::seal(&params, &[alice.pubkey(), bob.pubkey()], Some(sender.privkey()), b"").unwrap();
+    let (opened, from) = envelope::open(&params, bob.privkey(), &sealed).unwrap();
+    assert!(opened.is_empty());
+    assert_eq!(from.unwrap(), sender.pubkey());
+
+    let params: NoiseParams = "Noise_K_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    assert!(envelope::seal(&params, &[alice.pubkey()], Some(sender.privkey()), b"abc").is_err());
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    assert!(envelope::seal(&params, &[], None, b"abc").is_err());
+}
+
+#[test]
+fn test_envelope_untagged_stanza() {
+    let mut alice: Dh25519 = Default::default();
+    alice.set(&get_inc_key(32));
+    let params: NoiseParams = "Noise_N_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+
+    // A stanza whose tag doesn't match is still tried with the local key.
+    let mut sealed = envelope::seal(&params, &[alice.pubkey()], None, b"abc").unwrap();
+    let tag_start = envelope::MAGIC.len() + 1 + 2;
+    for byte in &mut sealed[tag_start..tag_start + envelope::TAG_LEN] {
+        *byte = 0;
+    }
+    let (opened, _) = envelope::open(&params, alice.privkey(), &sealed).unwrap();
+    assert_eq!(opened, b"abc");
+}
+
+#[test]
+fn test_padding_tag_is_not_a_plain_prologue() {
+    let params: NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+    let policy = PaddingPolicy::Multiple(128);
+    let tag = policy.prologue_tag();
+    let mut h_i = NoiseBuilder::new(params.clone())
+        .padding(policy)
+        .build_initiator().unwrap();
+    let mut h_r = NoiseBuilder::new(params)
+        .prologue(&tag)
+        .build_responder().unwrap();
+
+    let mut buffer_msg = [0u8; 1024];
+    let mut buffer_out = [0u8; 1024];
+    let len = h_i.write_message(&[], &mut buffer_msg).unwrap();
+    h_r.read_message(&buffer_msg[..len], &mut buffer_out).unwrap();
+    let len = h_r.write_message(&[], &mut buffer_msg).unwrap();
+    assert!(h_i.read_message(&buffer_msg[..len], &mut buffer_out).is_err());
+}